@@ -1,7 +1,23 @@
 #![allow(unused)]
+#![allow(clippy::upper_case_acronyms)]
 
 use clap::Parser;
-use network_test::read_packet;
+use network_test::capture::{Backend, CaptureLimits, ChannelOptions, Fanout, FanoutMode};
+use network_test::discover::DiscoverOptions;
+use network_test::export::ExportFormat;
+use network_test::flow::{FlowSort, FlowTimeouts};
+use network_test::interfaces::{InterfaceFilter, InterfaceSelection};
+use network_test::probe::{PingOptions, ProbeProtocol, TracerouteOptions};
+use network_test::rotation::RotationOptions;
+use network_test::store::StoreLimits;
+use network_test::tpacket::RingOptions;
+use network_test::{
+    file_inventory, file_stats, live_inventory, live_stats, read_packet, track_flows, FlowOptions,
+    HexDump, InventoryOptions, OutputFormat, ReadOptions, StatsOptions,
+};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
 
 mod network_test;
 
@@ -12,25 +28,355 @@ mod network_test;
 #[command(about = "NetworkTest And Rust Tutorial")]
 struct CommandLine {
     #[clap(subcommand)]
-    command: Option<Command>
+    command: Option<Command>,
 }
 
 #[derive(Parser)]
 enum Command {
     /// find information about network interfaces.
-    Ls,
+    Ls(LsArgs),
     /// capture packets on network interfaces specified by the name option.
-    Read(ReadArgs)
+    Read(ReadArgs),
+    /// browse live captures in an interactive terminal UI, one tab per interface.
+    Tui(TuiArgs),
+    /// track connections and print per-flow statistics as a table.
+    Flows(FlowsArgs),
+    /// receive NetFlow/IPFIX/sFlow exports over UDP and print their flow records.
+    Collect(CollectArgs),
+    /// report protocol hierarchy, top talkers, packet sizes and rates, live or from a pcap file.
+    Stats(StatsArgs),
+    /// send ICMP echo, TCP SYN or UDP probes to a host and report RTT and loss.
+    Ping(PingArgs),
+    /// list the hops to a host, probing with a growing TTL.
+    Traceroute(TracerouteArgs),
+    /// find the hosts on an interface's segment with an ARP sweep and IPv6 neighbour discovery.
+    Discover(DiscoverArgs),
+    /// list the hosts seen in ARP, DHCP, name service, LLDP and TCP traffic, live or from a pcap file.
+    Inventory(InventoryArgs),
+}
+
+#[derive(Parser)]
+struct LsArgs {
+    /// print a JSON array instead of text
+    #[arg(long)]
+    json: bool,
+    /// only interfaces that are up
+    #[arg(long)]
+    up: bool,
+    /// only loopback interfaces
+    #[arg(long, conflicts_with = "no_loopback")]
+    loopback: bool,
+    /// leave out loopback interfaces
+    #[arg(long)]
+    no_loopback: bool,
+    /// only interfaces with an IPv4 address
+    #[arg(long)]
+    ipv4: bool,
 }
 
 #[derive(Parser)]
 struct ReadArgs {
-    /// enter the network interface name to be used for capturing packets.
+    /// interface to capture on: a name, index, glob, /regex/, MAC or IP address, `any` or `default`
     #[arg(short, long)]
     name: Vec<String>,
+    /// keep watching for matching interfaces and capture on them once they come up
+    #[arg(long)]
+    hot_plug: bool,
     /// this option makes a file to record packets
     #[arg(short, long)]
-    save: bool
+    save: bool,
+    /// print every layer as a tree of named fields with hex offsets
+    #[arg(short, long, conflicts_with = "debug")]
+    verbose: bool,
+    /// print the raw Debug dump of each frame
+    #[arg(long)]
+    debug: bool,
+    /// dump each frame in hex, one line per layer boundary
+    #[arg(short = 'x', conflicts_with = "hex_ascii")]
+    hex: bool,
+    /// dump each frame in hex and ASCII, one line per layer boundary
+    #[arg(short = 'X')]
+    hex_ascii: bool,
+    /// stop after capturing this many packets
+    #[arg(short, long)]
+    count: Option<u64>,
+    /// stop after capturing for this many seconds
+    #[arg(long)]
+    duration: Option<u64>,
+    /// stop at this local time: "HH:MM[:SS]", "YYYY-MM-DD HH:MM[:SS]" or RFC 3339
+    #[arg(long, value_parser = parse_until)]
+    until: Option<Duration>,
+    /// with --save, start a new file once the current one reaches this many MB
+    #[arg(long)]
+    rotate_mb: Option<u64>,
+    /// with --save, start a new file every this many seconds
+    #[arg(long)]
+    rotate_secs: Option<u64>,
+    /// with --save, keep at most this many files, deleting the oldest
+    #[arg(long)]
+    max_files: Option<usize>,
+    /// with --save, gzip each file once it is closed
+    #[arg(long)]
+    compress: bool,
+    /// serve Prometheus metrics at http://<address>/metrics, e.g. 127.0.0.1:9100
+    #[arg(long)]
+    metrics_listen: Option<SocketAddr>,
+    /// leave the interface out of promiscuous mode, seeing only traffic addressed to it
+    #[arg(long)]
+    no_promiscuous: bool,
+    /// kernel receive buffer (SO_RCVBUF) in bytes; raise it when the kernel drops packets (default: net.core.rmem_default)
+    #[arg(long)]
+    read_buffer: Option<usize>,
+    /// kernel send buffer (SO_SNDBUF) in bytes (default: net.core.wmem_default)
+    #[arg(long)]
+    write_buffer: Option<usize>,
+    /// milliseconds a read waits before checking whether to stop
+    #[arg(long, default_value_t = 200)]
+    read_timeout: u64,
+    /// join a PACKET_FANOUT group spreading packets this way
+    #[arg(long, value_enum)]
+    fanout: Option<FanoutMode>,
+    /// fanout group id, shared with other processes capturing on the interface (default: from the PID)
+    #[arg(long, requires = "fanout")]
+    fanout_group: Option<u16>,
+    /// let the kernel reassemble fragments before fanning them out
+    #[arg(long, requires = "fanout")]
+    fanout_defrag: bool,
+    /// move packets to another socket of the group while one is full
+    #[arg(long, requires = "fanout")]
+    fanout_rollover: bool,
+    /// capture threads per interface, each with its own socket in one fanout group
+    #[arg(long, default_value_t = 1)]
+    workers: usize,
+    /// how frames reach the capture: a read per frame, or a TPACKET_V3 ring mapped into memory
+    #[arg(long, value_enum, default_value_t = Backend::Socket)]
+    backend: Backend,
+    /// with --backend mmap, KiB per ring block, a multiple of the page size
+    #[arg(long, default_value_t = 1024)]
+    ring_block_kib: usize,
+    /// with --backend mmap, frames of 2 KiB the ring holds, rounded up to whole blocks
+    #[arg(long, default_value_t = 16384)]
+    ring_frames: usize,
+    /// with --backend mmap, milliseconds before the kernel hands over a block that is not full
+    #[arg(long, default_value_t = 64)]
+    ring_retire_ms: u64,
+}
+
+/// Turns an `--until` time into how long is left until then.
+fn parse_until(text: &str) -> Result<Duration, String> {
+    use chrono::{DateTime, Local, NaiveDateTime, NaiveTime, TimeDelta};
+
+    let now = Local::now();
+    let target = if let Ok(time) = DateTime::parse_from_rfc3339(text) {
+        time.with_timezone(&Local)
+    } else if let Some(time) = ["%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(text, format).ok())
+    {
+        time.and_local_timezone(Local)
+            .earliest()
+            .ok_or(format!("{} does not exist in the local time zone", text))?
+    } else if let Some(time) = ["%H:%M:%S", "%H:%M"]
+        .iter()
+        .find_map(|format| NaiveTime::parse_from_str(text, format).ok())
+    {
+        let today = now.date_naive().and_time(time);
+        // a time that already passed today means tomorrow
+        let day = if today <= now.naive_local() {
+            today + TimeDelta::days(1)
+        } else {
+            today
+        };
+        day.and_local_timezone(Local)
+            .earliest()
+            .ok_or(format!("{} does not exist in the local time zone", text))?
+    } else {
+        return Err(format!("cannot understand the time {:?}", text));
+    };
+
+    (target - now)
+        .to_std()
+        .map_err(|_| format!("{} is already in the past", text))
+}
+
+#[derive(Parser)]
+struct TuiArgs {
+    /// interface to capture on: a name, index, glob, /regex/, MAC or IP address, `any` or `default`
+    #[arg(short, long)]
+    name: Vec<String>,
+    /// keep at most this many recent packets in memory per interface
+    #[arg(long, default_value_t = 10_000)]
+    store_packets: usize,
+    /// keep at most this many MiB of recent packets in memory per interface
+    #[arg(long, default_value_t = 64)]
+    store_mib: usize,
+    /// drop packets older than this many seconds from memory
+    #[arg(long)]
+    store_age: Option<u64>,
+}
+
+#[derive(Parser)]
+struct FlowsArgs {
+    /// interface to capture on: a name, index, glob, /regex/, MAC or IP address, `any` or `default`
+    #[arg(short, long)]
+    name: Vec<String>,
+    /// order the table by this column, largest first
+    #[arg(long, value_enum, default_value_t = FlowSort::Bytes)]
+    sort: FlowSort,
+    /// print only this many flows
+    #[arg(long)]
+    top: Option<usize>,
+    /// also print the table every this many seconds while capturing
+    #[arg(long)]
+    interval: Option<u64>,
+    /// end a flow after this many seconds without packets
+    #[arg(long, default_value_t = 15)]
+    idle_timeout: u64,
+    /// report and restart flows running longer than this many seconds
+    #[arg(long, default_value_t = 1800)]
+    active_timeout: u64,
+    /// stop after capturing this many packets
+    #[arg(short, long)]
+    count: Option<u64>,
+    /// stop after capturing for this many seconds
+    #[arg(long)]
+    duration: Option<u64>,
+    /// send finished flows over UDP to this collector, e.g. 127.0.0.1:2055
+    #[arg(long)]
+    export: Option<SocketAddr>,
+    /// record format used with --export
+    #[arg(long, value_enum, default_value_t = ExportFormat::V9)]
+    export_format: ExportFormat,
+}
+
+#[derive(Parser)]
+struct CollectArgs {
+    /// UDP port, or address:port, to receive flow exports on
+    #[arg(short, long)]
+    listen: String,
+    /// print every field of each flow record
+    #[arg(short, long)]
+    verbose: bool,
+    /// stop after collecting for this many seconds
+    #[arg(long)]
+    duration: Option<u64>,
+}
+
+#[derive(Parser)]
+struct StatsArgs {
+    /// interface to capture on: a name, index, glob, /regex/, MAC or IP address, `any` or `default`
+    #[arg(short, long, required_unless_present = "file")]
+    name: Vec<String>,
+    /// read packets from this pcap file instead of capturing
+    #[arg(short, long, conflicts_with = "name")]
+    file: Option<PathBuf>,
+    /// list this many talkers and ports per table
+    #[arg(long, default_value_t = 10)]
+    top: usize,
+    /// seconds per row of the packets/s and bits/s series
+    #[arg(long, default_value_t = 1.0)]
+    bucket: f64,
+    /// stop after this many packets
+    #[arg(short, long)]
+    count: Option<u64>,
+    /// stop after capturing for this many seconds
+    #[arg(long)]
+    duration: Option<u64>,
+}
+
+#[derive(Parser)]
+struct PingArgs {
+    /// host name or IP address to probe
+    target: String,
+    /// kind of probe to send
+    #[arg(short, long, value_enum, default_value_t = ProbeProtocol::Icmp)]
+    protocol: ProbeProtocol,
+    /// destination port of TCP and UDP probes (default 80 for TCP, 33434 for UDP)
+    #[arg(long)]
+    port: Option<u16>,
+    /// stop after sending this many probes
+    #[arg(short, long)]
+    count: Option<u64>,
+    /// seconds between probes
+    #[arg(short, long, default_value_t = 1.0)]
+    interval: f64,
+    /// seconds to wait for each answer
+    #[arg(short = 'W', long, default_value_t = 1.0)]
+    timeout: f64,
+    /// TTL or hop limit of the probes
+    #[arg(long, default_value_t = 64)]
+    ttl: u8,
+    /// capture the answers on this interface
+    #[arg(short, long)]
+    name: Option<String>,
+}
+
+#[derive(Parser)]
+struct TracerouteArgs {
+    /// host name or IP address to trace
+    target: String,
+    /// kind of probe to send
+    #[arg(short, long, value_enum, default_value_t = ProbeProtocol::Udp)]
+    protocol: ProbeProtocol,
+    /// destination port of TCP and UDP probes (default 80 for TCP, 33434 for UDP)
+    #[arg(long)]
+    port: Option<u16>,
+    /// TTL of the first hop probed
+    #[arg(short, long, default_value_t = 1)]
+    first_ttl: u8,
+    /// give up after this many hops
+    #[arg(short, long, default_value_t = 30)]
+    max_hops: u8,
+    /// probes per hop
+    #[arg(short, long, default_value_t = 3)]
+    queries: usize,
+    /// seconds to wait for the answers of a hop
+    #[arg(short = 'W', long, default_value_t = 1.0)]
+    timeout: f64,
+    /// capture the answers on this interface
+    #[arg(short, long)]
+    name: Option<String>,
+}
+
+#[derive(Parser)]
+struct DiscoverArgs {
+    /// enter the network interface name whose segment is scanned.
+    #[arg(short, long)]
+    name: String,
+    /// seconds to keep listening for answers after the last request
+    #[arg(short, long, default_value_t = 2.0)]
+    wait: f64,
+    /// refuse to sweep subnets with more addresses than this
+    #[arg(long, default_value_t = 4096)]
+    max_hosts: u32,
+    /// OUI database naming vendors (Wireshark manuf, IEEE oui.txt or nmap-mac-prefixes)
+    #[arg(long)]
+    oui: Option<PathBuf>,
+    /// scan IPv4 only
+    #[arg(long)]
+    no_ipv6: bool,
+}
+
+#[derive(Parser)]
+struct InventoryArgs {
+    /// interface to capture on: a name, index, glob, /regex/, MAC or IP address, `any` or `default`
+    #[arg(short, long, required_unless_present = "file")]
+    name: Vec<String>,
+    /// read packets from this pcap file instead of capturing
+    #[arg(short, long, conflicts_with = "name")]
+    file: Option<PathBuf>,
+    /// OUI database naming vendors (Wireshark manuf, IEEE oui.txt or nmap-mac-prefixes)
+    #[arg(long)]
+    oui: Option<PathBuf>,
+    /// fingerprint the OS of SYN and SYN-ACK senders with this p0f-style signature file
+    #[arg(long)]
+    p0f: Option<PathBuf>,
+    /// stop after this many packets
+    #[arg(short, long)]
+    count: Option<u64>,
+    /// stop after capturing for this many seconds
+    #[arg(long)]
+    duration: Option<u64>,
 }
 
 fn main() {
@@ -38,31 +384,264 @@ fn main() {
 
     if let Some(command) = cmd.command {
         match command {
-            Command::Ls => {
-                print!("{}", network_test::get_interface_names());
-            },
+            Command::Ls(args) => {
+                let filter = InterfaceFilter {
+                    up: args.up,
+                    loopback: match (args.loopback, args.no_loopback) {
+                        (true, _) => Some(true),
+                        (_, true) => Some(false),
+                        _ => None,
+                    },
+                    ipv4: args.ipv4,
+                };
+                print!("{}", network_test::get_interface_names(&filter, args.json));
+            }
             Command::Read(args) => {
-                if args.name.len() == 0 {
-                    panic!("Input interface name what you want to use");
-                }
+                let selection = match InterfaceSelection::parse(&args.name, args.hot_plug) {
+                    Ok(selection) => selection,
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        return;
+                    }
+                };
 
                 println!("args = {:?}", args.name);
-                
+
                 // read_packet(&[String::from("\\Device\\NPF_{795C5FEC-E759-4FF5-AE9A-F6782C4FC796}")]);
-                read_packet(&args.name, args.save);
+                let format = if args.verbose {
+                    OutputFormat::Tree
+                } else if args.debug {
+                    OutputFormat::Debug
+                } else {
+                    OutputFormat::Summary
+                };
+
+                let hex = if args.hex_ascii {
+                    HexDump::HexAscii
+                } else if args.hex {
+                    HexDump::Hex
+                } else {
+                    HexDump::Off
+                };
+
+                let now = Instant::now();
+                let deadline = [args.duration.map(Duration::from_secs), args.until]
+                    .into_iter()
+                    .flatten()
+                    .min()
+                    .map(|left| now + left);
+
+                read_packet(
+                    &selection,
+                    ReadOptions {
+                        is_save: args.save,
+                        format,
+                        hex,
+                        rotation: RotationOptions {
+                            max_bytes: args.rotate_mb.map(|mb| mb * 1024 * 1024),
+                            max_age: args.rotate_secs.map(Duration::from_secs),
+                            max_files: args.max_files,
+                            compress: args.compress,
+                        },
+                        limits: CaptureLimits {
+                            count: args.count,
+                            deadline,
+                        },
+                        metrics_listen: args.metrics_listen,
+                        channel: ChannelOptions {
+                            backend: args.backend,
+                            ring: RingOptions {
+                                block_size: args.ring_block_kib * 1024,
+                                frame_count: args.ring_frames.max(1),
+                                retire_timeout: Duration::from_millis(args.ring_retire_ms.max(1)),
+                            },
+                            promiscuous: !args.no_promiscuous,
+                            read_buffer: args.read_buffer,
+                            write_buffer: args.write_buffer,
+                            read_timeout: Duration::from_millis(args.read_timeout.max(1)),
+                            fanout: args.fanout.map(|mode| Fanout {
+                                mode,
+                                group: args.fanout_group.unwrap_or(std::process::id() as u16),
+                                defrag: args.fanout_defrag,
+                                rollover: args.fanout_rollover,
+                            }),
+                            workers: args.workers.max(1),
+                        },
+                    },
+                );
+            }
+            Command::Tui(args) => {
+                let selection = match InterfaceSelection::parse(&args.name, false) {
+                    Ok(selection) => selection,
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        return;
+                    }
+                };
+
+                let limits = StoreLimits {
+                    max_packets: args.store_packets,
+                    max_bytes: args.store_mib * 1024 * 1024,
+                    max_age: args.store_age.map(Duration::from_secs),
+                };
+
+                if let Err(e) = network_test::tui::run(&selection, limits) {
+                    eprintln!("{}", e);
+                }
+            }
+            Command::Flows(args) => {
+                let selection = match InterfaceSelection::parse(&args.name, false) {
+                    Ok(selection) => selection,
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        return;
+                    }
+                };
+
+                track_flows(
+                    &selection,
+                    FlowOptions {
+                        limits: CaptureLimits {
+                            count: args.count,
+                            deadline: args
+                                .duration
+                                .map(|secs| Instant::now() + Duration::from_secs(secs)),
+                        },
+                        timeouts: FlowTimeouts {
+                            idle: Duration::from_secs(args.idle_timeout),
+                            active: Duration::from_secs(args.active_timeout),
+                            ..FlowTimeouts::default()
+                        },
+                        sort: args.sort,
+                        top: args.top,
+                        interval: args.interval.map(Duration::from_secs),
+                        export: args.export.map(|collector| (collector, args.export_format)),
+                    },
+                );
+            }
+            Command::Collect(args) => {
+                let listen = match args.listen.parse::<u16>() {
+                    Ok(port) => SocketAddr::from(([0, 0, 0, 0], port)),
+                    Err(_) => match args.listen.parse::<SocketAddr>() {
+                        Ok(address) => address,
+                        Err(e) => {
+                            eprintln!(
+                                "cannot listen on {:?}, neither a port nor an address: {}",
+                                args.listen, e
+                            );
+                            return;
+                        }
+                    },
+                };
+                let deadline = args
+                    .duration
+                    .map(|secs| Instant::now() + Duration::from_secs(secs));
+
+                if let Err(e) = network_test::collector::collect(listen, args.verbose, deadline) {
+                    eprintln!("cannot collect on {}: {}", listen, e);
+                }
+            }
+            Command::Stats(args) => {
+                let options = StatsOptions {
+                    limits: CaptureLimits {
+                        count: args.count,
+                        deadline: args
+                            .duration
+                            .map(|secs| Instant::now() + Duration::from_secs(secs)),
+                    },
+                    top: args.top,
+                    bucket: Duration::from_secs_f64(args.bucket.max(0.001)),
+                };
+
+                match args.file {
+                    Some(path) => file_stats(&path, options),
+                    None => {
+                        let selection = match InterfaceSelection::parse(&args.name, false) {
+                            Ok(selection) => selection,
+                            Err(e) => {
+                                eprintln!("{}", e);
+                                return;
+                            }
+                        };
+                        live_stats(&selection, options)
+                    }
+                }
+            }
+            Command::Ping(args) => {
+                let options = PingOptions {
+                    protocol: args.protocol,
+                    port: args.port,
+                    count: args.count,
+                    interval: Duration::from_secs_f64(args.interval.max(0.01)),
+                    timeout: Duration::from_secs_f64(args.timeout.max(0.01)),
+                    ttl: args.ttl,
+                    interface: args.name,
+                };
+
+                if let Err(e) = network_test::probe::ping(&args.target, options) {
+                    eprintln!("{}", e);
+                }
+            }
+            Command::Traceroute(args) => {
+                let options = TracerouteOptions {
+                    protocol: args.protocol,
+                    port: args.port,
+                    first_ttl: args.first_ttl,
+                    max_hops: args.max_hops,
+                    queries: args.queries.max(1),
+                    timeout: Duration::from_secs_f64(args.timeout.max(0.01)),
+                    interface: args.name,
+                };
+
+                if let Err(e) = network_test::probe::traceroute(&args.target, options) {
+                    eprintln!("{}", e);
+                }
+            }
+            Command::Discover(args) => {
+                let options = DiscoverOptions {
+                    wait: Duration::from_secs_f64(args.wait.max(0.0)),
+                    max_hosts: args.max_hosts,
+                    oui: args.oui,
+                    ipv6: !args.no_ipv6,
+                };
+
+                if let Err(e) = network_test::discover::discover(&args.name, options) {
+                    eprintln!("{}", e);
+                }
+            }
+            Command::Inventory(args) => {
+                let options = InventoryOptions {
+                    limits: CaptureLimits {
+                        count: args.count,
+                        deadline: args
+                            .duration
+                            .map(|secs| Instant::now() + Duration::from_secs(secs)),
+                    },
+                    oui: args.oui,
+                    signatures: args.p0f,
+                };
+
+                match args.file {
+                    Some(path) => file_inventory(&path, options),
+                    None => {
+                        let selection = match InterfaceSelection::parse(&args.name, false) {
+                            Ok(selection) => selection,
+                            Err(e) => {
+                                eprintln!("{}", e);
+                                return;
+                            }
+                        };
+                        live_inventory(&selection, options)
+                    }
+                }
             }
             _ => {
                 println!("this is not defined command");
             }
         }
-        
     } else {
-        println!(
-            "This is Network Test. Made by croo12 <its19447@gmail.com>"
-        );
+        println!("This is Network Test. Made by croo12 <its19447@gmail.com>");
     }
-
-
 }
 
 // Invoke as echo <interface name>
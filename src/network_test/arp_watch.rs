@@ -0,0 +1,237 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::net::Ipv4Addr;
+use std::time::{Duration, SystemTime};
+
+use pnet::util::MacAddr;
+
+use crate::network_test::datalink::EthernetIIFrame;
+use crate::network_test::network::{ARPKind, PacketType};
+
+/// Changes of one address within this long count as flapping.
+const FLAP_WINDOW: Duration = Duration::from_secs(60);
+/// Bindings kept before idle ones are dropped.
+const MAX_BINDINGS: usize = 65_536;
+const IDLE_TIMEOUT: Duration = Duration::from_secs(3600);
+
+/// Something suspicious about an ARP packet, as arpwatch reports it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArpAlert {
+    /// the address is now claimed by another MAC
+    Conflict {
+        ip: Ipv4Addr,
+        previous: MacAddr,
+        current: MacAddr,
+    },
+    /// the address keeps moving between MACs
+    Flapping {
+        ip: Ipv4Addr,
+        macs: Vec<MacAddr>,
+        changes: usize,
+    },
+    /// the ARP sender is not the MAC the frame came from
+    SenderMismatch { ethernet: MacAddr, arp: MacAddr },
+}
+
+impl fmt::Display for ArpAlert {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArpAlert::Conflict {
+                ip,
+                previous,
+                current,
+            } => write!(
+                f,
+                "ARP conflict: {} moved from {} to {}",
+                ip, previous, current
+            ),
+            ArpAlert::Flapping { ip, macs, changes } => {
+                let macs: Vec<String> = macs.iter().map(|mac| mac.to_string()).collect();
+                write!(
+                    f,
+                    "ARP flapping: {} changed MAC {} times in {}s between {}",
+                    ip,
+                    changes,
+                    FLAP_WINDOW.as_secs(),
+                    macs.join(", ")
+                )
+            }
+            ArpAlert::SenderMismatch { ethernet, arp } => write!(
+                f,
+                "ARP sender {} is not the Ethernet source {}",
+                arp, ethernet
+            ),
+        }
+    }
+}
+
+/// Where an address was last seen, and its recent moves.
+#[derive(Debug)]
+struct Binding {
+    mac: MacAddr,
+    last_seen: SystemTime,
+    /// when the address moved, and the MAC it moved away from
+    changes: VecDeque<(SystemTime, MacAddr)>,
+}
+
+/// The IP to MAC bindings announced by ARP, checked against every new claim.
+#[derive(Debug, Default)]
+pub struct ArpWatch {
+    bindings: HashMap<Ipv4Addr, Binding>,
+}
+
+impl ArpWatch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The MAC `ip` is bound to at the moment.
+    pub fn lookup(&self, ip: Ipv4Addr) -> Option<MacAddr> {
+        self.bindings.get(&ip).map(|binding| binding.mac)
+    }
+
+    /// Learns from `frame` if it is an ARP packet and returns what looks wrong about it.
+    pub fn observe(&mut self, frame: &EthernetIIFrame, timestamp: SystemTime) -> Vec<ArpAlert> {
+        let mut alerts = Vec::new();
+        let packet = match frame.get_network_packet() {
+            PacketType::ARP(packet) => packet,
+            _ => return alerts,
+        };
+        let (Some(ip), Some(mac)) = (packet.get_sender_ip(), packet.get_sender_mac()) else {
+            return alerts;
+        };
+
+        if mac != frame.get_source() {
+            alerts.push(ArpAlert::SenderMismatch {
+                ethernet: frame.get_source(),
+                arp: mac,
+            });
+        }
+
+        // a probe has no address yet, it only asks whether one is free
+        if !matches!(
+            packet.kind(),
+            ARPKind::Request | ARPKind::Reply | ARPKind::Gratuitous | ARPKind::Announcement
+        ) || ip.is_unspecified()
+        {
+            return alerts;
+        }
+
+        if self.bindings.len() > MAX_BINDINGS {
+            self.bindings.retain(|_, binding| {
+                timestamp
+                    .duration_since(binding.last_seen)
+                    .unwrap_or_default()
+                    < IDLE_TIMEOUT
+            });
+        }
+
+        let binding = self.bindings.entry(ip).or_insert_with(|| Binding {
+            mac,
+            last_seen: timestamp,
+            changes: VecDeque::new(),
+        });
+        binding.last_seen = timestamp;
+        if binding.mac == mac {
+            return alerts;
+        }
+
+        let previous = std::mem::replace(&mut binding.mac, mac);
+        binding.changes.push_back((timestamp, previous));
+        while binding.changes.front().is_some_and(|(changed, _)| {
+            timestamp.duration_since(*changed).unwrap_or_default() > FLAP_WINDOW
+        }) {
+            binding.changes.pop_front();
+        }
+
+        if binding.changes.len() < 2 {
+            alerts.push(ArpAlert::Conflict {
+                ip,
+                previous,
+                current: mac,
+            });
+        } else {
+            let mut macs: Vec<MacAddr> = binding.changes.iter().map(|(_, mac)| *mac).collect();
+            macs.push(mac);
+            macs.sort();
+            macs.dedup();
+            alerts.push(ArpAlert::Flapping {
+                ip,
+                macs,
+                changes: binding.changes.len(),
+            });
+        }
+
+        alerts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network_test::util::frames::{self, CLIENT, SERVER};
+
+    /// An ARP reply saying 10.0.0.1 is at 02:00:00:00:00:`mac`, from `source`.
+    fn reply(source: u8, mac: u8) -> EthernetIIFrame {
+        let arp = frames::arp(2, (frames::mac(mac), CLIENT), (frames::mac(0xff), SERVER));
+        EthernetIIFrame::new(&frames::ethernet(
+            frames::mac(source),
+            [0xff; 6],
+            0x0806,
+            &arp,
+        ))
+        .unwrap()
+    }
+
+    fn mac(last: u8) -> MacAddr {
+        let [a, b, c, d, e, f] = frames::mac(last);
+        MacAddr::new(a, b, c, d, e, f)
+    }
+
+    fn at(seconds: u64) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(seconds)
+    }
+
+    #[test]
+    fn conflict_then_flapping() {
+        let ip = Ipv4Addr::new(10, 0, 0, 1);
+        let mut watch = ArpWatch::new();
+        assert!(watch.observe(&reply(1, 1), at(0)).is_empty());
+        assert_eq!(watch.lookup(ip), Some(mac(1)));
+
+        assert_eq!(
+            watch.observe(&reply(2, 2), at(1)),
+            vec![ArpAlert::Conflict {
+                ip,
+                previous: mac(1),
+                current: mac(2)
+            }]
+        );
+        assert_eq!(
+            watch.observe(&reply(1, 1), at(2)),
+            vec![ArpAlert::Flapping {
+                ip,
+                macs: vec![mac(1), mac(2)],
+                changes: 2
+            }]
+        );
+
+        // outside the window a move is a plain conflict again
+        assert!(matches!(
+            watch.observe(&reply(2, 2), at(200)).as_slice(),
+            [ArpAlert::Conflict { .. }]
+        ));
+    }
+
+    #[test]
+    fn sender_mismatch() {
+        let mut watch = ArpWatch::new();
+        assert_eq!(
+            watch.observe(&reply(9, 1), at(0)),
+            vec![ArpAlert::SenderMismatch {
+                ethernet: mac(9),
+                arp: mac(1)
+            }]
+        );
+    }
+}
@@ -0,0 +1,671 @@
+use std::fmt;
+use std::io::ErrorKind;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime};
+
+use pnet::datalink::Channel::Ethernet;
+use pnet::datalink::{DataLinkReceiver, DataLinkSender, NetworkInterface};
+
+use crate::network_test::datalink::EthernetIIFrame;
+use crate::network_test::interfaces::InterfaceSelection;
+use crate::network_test::metrics::Metrics;
+use crate::network_test::packet_socket::{Buffer, PacketSocket};
+use crate::network_test::tpacket::{KernelStats, Ring, RingOptions};
+
+/// Consecutive read errors after which an interface stops capturing.
+const MAX_READ_FAILURES: u32 = 10;
+/// Largest frame the socket backend reads whole; offloaded segments can
+/// reach this, longer frames are cut short.
+const MAX_FRAME: usize = 65_536;
+/// How often the kernel's socket counters are read; they are 32 bits wide
+/// and reading them empties them.
+const KERNEL_STATISTICS: Duration = Duration::from_secs(1);
+/// How often hot plugging looks for new interfaces.
+const HOT_PLUG_SCAN: Duration = Duration::from_secs(1);
+
+/// When a capture stops by itself; Ctrl-C and SIGTERM always stop it.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CaptureLimits {
+    /// stop after this many packets over all interfaces
+    pub count: Option<u64>,
+    /// stop once this instant has passed
+    pub deadline: Option<Instant>,
+}
+
+/// How frames get from the kernel to the capture threads.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Backend {
+    /// one read, and one copy, per frame
+    #[default]
+    Socket,
+    /// a TPACKET_V3 ring shared with the kernel, read a block at a time
+    Mmap,
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Backend::Socket => "socket",
+            Backend::Mmap => "mmap",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// How packets are spread over the sockets of one fanout group.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum FanoutMode {
+    /// by flow, so both directions of a connection land on one socket
+    Hash,
+    /// round robin
+    LoadBalance,
+    /// by the CPU the packet arrived on
+    Cpu,
+    /// fill one socket, then move to the next
+    Rollover,
+    Random,
+    /// by the NIC receive queue
+    QueueMapping,
+}
+
+impl fmt::Display for FanoutMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            FanoutMode::Hash => "hash",
+            FanoutMode::LoadBalance => "load-balance",
+            FanoutMode::Cpu => "cpu",
+            FanoutMode::Rollover => "rollover",
+            FanoutMode::Random => "random",
+            FanoutMode::QueueMapping => "queue-mapping",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Linux PACKET_FANOUT: sockets in one group share the packets of an interface.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fanout {
+    pub mode: FanoutMode,
+    /// other processes can join the group by its id; the n-th interface
+    /// of a capture uses `group + n`, since a group serves one interface
+    pub group: u16,
+    /// reassemble fragments first so they hash like the rest of their flow
+    pub defrag: bool,
+    /// move packets to another socket while one is full
+    pub rollover: bool,
+}
+
+/// How the capture sockets are set up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChannelOptions {
+    pub backend: Backend,
+    /// the ring of the mmap backend
+    pub ring: RingOptions,
+    pub promiscuous: bool,
+    /// kernel receive buffer of the socket backend in bytes, where frames
+    /// wait to be read; `None` keeps `net.core.rmem_default`
+    pub read_buffer: Option<usize>,
+    /// kernel send buffer of the socket backend in bytes
+    pub write_buffer: Option<usize>,
+    /// how long a read waits before checking whether to stop
+    pub read_timeout: Duration,
+    pub fanout: Option<Fanout>,
+    /// sockets, each read by its own thread, per interface; above one
+    /// they form a fanout group even if `fanout` is not set
+    pub workers: usize,
+}
+
+impl Default for ChannelOptions {
+    fn default() -> Self {
+        ChannelOptions {
+            backend: Backend::Socket,
+            ring: RingOptions::default(),
+            promiscuous: true,
+            read_buffer: None,
+            write_buffer: None,
+            read_timeout: Duration::from_millis(200),
+            fanout: None,
+            workers: 1,
+        }
+    }
+}
+
+impl ChannelOptions {
+    /// The fanout group the sockets of the `position`-th interface join, if any.
+    fn fanout_for(&self, position: usize) -> Option<Fanout> {
+        let fanout = match (self.fanout, self.workers) {
+            (Some(fanout), _) => fanout,
+            (None, 1) => return None,
+            (None, _) => Fanout {
+                mode: FanoutMode::Hash,
+                // ids are shared by every process in the namespace
+                group: std::process::id() as u16,
+                defrag: true,
+                rollover: false,
+            },
+        };
+        Some(Fanout {
+            group: fanout.group.wrapping_add(position as u16),
+            ..fanout
+        })
+    }
+
+    /// The settings one interface captures with, as reported when it
+    /// starts. The buffer sizes the kernel granted follow once it opened.
+    fn describe(&self, fanout: Option<Fanout>) -> String {
+        let mut settings = vec![String::from(match self.promiscuous {
+            true => "promiscuous",
+            false => "not promiscuous",
+        })];
+        match self.backend {
+            Backend::Socket => {}
+            Backend::Mmap => settings.push(self.ring.to_string()),
+        }
+        settings.push(format!("read timeout {} ms", self.read_timeout.as_millis()));
+        if let Some(fanout) = fanout {
+            let mut group = format!("fanout {} group {}", fanout.mode, fanout.group);
+            if fanout.defrag {
+                group.push_str(" defrag");
+            }
+            if fanout.rollover {
+                group.push_str(" rollover");
+            }
+            settings.push(group);
+        }
+        if self.workers > 1 {
+            settings.push(format!("{} workers", self.workers));
+        }
+        settings.join(", ")
+    }
+}
+
+/// Receives every frame one capture thread dissected.
+pub trait FrameHandler: Send {
+    fn handle(&mut self, timestamp: SystemTime, data: &[u8], frame: EthernetIIFrame);
+
+    /// Called once when the capture thread stops.
+    fn finish(&mut self) {}
+}
+
+/// Per-interface counters reported when a capture ends.
+#[derive(Debug, Default)]
+struct CaptureCounters {
+    captured: AtomicU64,
+    /// frames the channel failed to deliver
+    dropped: AtomicU64,
+    /// frames that could not be dissected
+    malformed: AtomicU64,
+    /// what the kernel reported for the sockets
+    kernel_packets: AtomicU64,
+    kernel_drops: AtomicU64,
+    kernel_freezes: AtomicU64,
+}
+
+impl CaptureCounters {
+    fn add_kernel(&self, stats: KernelStats) {
+        self.kernel_packets
+            .fetch_add(stats.packets, Ordering::Relaxed);
+        self.kernel_drops.fetch_add(stats.drops, Ordering::Relaxed);
+        self.kernel_freezes
+            .fetch_add(stats.freezes, Ordering::Relaxed);
+    }
+
+    fn kernel(&self) -> KernelStats {
+        KernelStats {
+            packets: self.kernel_packets.load(Ordering::Relaxed),
+            drops: self.kernel_drops.load(Ordering::Relaxed),
+            freezes: self.kernel_freezes.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct InterfaceSummary {
+    pub name: String,
+    pub captured: u64,
+    pub dropped: u64,
+    pub malformed: u64,
+    pub kernel: KernelStats,
+}
+
+#[derive(Debug, Clone)]
+pub struct CaptureSummary {
+    pub elapsed: Duration,
+    pub interfaces: Vec<InterfaceSummary>,
+}
+
+impl fmt::Display for CaptureSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} packets captured in {:.3}s",
+            self.interfaces.iter().map(|i| i.captured).sum::<u64>(),
+            self.elapsed.as_secs_f64()
+        )?;
+        for interface in &self.interfaces {
+            writeln!(
+                f,
+                "{} : {} captured, {} dropped, {} malformed; {}",
+                interface.name,
+                interface.captured,
+                interface.dropped,
+                interface.malformed,
+                interface.kernel
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Shared between the capture threads and the thread waiting for them.
+struct StopSignal {
+    stop: AtomicBool,
+    count: Option<u64>,
+    taken: AtomicU64,
+}
+
+impl StopSignal {
+    fn is_set(&self) -> bool {
+        self.stop.load(Ordering::Relaxed)
+    }
+
+    fn set(&self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+
+    /// Claims one packet of the `-c` budget; false once it is used up.
+    fn take(&self) -> bool {
+        let taken = self.taken.fetch_add(1, Ordering::Relaxed) + 1;
+        match self.count {
+            Some(count) if taken >= count => {
+                self.set();
+                taken == count
+            }
+            _ => true,
+        }
+    }
+}
+
+/// Captures on every interface `selection` picks, one thread each, until a
+/// limit is reached, a signal arrives or every thread gave up. With hot
+/// plugging it instead keeps going, starting on matching interfaces as they
+/// come up. Each interface gets `channel.workers` threads, every one with
+/// its own handler, made with the worker's index. `tick` runs on the
+/// calling thread about ten times a second meanwhile. Every frame, parse
+/// error and read error is also counted in `metrics` if given.
+pub fn run<F, T>(
+    selection: &InterfaceSelection,
+    channel: &ChannelOptions,
+    limits: CaptureLimits,
+    metrics: Option<Arc<Metrics>>,
+    mut make_handler: F,
+    mut tick: T,
+) -> Result<CaptureSummary, String>
+where
+    F: FnMut(&NetworkInterface, usize) -> Box<dyn FrameHandler>,
+    T: FnMut(),
+{
+    let interfaces = selection.resolve()?;
+    if interfaces.is_empty() {
+        println!("waiting for a matching interface to come up");
+    }
+
+    let signal = Arc::new(StopSignal {
+        stop: AtomicBool::new(false),
+        count: limits.count,
+        taken: AtomicU64::new(0),
+    });
+
+    let handler_signal = Arc::clone(&signal);
+    if let Err(e) = ctrlc::set_handler(move || handler_signal.set()) {
+        eprintln!("cannot install the signal handler: {}", e);
+    }
+
+    let mut thread_handler: Vec<(String, Arc<CaptureCounters>, JoinHandle<()>)> = vec![];
+    // name and index of every interface captured on, so one that is
+    // removed and plugged in again is picked up anew
+    let mut started_on: Vec<(String, u32)> = vec![];
+    let started = Instant::now();
+
+    let mut start = |interface: NetworkInterface, position: usize| {
+        if let Some(metrics) = metrics.as_ref() {
+            metrics.register(&interface.name);
+        }
+        let fanout = channel.fanout_for(position);
+        let channel = *channel;
+
+        println!("action thread for {:?}", &interface.name);
+        println!("{} : {}", interface.name, channel.describe(fanout));
+
+        let counters = Arc::new(CaptureCounters::default());
+        (0..channel.workers.max(1))
+            .map(|worker| {
+                let interface = interface.clone();
+                let signal = Arc::clone(&signal);
+                let metrics = metrics.clone();
+                let thread_counters = Arc::clone(&counters);
+                let handler = make_handler(&interface, worker);
+                let name = interface.name.clone();
+                let handle = thread::spawn(move || {
+                    let worker = Worker {
+                        interface,
+                        index: worker,
+                        channel,
+                        fanout,
+                    };
+                    capture_packet(
+                        &worker,
+                        handler,
+                        &signal,
+                        &thread_counters,
+                        metrics.as_deref(),
+                    )
+                });
+                (name, Arc::clone(&counters), handle)
+            })
+            .collect::<Vec<_>>()
+    };
+
+    for interface in interfaces {
+        started_on.push((interface.name.clone(), interface.index));
+        thread_handler.extend(start(interface, started_on.len() - 1));
+    }
+
+    let mut last_scan = Instant::now();
+    while !signal.is_set()
+        && (selection.hot_plug || thread_handler.iter().any(|(_, _, h)| !h.is_finished()))
+    {
+        if limits
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            signal.set();
+        }
+        if selection.hot_plug && last_scan.elapsed() >= HOT_PLUG_SCAN {
+            last_scan = Instant::now();
+            for interface in selection.select(pnet::datalink::interfaces()) {
+                let key = (interface.name.clone(), interface.index);
+                if interface.is_up() && !started_on.contains(&key) {
+                    started_on.push(key);
+                    thread_handler.extend(start(interface, started_on.len() - 1));
+                }
+            }
+        }
+        tick();
+        thread::sleep(Duration::from_millis(100));
+    }
+    signal.set();
+
+    let mut interfaces: Vec<InterfaceSummary> = vec![];
+    let mut counted: Vec<Arc<CaptureCounters>> = vec![];
+    for (name, counters, handle) in thread_handler {
+        if handle.join().is_err() {
+            eprintln!("capture thread for {} panicked", name);
+        }
+        // the workers of one interface share their counters
+        if counted.iter().any(|seen| Arc::ptr_eq(seen, &counters)) {
+            continue;
+        }
+        interfaces.push(InterfaceSummary {
+            name,
+            captured: counters.captured.load(Ordering::Relaxed),
+            dropped: counters.dropped.load(Ordering::Relaxed),
+            malformed: counters.malformed.load(Ordering::Relaxed),
+            kernel: counters.kernel(),
+        });
+        counted.push(counters);
+    }
+
+    Ok(CaptureSummary {
+        elapsed: started.elapsed(),
+        interfaces,
+    })
+}
+
+/// Both halves of a layer 2 channel.
+pub type Channel = (Box<dyn DataLinkSender>, Box<dyn DataLinkReceiver>);
+
+/// Creates a new channel dealing with layer 2 packets and keeps its receiving half.
+/// Reads time out regularly so capture loops can notice a stop request.
+pub fn open_receiver(interface: &NetworkInterface) -> Result<Box<dyn DataLinkReceiver>, String> {
+    open_channel(interface).map(|(_tx, rx)| rx)
+}
+
+/// Like `open_receiver`, keeping the sending half for tools that inject frames.
+pub fn open_channel(interface: &NetworkInterface) -> Result<Channel, String> {
+    let config = pnet::datalink::Config {
+        read_timeout: Some(ChannelOptions::default().read_timeout),
+        ..Default::default()
+    };
+    match pnet::datalink::channel(interface, config) {
+        Ok(Ethernet(tx, rx)) => Ok((tx, rx)),
+        Ok(_exception) => Err(String::from("Unhandled channel type")),
+        Err(e) => Err(format!(
+            "An error occurred when creating the datalink channel: {}\ninterface: {}",
+            e, interface.description
+        )),
+    }
+}
+
+/// What one capture thread reads with.
+struct Worker {
+    interface: NetworkInterface,
+    /// position among the threads of its interface
+    index: usize,
+    channel: ChannelOptions,
+    fanout: Option<Fanout>,
+}
+
+fn capture_packet(
+    worker: &Worker,
+    mut handler: Box<dyn FrameHandler>,
+    signal: &StopSignal,
+    counters: &CaptureCounters,
+    metrics: Option<&Metrics>,
+) {
+    let interface = &worker.interface;
+    // both backends hand their frames to the same dissection
+    let mut deliver = |timestamp: SystemTime, packet: &[u8]| -> bool {
+        match EthernetIIFrame::new(packet) {
+            Some(frame) => {
+                if !signal.take() {
+                    return false;
+                }
+                counters.captured.fetch_add(1, Ordering::Relaxed);
+                if let Some(metrics) = metrics {
+                    metrics.record_frame(&interface.name, packet, &frame);
+                }
+                handler.handle(timestamp, packet, frame);
+            }
+            None => {
+                counters.malformed.fetch_add(1, Ordering::Relaxed);
+                if let Some(metrics) = metrics {
+                    metrics.record_malformed(&interface.name);
+                }
+            }
+        };
+        true
+    };
+
+    match worker.channel.backend {
+        Backend::Socket => read_socket(worker, signal, counters, metrics, &mut deliver),
+        Backend::Mmap => read_ring(worker, signal, counters, metrics, &mut deliver),
+    }
+
+    handler.finish();
+}
+
+/// Opens a packet socket with the kernel buffers the channel options ask
+/// for. The first worker of an interface warns when the kernel granted
+/// less and reports the sizes it got; the others share its settings.
+fn open_socket(worker: &Worker) -> Result<PacketSocket, String> {
+    let (interface, channel) = (&worker.interface, &worker.channel);
+    let report = worker.index == 0;
+    let socket = PacketSocket::new()?;
+    let error = |what: &str, e: std::io::Error| format!("cannot set the {}: {}", what, e);
+
+    // sized before binding, so no frame waits in the default buffer
+    for (buffer, size, name, limit) in [
+        (Buffer::Receive, channel.read_buffer, "receive", "rmem_max"),
+        (Buffer::Send, channel.write_buffer, "send", "wmem_max"),
+    ] {
+        let Some(size) = size else {
+            continue;
+        };
+        let granted = socket
+            .set_buffer(buffer, size)
+            .map_err(|e| error(&format!("{} buffer", name), e))?;
+        // the kernel reports twice what it granted
+        if report && granted < size.saturating_mul(2) {
+            eprintln!(
+                "{} : the kernel granted a {} B {} buffer of the {} B asked for; \
+                 raise net.core.{} or run with CAP_NET_ADMIN",
+                interface.name,
+                granted / 2,
+                name,
+                size,
+                limit
+            );
+        }
+    }
+    socket
+        .set_read_timeout(channel.read_timeout)
+        .map_err(|e| error("read timeout", e))?;
+    socket.attach(interface, channel.promiscuous, worker.fanout)?;
+
+    if report {
+        let receive = socket.buffer(Buffer::Receive);
+        let send = socket.buffer(Buffer::Send);
+        if let (Ok(receive), Ok(send)) = (receive, send) {
+            println!(
+                "{} : receive buffer {} B, send buffer {} B, as the kernel reports them",
+                interface.name, receive, send
+            );
+        }
+    }
+
+    Ok(socket)
+}
+
+fn read_socket(
+    worker: &Worker,
+    signal: &StopSignal,
+    counters: &CaptureCounters,
+    metrics: Option<&Metrics>,
+    deliver: &mut dyn FnMut(SystemTime, &[u8]) -> bool,
+) {
+    let interface = &worker.interface;
+    let socket = match open_socket(worker) {
+        Ok(socket) => socket,
+        Err(e) => {
+            println!("{} : {}", interface.name, e);
+            return;
+        }
+    };
+    let mut buffer = vec![0; MAX_FRAME];
+
+    let mut failures = 0;
+    let mut last_collected = Instant::now();
+    while !signal.is_set() {
+        if last_collected.elapsed() >= KERNEL_STATISTICS {
+            last_collected = Instant::now();
+            collect_kernel(interface, counters, metrics, socket.statistics());
+        }
+        match socket.receive(&mut buffer) {
+            Ok(length) => {
+                failures = 0;
+                if !deliver(SystemTime::now(), &buffer[..length]) {
+                    break;
+                }
+            }
+            // the read timeout passed, or a signal such as Ctrl-C arrived
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    ErrorKind::WouldBlock | ErrorKind::TimedOut | ErrorKind::Interrupted
+                ) => {}
+            Err(e) => {
+                read_failed(interface, counters, metrics, &e);
+                failures += 1;
+                if failures >= MAX_READ_FAILURES {
+                    break;
+                }
+            }
+        }
+    }
+    collect_kernel(interface, counters, metrics, socket.statistics());
+}
+
+fn read_ring(
+    worker: &Worker,
+    signal: &StopSignal,
+    counters: &CaptureCounters,
+    metrics: Option<&Metrics>,
+    deliver: &mut dyn FnMut(SystemTime, &[u8]) -> bool,
+) {
+    let (interface, channel) = (&worker.interface, &worker.channel);
+    let mut ring = match Ring::open(interface, &channel.ring, channel.promiscuous, worker.fanout) {
+        Ok(ring) => ring,
+        Err(e) => {
+            println!("{} : {}", interface.name, e);
+            return;
+        }
+    };
+    let mut failures = 0;
+    let mut last_collected = Instant::now();
+    while !signal.is_set() {
+        match ring.next_block(channel.read_timeout, deliver) {
+            Ok(true) => failures = 0,
+            Ok(false) => break,
+            Err(e) => {
+                read_failed(interface, counters, metrics, &e);
+                failures += 1;
+                if failures >= MAX_READ_FAILURES {
+                    break;
+                }
+            }
+        }
+        if last_collected.elapsed() >= KERNEL_STATISTICS {
+            last_collected = Instant::now();
+            collect_kernel(interface, counters, metrics, ring.statistics());
+        }
+    }
+    collect_kernel(interface, counters, metrics, ring.statistics());
+}
+
+/// Adds what the kernel counted for a capture socket to the summary and
+/// the metrics.
+fn collect_kernel(
+    interface: &NetworkInterface,
+    counters: &CaptureCounters,
+    metrics: Option<&Metrics>,
+    stats: std::io::Result<KernelStats>,
+) {
+    match stats {
+        Ok(stats) => {
+            counters.add_kernel(stats);
+            if let Some(metrics) = metrics {
+                metrics.record_kernel(&interface.name, stats);
+            }
+        }
+        Err(e) => eprintln!(
+            "cannot read the kernel statistics of {}: {}",
+            interface.name, e
+        ),
+    }
+}
+
+fn read_failed(
+    interface: &NetworkInterface,
+    counters: &CaptureCounters,
+    metrics: Option<&Metrics>,
+    e: &std::io::Error,
+) {
+    counters.dropped.fetch_add(1, Ordering::Relaxed);
+    if let Some(metrics) = metrics {
+        metrics.record_read_error(&interface.name);
+    }
+    eprintln!("An error occurred while reading: {}", e);
+}
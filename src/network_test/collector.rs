@@ -0,0 +1,891 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io::ErrorKind;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::network_test::datalink::EthernetIIFrame;
+use crate::network_test::flow::FlowKey;
+use crate::network_test::network::PacketType;
+use crate::network_test::transport::TransportSegment;
+
+/// UDP ports flow telemetry is usually sent to.
+pub const NETFLOW_PORTS: [u16; 3] = [2055, 9995, 9996];
+pub const IPFIX_PORT: u16 = 4739;
+pub const SFLOW_PORT: u16 = 6343;
+
+pub fn is_flow_port(port: u16) -> bool {
+    NETFLOW_PORTS.contains(&port) || port == IPFIX_PORT || port == SFLOW_PORT
+}
+
+/// The exporter address and UDP payload of a frame sent to a flow port.
+pub fn export_payload(frame: &EthernetIIFrame) -> Option<(IpAddr, &[u8])> {
+    match frame.get_network_packet() {
+        PacketType::IPv4(packet) => match packet.get_transport_segment() {
+            TransportSegment::UDP(segment) if is_flow_port(segment.get_destination_port()) => {
+                Some((IpAddr::V4(packet.get_source()), segment.get_data()))
+            }
+            _ => None,
+        },
+        PacketType::IPv6(packet) => {
+            let payload = packet.get_payload();
+            let port = payload
+                .get(2..4)
+                .map(|p| u16::from_be_bytes([p[0], p[1]]))?;
+            (packet.get_next_header() == 17 && is_flow_port(port)).then(|| {
+                (
+                    IpAddr::V6(packet.get_source()),
+                    &payload[8.min(payload.len())..],
+                )
+            })
+        }
+        _ => None,
+    }
+}
+
+/// One information element of a flow record.
+#[derive(Debug, Clone)]
+pub struct Field {
+    pub id: u16,
+    /// 0 for IANA elements
+    pub enterprise: u32,
+    pub value: Vec<u8>,
+}
+
+/// A decoded flow record: NetFlow/IPFIX data or an sFlow sample.
+#[derive(Debug, Clone, Default)]
+pub struct FlowData {
+    pub fields: Vec<Field>,
+}
+
+/// One decoded export packet.
+#[derive(Debug, Clone)]
+pub struct ExportPacket {
+    pub protocol: &'static str,
+    pub sequence: u32,
+    /// source id, observation domain or sFlow agent sub-id
+    pub domain: u32,
+    pub flows: Vec<FlowData>,
+    pub templates: usize,
+    /// sets or samples that could not be decoded, e.g. data before its template
+    pub notes: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+struct Template {
+    fields: Vec<(u16, u16, u32)>,
+}
+
+/// Decodes NetFlow v5/v9, IPFIX and sFlow v5, keeping the v9/IPFIX
+/// templates each exporter announced for the packets that follow.
+#[derive(Debug, Default)]
+pub struct FlowDecoder {
+    templates: HashMap<(IpAddr, u32, u16), Template>,
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Reader { data, position: 0 }
+    }
+
+    fn remaining(&self) -> usize {
+        self.data.len() - self.position
+    }
+
+    fn bytes(&mut self, length: usize) -> Result<&'a [u8], String> {
+        if self.remaining() < length {
+            return Err(format!(
+                "truncated at byte {}: wanted {} more, {} left",
+                self.position,
+                length,
+                self.remaining()
+            ));
+        }
+        let bytes = &self.data[self.position..self.position + length];
+        self.position += length;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        let b = self.bytes(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        let b = self.bytes(4)?;
+        Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+}
+
+impl FlowDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Decodes one UDP payload sent by `exporter`, guessing the protocol
+    /// from its version field.
+    pub fn decode(&mut self, exporter: IpAddr, data: &[u8]) -> Result<ExportPacket, String> {
+        let mut reader = Reader::new(data);
+
+        if data.len() >= 4 && u32::from_be_bytes([data[0], data[1], data[2], data[3]]) == 5 {
+            return decode_sflow(&mut reader);
+        }
+
+        match reader.u16()? {
+            5 => decode_v5(&mut reader),
+            9 => self.decode_v9(exporter, &mut reader),
+            10 => self.decode_ipfix(exporter, &mut reader),
+            other => Err(format!("not a flow export packet (version {})", other)),
+        }
+    }
+
+    fn decode_v9(&mut self, exporter: IpAddr, reader: &mut Reader) -> Result<ExportPacket, String> {
+        let _count = reader.u16()?;
+        let _uptime = reader.u32()?;
+        let _unix_secs = reader.u32()?;
+        let sequence = reader.u32()?;
+        let source_id = reader.u32()?;
+
+        let mut packet = ExportPacket::new("NetFlow v9", sequence, source_id);
+        while reader.remaining() >= 4 {
+            let id = reader.u16()?;
+            let length = reader.u16()? as usize;
+            if length < 4 {
+                return Err(format!("flowset {} has length {}", id, length));
+            }
+            let mut set = Reader::new(reader.bytes(length - 4)?);
+
+            match id {
+                0 => {
+                    while set.remaining() >= 4 {
+                        let template_id = set.u16()?;
+                        let count = set.u16()?;
+                        let fields = (0..count)
+                            .map(|_| Ok((set.u16()?, set.u16()?, 0)))
+                            .collect::<Result<_, String>>()?;
+                        self.learn(exporter, source_id, template_id, fields, &mut packet);
+                    }
+                }
+                1 => {
+                    while set.remaining() >= 6 {
+                        let template_id = set.u16()?;
+                        let scope_length = set.u16()? as usize;
+                        let option_length = set.u16()? as usize;
+                        let fields = (0..(scope_length + option_length) / 4)
+                            .map(|_| Ok((set.u16()?, set.u16()?, 0)))
+                            .collect::<Result<_, String>>()?;
+                        self.learn(exporter, source_id, template_id, fields, &mut packet);
+                    }
+                }
+                id if id >= 256 => {
+                    self.decode_data(exporter, source_id, id, &mut set, &mut packet)?
+                }
+                id => packet.notes.push(format!("reserved flowset id {}", id)),
+            }
+        }
+
+        Ok(packet)
+    }
+
+    fn decode_ipfix(
+        &mut self,
+        exporter: IpAddr,
+        reader: &mut Reader,
+    ) -> Result<ExportPacket, String> {
+        let length = reader.u16()? as usize;
+        let _export_time = reader.u32()?;
+        let sequence = reader.u32()?;
+        let domain = reader.u32()?;
+
+        let mut packet = ExportPacket::new("IPFIX", sequence, domain);
+        let end = length.min(reader.data.len());
+        while end.saturating_sub(reader.position) >= 4 {
+            let id = reader.u16()?;
+            let length = reader.u16()? as usize;
+            if length < 4 {
+                return Err(format!("set {} has length {}", id, length));
+            }
+            let mut set = Reader::new(reader.bytes(length - 4)?);
+
+            match id {
+                2 | 3 => {
+                    while set.remaining() >= 4 {
+                        let template_id = set.u16()?;
+                        let count = set.u16()?;
+                        if id == 3 {
+                            let _scope_count = set.u16()?;
+                        }
+                        let fields = (0..count)
+                            .map(|_| {
+                                let field = set.u16()?;
+                                let size = set.u16()?;
+                                // the enterprise bit adds an enterprise number
+                                let enterprise = if field & 0x8000 != 0 { set.u32()? } else { 0 };
+                                Ok((field & 0x7fff, size, enterprise))
+                            })
+                            .collect::<Result<_, String>>()?;
+                        self.learn(exporter, domain, template_id, fields, &mut packet);
+                    }
+                }
+                id if id >= 256 => self.decode_data(exporter, domain, id, &mut set, &mut packet)?,
+                id => packet.notes.push(format!("reserved set id {}", id)),
+            }
+        }
+
+        Ok(packet)
+    }
+
+    fn learn(
+        &mut self,
+        exporter: IpAddr,
+        domain: u32,
+        template_id: u16,
+        fields: Vec<(u16, u16, u32)>,
+        packet: &mut ExportPacket,
+    ) {
+        self.templates
+            .insert((exporter, domain, template_id), Template { fields });
+        packet.templates += 1;
+    }
+
+    fn decode_data(
+        &self,
+        exporter: IpAddr,
+        domain: u32,
+        template_id: u16,
+        set: &mut Reader,
+        packet: &mut ExportPacket,
+    ) -> Result<(), String> {
+        let Some(template) = self.templates.get(&(exporter, domain, template_id)) else {
+            packet.notes.push(format!(
+                "{} bytes for template {} which was not announced yet",
+                set.remaining(),
+                template_id
+            ));
+            return Ok(());
+        };
+
+        let minimum: usize = template
+            .fields
+            .iter()
+            .map(|(_, size, _)| if *size == 0xffff { 1 } else { *size as usize })
+            .sum();
+
+        // whatever is left after the last record is padding
+        while minimum > 0 && set.remaining() >= minimum {
+            let mut flow = FlowData::default();
+            for &(id, size, enterprise) in &template.fields {
+                let size = match size {
+                    // IPFIX variable length: one byte, or 255 and two bytes
+                    0xffff => match set.u8()? {
+                        255 => set.u16()? as usize,
+                        short => short as usize,
+                    },
+                    size => size as usize,
+                };
+                flow.fields.push(Field {
+                    id,
+                    enterprise,
+                    value: set.bytes(size)?.to_vec(),
+                });
+            }
+            packet.flows.push(flow);
+        }
+
+        Ok(())
+    }
+}
+
+impl ExportPacket {
+    fn new(protocol: &'static str, sequence: u32, domain: u32) -> Self {
+        ExportPacket {
+            protocol,
+            sequence,
+            domain,
+            flows: vec![],
+            templates: 0,
+            notes: vec![],
+        }
+    }
+}
+
+fn decode_v5(reader: &mut Reader) -> Result<ExportPacket, String> {
+    let count = reader.u16()?;
+    let _uptime = reader.u32()?;
+    let _unix_secs = reader.u32()?;
+    let _unix_nsecs = reader.u32()?;
+    let sequence = reader.u32()?;
+    let engine = reader.u16()?;
+    let _sampling = reader.u16()?;
+
+    let mut packet = ExportPacket::new("NetFlow v5", sequence, engine as u32);
+    // field layout of a v5 record, as IPFIX element ids and sizes
+    let layout: [(u16, usize); 20] = [
+        (8, 4),
+        (12, 4),
+        (15, 4),
+        (10, 2),
+        (14, 2),
+        (2, 4),
+        (1, 4),
+        (22, 4),
+        (21, 4),
+        (7, 2),
+        (11, 2),
+        (0, 1),
+        (6, 1),
+        (4, 1),
+        (5, 1),
+        (16, 2),
+        (17, 2),
+        (9, 1),
+        (13, 1),
+        (0, 2),
+    ];
+
+    for _ in 0..count {
+        let mut flow = FlowData::default();
+        for (id, size) in layout {
+            let value = reader.bytes(size)?.to_vec();
+            if id != 0 {
+                flow.fields.push(Field {
+                    id,
+                    enterprise: 0,
+                    value,
+                });
+            }
+        }
+        packet.flows.push(flow);
+    }
+
+    Ok(packet)
+}
+
+fn decode_sflow(reader: &mut Reader) -> Result<ExportPacket, String> {
+    let _version = reader.u32()?;
+    match reader.u32()? {
+        1 => {
+            reader.bytes(4)?;
+        }
+        2 => {
+            reader.bytes(16)?;
+        }
+        other => return Err(format!("unknown sFlow agent address type {}", other)),
+    }
+    let sub_agent = reader.u32()?;
+    let sequence = reader.u32()?;
+    let _uptime = reader.u32()?;
+    let samples = reader.u32()?;
+
+    let mut packet = ExportPacket::new("sFlow v5", sequence, sub_agent);
+    for _ in 0..samples {
+        let format = reader.u32()?;
+        let length = reader.u32()? as usize;
+        let mut sample = Reader::new(reader.bytes(length)?);
+
+        match format {
+            // flow sample and expanded flow sample
+            1 | 3 => {
+                let _sequence = sample.u32()?;
+                sample.bytes(if format == 1 { 4 } else { 8 })?;
+                let sampling_rate = sample.u32()?;
+                let _pool = sample.u32()?;
+                let _drops = sample.u32()?;
+                sample.bytes(if format == 1 { 8 } else { 16 })?;
+                let records = sample.u32()?;
+
+                for _ in 0..records {
+                    let record_format = sample.u32()?;
+                    let record_length = sample.u32()? as usize;
+                    let record = sample.bytes(record_length)?;
+
+                    match record_format {
+                        1 => packet.flows.push(sampled_header(record, sampling_rate)?),
+                        other => packet.notes.push(format!(
+                            "flow record format {} ({} bytes)",
+                            other, record_length
+                        )),
+                    }
+                }
+            }
+            // counter sample and expanded counter sample
+            2 | 4 => {
+                let _sequence = sample.u32()?;
+                sample.bytes(if format == 2 { 4 } else { 8 })?;
+                let records = sample.u32()?;
+                for _ in 0..records {
+                    let record_format = sample.u32()?;
+                    let record_length = sample.u32()? as usize;
+                    let mut record = Reader::new(sample.bytes(record_length)?);
+
+                    if record_format == 1 && record_length >= 32 {
+                        let index = record.u32()?;
+                        record.bytes(20)?;
+                        let octets = record.bytes(8)?;
+                        packet.notes.push(format!(
+                            "counters of interface {}: {} octets in",
+                            index,
+                            u64::from_be_bytes(octets.try_into().unwrap())
+                        ));
+                    } else {
+                        packet.notes.push(format!(
+                            "counter record format {} ({} bytes)",
+                            record_format, record_length
+                        ));
+                    }
+                }
+            }
+            other => packet
+                .notes
+                .push(format!("sample format {} ({} bytes)", other, length)),
+        }
+    }
+
+    Ok(packet)
+}
+
+/// Turns an sFlow raw packet header record into a flow record by
+/// dissecting the sampled frame.
+fn sampled_header(record: &[u8], sampling_rate: u32) -> Result<FlowData, String> {
+    let mut reader = Reader::new(record);
+    let protocol = reader.u32()?;
+    let frame_length = reader.u32()?;
+    let _stripped = reader.u32()?;
+    let header_length = reader.u32()? as usize;
+    let header = reader.bytes(header_length)?;
+
+    let mut flow = FlowData::default();
+    let mut push = |id: u16, value: Vec<u8>| {
+        flow.fields.push(Field {
+            id,
+            enterprise: 0,
+            value,
+        })
+    };
+    // one sampled packet stands for `sampling_rate` packets
+    push(2, (sampling_rate as u64).to_be_bytes().to_vec());
+    push(
+        1,
+        (frame_length as u64 * sampling_rate as u64)
+            .to_be_bytes()
+            .to_vec(),
+    );
+
+    // header protocol 1 is Ethernet
+    let key = (protocol == 1)
+        .then(|| EthernetIIFrame::new(header))
+        .flatten()
+        .as_ref()
+        .and_then(FlowKey::from_frame);
+    if let Some(key) = key {
+        let (source, destination) = match (key.source, key.destination) {
+            (IpAddr::V4(s), IpAddr::V4(d)) => ((8, s.octets().to_vec()), (12, d.octets().to_vec())),
+            (s, d) => ((27, ip_bytes(s)), (28, ip_bytes(d))),
+        };
+        push(source.0, source.1);
+        push(destination.0, destination.1);
+        push(7, key.source_port.to_be_bytes().to_vec());
+        push(11, key.destination_port.to_be_bytes().to_vec());
+        push(4, vec![key.protocol]);
+    }
+
+    Ok(flow)
+}
+
+fn ip_bytes(address: IpAddr) -> Vec<u8> {
+    match address {
+        IpAddr::V4(address) => address.octets().to_vec(),
+        IpAddr::V6(address) => address.octets().to_vec(),
+    }
+}
+
+/// IANA names of the information elements this tool knows.
+fn field_name(id: u16) -> Option<&'static str> {
+    Some(match id {
+        1 => "octetDeltaCount",
+        2 => "packetDeltaCount",
+        4 => "protocolIdentifier",
+        5 => "ipClassOfService",
+        6 => "tcpControlBits",
+        7 => "sourceTransportPort",
+        8 => "sourceIPv4Address",
+        9 => "sourceIPv4PrefixLength",
+        10 => "ingressInterface",
+        11 => "destinationTransportPort",
+        12 => "destinationIPv4Address",
+        13 => "destinationIPv4PrefixLength",
+        14 => "egressInterface",
+        15 => "ipNextHopIPv4Address",
+        16 => "bgpSourceAsNumber",
+        17 => "bgpDestinationAsNumber",
+        21 => "flowEndSysUpTime",
+        22 => "flowStartSysUpTime",
+        27 => "sourceIPv6Address",
+        28 => "destinationIPv6Address",
+        32 => "icmpTypeCodeIPv4",
+        61 => "flowDirection",
+        136 => "flowEndReason",
+        150 => "flowStartSeconds",
+        151 => "flowEndSeconds",
+        152 => "flowStartMilliseconds",
+        153 => "flowEndMilliseconds",
+        _ => return None,
+    })
+}
+
+impl Field {
+    fn as_u64(&self) -> Option<u64> {
+        (self.value.len() <= 8).then(|| self.value.iter().fold(0, |n, &b| (n << 8) | b as u64))
+    }
+
+    fn as_address(&self) -> Option<IpAddr> {
+        match self.value.len() {
+            4 => Some(IpAddr::V4(Ipv4Addr::from(
+                <[u8; 4]>::try_from(self.value.as_slice()).ok()?,
+            ))),
+            16 => Some(IpAddr::V6(Ipv6Addr::from(
+                <[u8; 16]>::try_from(self.value.as_slice()).ok()?,
+            ))),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Field {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.enterprise, field_name(self.id)) {
+            (0, Some(name)) => write!(f, "{}=", name)?,
+            (0, None) => write!(f, "ie{}=", self.id)?,
+            (enterprise, _) => write!(f, "ie{}/{}=", enterprise, self.id)?,
+        }
+
+        match (self.enterprise, self.id) {
+            (0, 8 | 12 | 15 | 27 | 28) if self.as_address().is_some() => {
+                write!(f, "{}", self.as_address().unwrap())
+            }
+            _ => match self.as_u64() {
+                Some(value) => write!(f, "{}", value),
+                None => self.value.iter().try_for_each(|b| write!(f, "{:02x}", b)),
+            },
+        }
+    }
+}
+
+impl FlowData {
+    fn get(&self, id: u16) -> Option<&Field> {
+        self.fields.iter().find(|f| f.enterprise == 0 && f.id == id)
+    }
+
+    fn address(&self, v4: u16, v6: u16) -> Option<IpAddr> {
+        self.get(v4)
+            .or_else(|| self.get(v6))
+            .and_then(Field::as_address)
+    }
+
+    fn number(&self, id: u16) -> u64 {
+        self.get(id).and_then(Field::as_u64).unwrap_or(0)
+    }
+}
+
+/// `{}` prints `proto src → dst pkts bytes`, `{:#}` every field.
+impl fmt::Display for FlowData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if f.alternate() {
+            for field in &self.fields {
+                writeln!(f, "    {}", field)?;
+            }
+            return Ok(());
+        }
+
+        let source = self.address(8, 27);
+        let destination = self.address(12, 28);
+        if let (Some(source), Some(destination)) = (source, destination) {
+            let key = FlowKey {
+                protocol: self.number(4) as u8,
+                source,
+                source_port: self.number(7) as u16,
+                destination,
+                destination_port: self.number(11) as u16,
+            };
+            write!(f, "{} ", key)?;
+        }
+
+        write!(
+            f,
+            "packets={} bytes={} ({} fields)",
+            self.number(2),
+            self.number(1),
+            self.fields.len()
+        )
+    }
+}
+
+/// `{}` prints one line about the packet, `{:#}` adds every flow.
+impl fmt::Display for ExportPacket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} seq={} domain={} flows={} templates={}",
+            self.protocol,
+            self.sequence,
+            self.domain,
+            self.flows.len(),
+            self.templates
+        )?;
+
+        if f.alternate() {
+            writeln!(f)?;
+            for flow in &self.flows {
+                writeln!(f, "  {}", flow)?;
+                write!(f, "{:#}", flow)?;
+            }
+            for note in &self.notes {
+                writeln!(f, "  ({})", note)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Listens for flow exports on `listen` and prints what they carry until
+/// Ctrl-C, SIGTERM or `deadline`.
+pub fn collect(
+    listen: SocketAddr,
+    verbose: bool,
+    deadline: Option<Instant>,
+) -> std::io::Result<()> {
+    let socket = UdpSocket::bind(listen)?;
+    socket.set_read_timeout(Some(Duration::from_millis(200)))?;
+    println!("collecting flow exports on {}", socket.local_addr()?);
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let handler_stop = Arc::clone(&stop);
+    if let Err(e) = ctrlc::set_handler(move || handler_stop.store(true, Ordering::Relaxed)) {
+        eprintln!("cannot install the signal handler: {}", e);
+    }
+
+    let mut decoder = FlowDecoder::new();
+    let mut buffer = vec![0; 65535];
+    let (mut packets, mut flows) = (0, 0);
+
+    while !stop.load(Ordering::Relaxed) && deadline.is_none_or(|d| Instant::now() < d) {
+        let (length, exporter) = match socket.recv_from(&mut buffer) {
+            Ok(received) => received,
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => continue,
+            Err(e) => return Err(e),
+        };
+
+        packets += 1;
+        match decoder.decode(exporter.ip(), &buffer[..length]) {
+            Ok(packet) => {
+                flows += packet.flows.len();
+                if verbose {
+                    print!("{}: {:#}", exporter, packet);
+                } else {
+                    println!("{}: {}", exporter, packet);
+                    for flow in &packet.flows {
+                        println!("  {}", flow);
+                    }
+                }
+            }
+            Err(e) => println!("{} bytes from {}: {}", length, exporter, e),
+        }
+    }
+
+    println!("\n{} export packets, {} flow records", packets, flows);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network_test::util::frames::{udp, Ipv4};
+
+    const EXPORTER: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+
+    fn words(out: &mut Vec<u8>, words: &[u32]) {
+        for word in words {
+            out.extend_from_slice(&word.to_be_bytes());
+        }
+    }
+
+    /// A v9 or IPFIX set with its id and length.
+    fn set(id: u16, body: &[u8]) -> Vec<u8> {
+        let mut set = id.to_be_bytes().to_vec();
+        set.extend_from_slice(&(4 + body.len() as u16).to_be_bytes());
+        set.extend_from_slice(body);
+        set
+    }
+
+    fn v9(sequence: u32, flowsets: &[Vec<u8>]) -> Vec<u8> {
+        let mut packet = vec![0, 9, 0, flowsets.len() as u8];
+        words(&mut packet, &[0, 0, sequence, 7]);
+        packet.extend(flowsets.concat());
+        packet
+    }
+
+    fn ipfix(sequence: u32, sets: &[Vec<u8>]) -> Vec<u8> {
+        let sets = sets.concat();
+        let mut packet = vec![0, 10];
+        packet.extend_from_slice(&(16 + sets.len() as u16).to_be_bytes());
+        words(&mut packet, &[0, sequence, 3]);
+        packet.extend(sets);
+        packet
+    }
+
+    #[test]
+    fn netflow_v5() {
+        let mut packet = vec![0, 5, 0, 1];
+        words(&mut packet, &[0, 0, 0, 42, 0x0102_0000]);
+        packet.extend_from_slice(&[10, 0, 0, 1, 10, 0, 0, 2, 0, 0, 0, 0, 0, 1, 0, 2]);
+        words(&mut packet, &[3, 300, 1000, 2000]);
+        packet.extend_from_slice(&[0x14, 0xe9, 0, 53, 0, 0, 17, 0, 0, 0, 0, 0, 24, 24, 0, 0]);
+
+        let decoded = FlowDecoder::new().decode(EXPORTER, &packet).unwrap();
+        assert_eq!(
+            decoded.to_string(),
+            "NetFlow v5 seq=42 domain=258 flows=1 templates=0"
+        );
+        assert_eq!(
+            decoded.flows[0].to_string(),
+            "UDP 10.0.0.1:5353 → 10.0.0.2:53 packets=3 bytes=300 (18 fields)"
+        );
+
+        let error = FlowDecoder::new()
+            .decode(EXPORTER, &packet[..70])
+            .unwrap_err();
+        assert_eq!(error, "truncated at byte 70: wanted 2 more, 0 left");
+    }
+
+    #[test]
+    fn netflow_v9_templates_carry_over_to_later_packets() {
+        let template = set(
+            0,
+            &[
+                1, 0, 0, 5, 0, 8, 0, 4, 0, 12, 0, 4, 0, 4, 0, 1, 0, 2, 0, 4, 0, 1, 0, 4,
+            ],
+        );
+        let mut record = vec![10, 0, 0, 1, 10, 0, 0, 2, 6];
+        words(&mut record, &[2, 120]);
+        record.extend_from_slice(&[0; 3]);
+        let data = v9(2, &[set(256, &record)]);
+
+        let mut decoder = FlowDecoder::new();
+        // data before its template is noted, not guessed at
+        let early = decoder.decode(EXPORTER, &data).unwrap();
+        assert!(early.flows.is_empty());
+        assert_eq!(
+            early.notes,
+            ["20 bytes for template 256 which was not announced yet"]
+        );
+
+        let announced = decoder.decode(EXPORTER, &v9(1, &[template])).unwrap();
+        assert_eq!(
+            announced.to_string(),
+            "NetFlow v9 seq=1 domain=7 flows=0 templates=1"
+        );
+
+        let decoded = decoder.decode(EXPORTER, &data).unwrap();
+        assert_eq!(decoded.flows.len(), 1, "the padding is no second record");
+        assert_eq!(
+            decoded.flows[0].to_string(),
+            "TCP 10.0.0.1:0 → 10.0.0.2:0 packets=2 bytes=120 (5 fields)"
+        );
+
+        // templates belong to the exporter that sent them
+        let other = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2));
+        assert!(decoder.decode(other, &data).unwrap().flows.is_empty());
+    }
+
+    #[test]
+    fn ipfix_enterprise_and_variable_length_fields() {
+        // sourceIPv4Address, enterprise 9 element 1, variable-length interfaceName
+        let template = set(
+            2,
+            &[
+                1, 44, 0, 3, 0, 8, 0, 4, 0x80, 1, 0, 2, 0, 0, 0, 9, 0, 82, 0xff, 0xff,
+            ],
+        );
+        let data = set(300, &[10, 0, 0, 1, 0, 7, 4, b'e', b't', b'h', b'0']);
+
+        let decoded = FlowDecoder::new()
+            .decode(EXPORTER, &ipfix(9, &[template, data]))
+            .unwrap();
+        assert_eq!(
+            decoded.to_string(),
+            "IPFIX seq=9 domain=3 flows=1 templates=1"
+        );
+        let fields: Vec<String> = decoded.flows[0]
+            .fields
+            .iter()
+            .map(|f| f.to_string())
+            .collect();
+        assert_eq!(
+            fields,
+            ["sourceIPv4Address=10.0.0.1", "ie9/1=7", "ie82=1702127664"]
+        );
+        assert_eq!(decoded.flows[0].fields[2].value, b"eth0");
+    }
+
+    #[test]
+    fn sflow_flow_and_counter_samples() {
+        // UDP from the client's port 5353 to the server's port 53
+        let frame = Ipv4::between(true, 17).frame(&udp(5353, 53, &[]));
+        let mut record = Vec::new();
+        words(&mut record, &[1, 60, 4, frame.len() as u32]);
+        record.extend_from_slice(&frame);
+
+        let mut flow_sample = Vec::new();
+        words(
+            &mut flow_sample,
+            &[1, 3, 100, 1000, 0, 1, 2, 1, 1, record.len() as u32],
+        );
+        flow_sample.extend(record);
+
+        let mut counter_sample = Vec::new();
+        words(
+            &mut counter_sample,
+            &[1, 3, 1, 1, 88, 3, 6, 0, 1000, 1, 1, 0, 5000],
+        );
+        counter_sample.extend_from_slice(&[0; 88 - 32]);
+
+        let mut packet = Vec::new();
+        words(&mut packet, &[5, 1, 0xc000_0201, 0, 11, 0, 2]);
+        words(&mut packet, &[1, flow_sample.len() as u32]);
+        packet.extend(flow_sample);
+        words(&mut packet, &[2, counter_sample.len() as u32]);
+        packet.extend(counter_sample);
+
+        let decoded = FlowDecoder::new().decode(EXPORTER, &packet).unwrap();
+        assert_eq!(
+            decoded.to_string(),
+            "sFlow v5 seq=11 domain=0 flows=1 templates=0"
+        );
+        assert_eq!(
+            decoded.flows[0].to_string(),
+            "UDP 10.0.0.1:5353 → 10.0.0.2:53 packets=100 bytes=6000 (7 fields)"
+        );
+        assert_eq!(decoded.notes, ["counters of interface 3: 5000 octets in"]);
+    }
+
+    #[test]
+    fn not_a_flow_export() {
+        assert_eq!(
+            FlowDecoder::new()
+                .decode(EXPORTER, &[0, 4, 0, 0])
+                .unwrap_err(),
+            "not a flow export packet (version 4)"
+        );
+    }
+}
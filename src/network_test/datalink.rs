@@ -1,6 +1,8 @@
 use pnet::packet::{ethernet, PrimitiveValues};
 
+use crate::network_test::hexdump::Region;
 use crate::network_test::{network, util};
+use std::fmt;
 
 #[derive(Debug)]
 pub struct EthernetIIFrame {
@@ -8,11 +10,16 @@ pub struct EthernetIIFrame {
     sender: pnet::util::MacAddr,
     ether_type: ethernet::EtherType,
     payload: network::PacketType,
+    length: usize,
 }
 
 impl EthernetIIFrame {
     pub fn new(byte_array: &[u8]) -> Option<Self> {
-        let mut iter = byte_array.iter().cloned();
+        if byte_array.len() < 14 {
+            return None;
+        }
+
+        let mut iter = byte_array.iter().copied();
 
         let destination = util::mapping_mac_addr(iter.by_ref().take(6).collect());
         let sender = util::mapping_mac_addr(iter.by_ref().take(6).collect());
@@ -24,13 +31,20 @@ impl EthernetIIFrame {
         let packet = match ether_type.to_primitive_values().0 {
             x if x < 0x0600 => network::PacketType::Length(x),
             0x0600 => network::PacketType::XNSIDP,
-            0x0800 => network::PacketType::IPv4(network::IPv4Packet::new(&bytes).unwrap()),
+            0x0800 => network::PacketType::IPv4(network::IPv4Packet::new(&bytes)?),
             0x0805 => network::PacketType::X25PLP,
-            0x0806 => network::PacketType::ARP(network::ARPPacket::new(&bytes).unwrap()),
-            0x8035 => network::PacketType::RARP,
+            // a cut-off ARP packet still lists the frame, by its EtherType
+            0x0806 => match network::ARPPacket::new(&bytes) {
+                Some(arp) => network::PacketType::ARP(arp),
+                None => network::PacketType::UNDEFINED(0x0806),
+            },
+            0x8035 => match network::ARPPacket::new(&bytes) {
+                Some(rarp) => network::PacketType::RARP(rarp),
+                None => network::PacketType::UNDEFINED(0x8035),
+            },
             0x8137 => network::PacketType::NetwareIPX,
             0x8191 => network::PacketType::NetBIOS,
-            0x86DD => network::PacketType::IPv6(network::IPv6Packet::new(&bytes).unwrap()),
+            0x86DD => network::PacketType::IPv6(network::IPv6Packet::new(&bytes)?),
             other => network::PacketType::UNDEFINED(other),
         };
 
@@ -39,6 +53,7 @@ impl EthernetIIFrame {
             sender,
             ether_type,
             payload: packet,
+            length: byte_array.len(),
             // frame_check_sequence,
         })
     }
@@ -47,7 +62,61 @@ impl EthernetIIFrame {
         self.sender
     }
 
+    pub fn get_destination(&self) -> pnet::util::MacAddr {
+        self.destination
+    }
+
+    pub fn get_ether_type(&self) -> u16 {
+        self.ether_type.to_primitive_values().0
+    }
+
     pub fn get_network_packet(&self) -> &network::PacketType {
         &self.payload
     }
-}
\ No newline at end of file
+
+    pub fn get_length(&self) -> usize {
+        self.length
+    }
+
+    /// Byte ranges of every dissected layer, in frame offsets.
+    pub fn regions(&self) -> Vec<Region> {
+        let mut regions = vec![Region::new("Ethernet II", 0, 14)];
+        self.payload.regions(14, &mut regions);
+        regions
+    }
+}
+
+/// `{}` prints a one-line summary (`src → dst protocol length info`),
+/// `{:#}` prints every dissected layer with its fields and frame offsets.
+impl fmt::Display for EthernetIIFrame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if f.alternate() {
+            writeln!(f, "Ethernet II @0x0000, {} bytes", self.length)?;
+            util::write_field(f, 0, "destination", self.destination)?;
+            util::write_field(f, 6, "sender", self.sender)?;
+            util::write_field(
+                f,
+                12,
+                "ether_type",
+                format!("0x{:04x}", self.ether_type.to_primitive_values().0),
+            )?;
+            return self.payload.write_tree(f, 14);
+        }
+
+        let source = self.payload.source().unwrap_or(self.sender.to_string());
+        let destination = self
+            .payload
+            .destination()
+            .unwrap_or(self.destination.to_string());
+
+        write!(
+            f,
+            "{} → {} {} {} {}",
+            source,
+            destination,
+            self.payload.protocol_name(),
+            self.length,
+            self.payload
+        )
+    }
+}
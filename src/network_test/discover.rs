@@ -0,0 +1,465 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::io::ErrorKind;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use pnet::datalink::DataLinkSender;
+use pnet::ipnetwork::IpNetwork;
+use pnet::util::MacAddr;
+
+use crate::network_test::capture;
+use crate::network_test::datalink::EthernetIIFrame;
+use crate::network_test::network::{ARPKind, PacketType};
+use crate::network_test::util;
+
+/// Where OUI databases are usually installed, tried in order.
+const OUI_PATHS: [&str; 4] = [
+    "/usr/share/wireshark/manuf",
+    "/usr/share/ieee-data/oui.txt",
+    "/usr/share/misc/oui.txt",
+    "/usr/share/nmap/nmap-mac-prefixes",
+];
+
+/// Vendors worth knowing in a lab even without an OUI database.
+const KNOWN_OUIS: [([u8; 3], &str); 10] = [
+    ([0x00, 0x05, 0x69], "VMware"),
+    ([0x00, 0x0c, 0x29], "VMware"),
+    ([0x00, 0x50, 0x56], "VMware"),
+    ([0x08, 0x00, 0x27], "VirtualBox"),
+    ([0x52, 0x54, 0x00], "QEMU/KVM"),
+    ([0x00, 0x16, 0x3e], "Xen"),
+    ([0x00, 0x15, 0x5d], "Microsoft Hyper-V"),
+    ([0xb8, 0x27, 0xeb], "Raspberry Pi"),
+    ([0xdc, 0xa6, 0x32], "Raspberry Pi"),
+    ([0xe4, 0x5f, 0x01], "Raspberry Pi"),
+];
+
+/// Time between two ARP requests, so a sweep does not flood the segment.
+const SEND_INTERVAL: Duration = Duration::from_micros(500);
+
+/// Everything `discover` needs besides the interface name.
+#[derive(Debug, Clone)]
+pub struct DiscoverOptions {
+    /// how long to keep listening after the last request
+    pub wait: Duration,
+    /// refuse to sweep subnets with more addresses than this
+    pub max_hosts: u32,
+    /// OUI database to name vendors with, instead of the usual locations
+    pub oui: Option<PathBuf>,
+    pub ipv6: bool,
+}
+
+/// One host found on the segment.
+#[derive(Debug, Clone)]
+struct Host {
+    mac: MacAddr,
+    /// what revealed it: ARP, NDP or ICMPv6 echo
+    method: &'static str,
+}
+
+/// MAC address prefixes and the vendors they were assigned to.
+#[derive(Debug, Default)]
+pub struct Vendors {
+    prefixes: HashMap<[u8; 3], String>,
+}
+
+impl Vendors {
+    /// Reads `path` or, without one, the first OUI database installed.
+    /// Wireshark `manuf`, IEEE `oui.txt` and nmap `nmap-mac-prefixes` are understood.
+    pub fn load(path: Option<&Path>) -> Result<Self, String> {
+        let text = match path {
+            Some(path) => fs::read_to_string(path)
+                .map_err(|e| format!("cannot read {}: {}", path.display(), e))?,
+            None => match OUI_PATHS
+                .iter()
+                .find_map(|path| fs::read_to_string(path).ok())
+            {
+                Some(text) => text,
+                None => return Ok(Vendors::default()),
+            },
+        };
+
+        let mut prefixes = HashMap::new();
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (prefix, rest) =
+                line.split_at(line.find(char::is_whitespace).unwrap_or(line.len()));
+            // longer prefixes such as `00:1B:C5:00:00:00/36` are too fine for this table
+            if prefix.contains('/') {
+                continue;
+            }
+            let digits: String = prefix.chars().filter(char::is_ascii_hexdigit).collect();
+            if digits.len() != 6 || !matches!(prefix.len(), 6 | 8) {
+                continue;
+            }
+            let Ok(number) = u32::from_str_radix(&digits, 16) else {
+                continue;
+            };
+            let [_, a, b, c] = number.to_be_bytes();
+
+            // manuf has a short and a long name separated by a tab, oui.txt a `(hex)` marker
+            let rest = rest.trim().trim_start_matches("(hex)").trim();
+            let name = rest.split('\t').next_back().unwrap_or(rest).trim();
+            if !name.is_empty() {
+                prefixes.insert([a, b, c], name.to_string());
+            }
+        }
+
+        Ok(Vendors { prefixes })
+    }
+
+    pub fn lookup(&self, mac: MacAddr) -> String {
+        let prefix = [mac.0, mac.1, mac.2];
+        if let Some(name) = self.prefixes.get(&prefix) {
+            return name.clone();
+        }
+        if let Some((_, name)) = KNOWN_OUIS.iter().find(|(oui, _)| *oui == prefix) {
+            return name.to_string();
+        }
+        if mac.0 & 0x02 != 0 {
+            String::from("(locally administered)")
+        } else {
+            String::from("(unknown)")
+        }
+    }
+}
+
+/// Sweeps the IPv4 subnets of `name` with ARP requests and finds IPv6
+/// neighbours with an all-nodes echo followed by neighbour solicitations,
+/// then prints every host that answered.
+pub fn discover(name: &str, options: DiscoverOptions) -> Result<(), String> {
+    let interface = pnet::datalink::interfaces()
+        .into_iter()
+        .find(|x| x.name == name)
+        .ok_or(format!("no interface is named {}", name))?;
+    let mac = interface
+        .mac
+        .filter(|mac| *mac != MacAddr::zero())
+        .ok_or(format!("{} has no MAC address to send from", name))?;
+    let vendors = Vendors::load(options.oui.as_deref())?;
+
+    let networks: Vec<IpNetwork> = interface
+        .ips
+        .iter()
+        .copied()
+        .filter(|network| !network.ip().is_loopback())
+        .filter(|network| options.ipv6 || network.is_ipv4())
+        .collect();
+
+    let mut targets: Vec<(Ipv4Addr, Ipv4Addr)> = vec![];
+    for network in &networks {
+        let IpNetwork::V4(network) = network else {
+            continue;
+        };
+        if network.size() > options.max_hosts {
+            return Err(format!(
+                "{} has {} addresses, more than --max-hosts {}",
+                network,
+                network.size(),
+                options.max_hosts
+            ));
+        }
+        let own = network.ip();
+        targets.extend(
+            network
+                .iter()
+                // /31 and /32 have no network and broadcast addresses
+                .filter(|ip| {
+                    network.prefix() >= 31
+                        || (*ip != network.network() && *ip != network.broadcast())
+                })
+                .filter(|ip| *ip != own)
+                .map(|ip| (own, ip)),
+        );
+    }
+    if targets.is_empty() && !networks.iter().any(IpNetwork::is_ipv6) {
+        return Err(format!("{} has no address to scan from", name));
+    }
+
+    let (tx, mut rx) = capture::open_channel(&interface)?;
+    println!(
+        "discovering hosts on {} ({}), {} IPv4 addresses{}",
+        name,
+        mac,
+        targets.len(),
+        if options.ipv6 {
+            " and IPv6 neighbours"
+        } else {
+            ""
+        }
+    );
+
+    // receive on another thread so sending never waits for a read to time out
+    let stop = Arc::new(AtomicBool::new(false));
+    let (frames, received) = mpsc::channel::<Vec<u8>>();
+    let receiver_stop = Arc::clone(&stop);
+    let receiver = thread::spawn(move || {
+        while !receiver_stop.load(Ordering::Relaxed) {
+            match rx.next() {
+                Ok(data) => {
+                    if frames.send(data.to_vec()).is_err() {
+                        break;
+                    }
+                }
+                Err(e) if e.kind() == ErrorKind::TimedOut => {}
+                Err(e) => {
+                    eprintln!("An error occurred while reading: {}", e);
+                    break;
+                }
+            }
+        }
+    });
+
+    let started = Instant::now();
+    let mut scan = Scan {
+        tx,
+        mac,
+        hosts: BTreeMap::new(),
+        solicited: HashSet::new(),
+    };
+    let result = scan.sweep(&networks, targets, &received, options.wait);
+    stop.store(true, Ordering::Relaxed);
+    let _ = receiver.join();
+    result?;
+    let hosts = scan.hosts;
+
+    println!(
+        "\n{:<40} {:<18} {:<12} vendor",
+        "IP address", "MAC address", "found by"
+    );
+    for (ip, host) in &hosts {
+        println!(
+            "{:<40} {:<18} {:<12} {}",
+            ip.to_string(),
+            host.mac.to_string(),
+            host.method,
+            vendors.lookup(host.mac)
+        );
+    }
+    println!(
+        "\n{} hosts found on {} in {:.3}s",
+        hosts.len(),
+        name,
+        started.elapsed().as_secs_f64()
+    );
+
+    Ok(())
+}
+
+/// The state of one `discover` run.
+struct Scan {
+    tx: Box<dyn DataLinkSender>,
+    mac: MacAddr,
+    hosts: BTreeMap<IpAddr, Host>,
+    /// IPv6 neighbours already asked for their MAC
+    solicited: HashSet<Ipv6Addr>,
+}
+
+impl Scan {
+    fn send(&mut self, frame: &[u8]) -> Result<(), String> {
+        match self.tx.send_to(frame, None) {
+            Some(Ok(())) => Ok(()),
+            Some(Err(e)) => Err(format!("cannot send a frame: {}", e)),
+            None => Err(String::from("cannot send a frame: the channel has no room")),
+        }
+    }
+
+    /// Sends every request, then keeps handling answers for `wait`.
+    fn sweep(
+        &mut self,
+        networks: &[IpNetwork],
+        targets: Vec<(Ipv4Addr, Ipv4Addr)>,
+        received: &Receiver<Vec<u8>>,
+        wait: Duration,
+    ) -> Result<(), String> {
+        for network in networks {
+            if let IpNetwork::V6(network) = network {
+                self.send(&echo_all_nodes(self.mac, network.ip()))?;
+            }
+        }
+        for (own, target) in targets {
+            self.send(&arp_request(self.mac, own, target))?;
+            while let Ok(data) = received.try_recv() {
+                self.handle(&data)?;
+            }
+            thread::sleep(SEND_INTERVAL);
+        }
+
+        let deadline = Instant::now() + wait;
+        while let Some(left) = deadline.checked_duration_since(Instant::now()) {
+            match received.recv_timeout(left) {
+                Ok(data) => self.handle(&data)?,
+                Err(RecvTimeoutError::Timeout | RecvTimeoutError::Disconnected) => break,
+            }
+        }
+        Ok(())
+    }
+
+    /// Learns the hosts an ARP or NDP answer in `data` reveals.
+    fn handle(&mut self, data: &[u8]) -> Result<(), String> {
+        let Some(frame) = EthernetIIFrame::new(data) else {
+            return Ok(());
+        };
+        if frame.get_source() == self.mac {
+            return Ok(());
+        }
+
+        match frame.get_network_packet() {
+            PacketType::ARP(packet) => {
+                let (Some(ip), Some(sender)) = (packet.get_sender_ip(), packet.get_sender_mac())
+                else {
+                    return Ok(());
+                };
+                if packet.kind() != ARPKind::Probe && !ip.is_unspecified() {
+                    self.hosts.insert(
+                        IpAddr::V4(ip),
+                        Host {
+                            mac: sender,
+                            method: "ARP",
+                        },
+                    );
+                }
+            }
+            PacketType::IPv6(packet) if packet.get_next_header() == 58 => {
+                let payload = packet.get_payload();
+                match payload.first() {
+                    // an echo reply shows the host; its NDP answer confirms the MAC
+                    Some(129) => {
+                        let ip = packet.get_source();
+                        self.hosts.entry(IpAddr::V6(ip)).or_insert(Host {
+                            mac: frame.get_source(),
+                            method: "ICMPv6 echo",
+                        });
+                        if self.solicited.insert(ip) {
+                            let solicitation =
+                                neighbour_solicitation(self.mac, packet.get_destination(), ip);
+                            self.send(&solicitation)?;
+                        }
+                    }
+                    Some(136) => {
+                        if let Some((ip, advertised)) = neighbour_advertisement(payload) {
+                            self.hosts.insert(
+                                IpAddr::V6(ip),
+                                Host {
+                                    mac: advertised.unwrap_or(frame.get_source()),
+                                    method: "NDP",
+                                },
+                            );
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+fn ethernet_header(destination: MacAddr, source: MacAddr, ether_type: u16) -> Vec<u8> {
+    let mut header = Vec::with_capacity(14);
+    header.extend(destination.octets());
+    header.extend(source.octets());
+    header.extend(ether_type.to_be_bytes());
+    header
+}
+
+fn arp_request(mac: MacAddr, own: Ipv4Addr, target: Ipv4Addr) -> Vec<u8> {
+    let mut frame = ethernet_header(MacAddr::broadcast(), mac, 0x0806);
+    frame.extend([0, 1, 0x08, 0x00, 6, 4, 0, 1]);
+    frame.extend(mac.octets());
+    frame.extend(own.octets());
+    frame.extend([0; 6]);
+    frame.extend(target.octets());
+    // pad to the shortest Ethernet frame
+    frame.resize(60, 0);
+    frame
+}
+
+/// An IPv6 packet carrying `icmp`, whose checksum is filled in here.
+fn icmpv6_frame(
+    destination_mac: MacAddr,
+    mac: MacAddr,
+    source: Ipv6Addr,
+    destination: Ipv6Addr,
+    hop_limit: u8,
+    mut icmp: Vec<u8>,
+) -> Vec<u8> {
+    let pseudo = util::pseudo_header(IpAddr::V6(source), IpAddr::V6(destination), 58, icmp.len());
+    let checksum = util::internet_checksum(&[pseudo, icmp.clone()].concat());
+    icmp[2..4].copy_from_slice(&checksum.to_be_bytes());
+
+    let mut frame = ethernet_header(destination_mac, mac, 0x86dd);
+    frame.extend([0x60, 0, 0, 0]);
+    frame.extend((icmp.len() as u16).to_be_bytes());
+    frame.extend([58, hop_limit]);
+    frame.extend(source.octets());
+    frame.extend(destination.octets());
+    frame.extend(icmp);
+    frame
+}
+
+/// The MAC a multicast IPv6 address maps to (RFC 2464).
+fn multicast_mac(address: Ipv6Addr) -> MacAddr {
+    let octets = address.octets();
+    MacAddr::new(0x33, 0x33, octets[12], octets[13], octets[14], octets[15])
+}
+
+/// An echo request to ff02::1, which every IPv6 node on the link answers.
+fn echo_all_nodes(mac: MacAddr, source: Ipv6Addr) -> Vec<u8> {
+    let all_nodes = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 1);
+    let identifier = std::process::id() as u16;
+    let mut icmp = vec![128, 0, 0, 0];
+    icmp.extend(identifier.to_be_bytes());
+    icmp.extend([0, 0]);
+    icmp.extend(b"discover");
+    icmpv6_frame(multicast_mac(all_nodes), mac, source, all_nodes, 1, icmp)
+}
+
+/// Asks `target` for its MAC through its solicited-node multicast group.
+fn neighbour_solicitation(mac: MacAddr, source: Ipv6Addr, target: Ipv6Addr) -> Vec<u8> {
+    let octets = target.octets();
+    let group = Ipv6Addr::from(
+        u128::from(Ipv6Addr::new(0xff02, 0, 0, 0, 0, 1, 0xff00, 0))
+            | (u128::from_be_bytes(octets) & 0xff_ffff),
+    );
+
+    let mut icmp = vec![135, 0, 0, 0, 0, 0, 0, 0];
+    icmp.extend(octets);
+    // source link-layer address option
+    icmp.extend([1, 1]);
+    icmp.extend(mac.octets());
+    icmpv6_frame(multicast_mac(group), mac, source, group, 255, icmp)
+}
+
+/// The target address of a neighbour advertisement and its link-layer address option.
+fn neighbour_advertisement(icmp: &[u8]) -> Option<(Ipv6Addr, Option<MacAddr>)> {
+    let target = Ipv6Addr::from(<[u8; 16]>::try_from(icmp.get(8..24)?).ok()?);
+
+    let mut options = icmp.get(24..)?;
+    let mut advertised = None;
+    while options.len() >= 8 {
+        let length = options[1] as usize * 8;
+        if length == 0 || length > options.len() {
+            break;
+        }
+        if options[0] == 2 && length == 8 {
+            let [_, _, a, b, c, d, e, f] = options[..8] else {
+                break;
+            };
+            advertised = Some(MacAddr::new(a, b, c, d, e, f));
+        }
+        options = &options[length..];
+    }
+
+    Some((target, advertised))
+}
@@ -0,0 +1,396 @@
+use std::io;
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::network_test::flow::{DirectionStats, FlowKey, FlowRecord};
+
+/// Keeps export packets under a typical path MTU.
+const MAX_PACKET_SIZE: usize = 1400;
+/// Templates are repeated this often so collectors that start late catch up.
+const TEMPLATE_INTERVAL: Duration = Duration::from_secs(60);
+
+const TEMPLATE_IPV4: u16 = 256;
+const TEMPLATE_IPV6: u16 = 257;
+
+/// Information elements shared by NetFlow v9 and IPFIX.
+const OCTET_DELTA_COUNT: u16 = 1;
+const PACKET_DELTA_COUNT: u16 = 2;
+const PROTOCOL_IDENTIFIER: u16 = 4;
+const TCP_CONTROL_BITS: u16 = 6;
+const SOURCE_TRANSPORT_PORT: u16 = 7;
+const SOURCE_IPV4_ADDRESS: u16 = 8;
+const DESTINATION_TRANSPORT_PORT: u16 = 11;
+const DESTINATION_IPV4_ADDRESS: u16 = 12;
+const FLOW_END_SYS_UP_TIME: u16 = 21;
+const FLOW_START_SYS_UP_TIME: u16 = 22;
+const SOURCE_IPV6_ADDRESS: u16 = 27;
+const DESTINATION_IPV6_ADDRESS: u16 = 28;
+const FLOW_START_MILLISECONDS: u16 = 152;
+const FLOW_END_MILLISECONDS: u16 = 153;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ExportFormat {
+    /// NetFlow v5, IPv4 flows only
+    V5,
+    /// NetFlow v9 with templates
+    V9,
+    /// IPFIX (NetFlow v10) with templates
+    Ipfix,
+}
+
+/// One direction of a flow, which is what NetFlow records describe.
+struct Record {
+    key: FlowKey,
+    stats: DirectionStats,
+    first_seen: SystemTime,
+    last_seen: SystemTime,
+}
+
+/// Sends finished flows from a `FlowTable` to a collector over UDP.
+pub struct FlowExporter {
+    socket: UdpSocket,
+    collector: SocketAddr,
+    format: ExportFormat,
+    /// when the exporter started, which the sysUptime fields count from
+    boot: SystemTime,
+    /// v9 counts packets, IPFIX and v5 count flow records
+    sequence: u32,
+    source_id: u32,
+    last_template: Option<Instant>,
+}
+
+impl FlowExporter {
+    pub fn new(collector: SocketAddr, format: ExportFormat) -> io::Result<Self> {
+        let bind: SocketAddr = match collector {
+            SocketAddr::V4(_) => "0.0.0.0:0".parse().unwrap(),
+            SocketAddr::V6(_) => "[::]:0".parse().unwrap(),
+        };
+
+        Ok(FlowExporter {
+            socket: UdpSocket::bind(bind)?,
+            collector,
+            format,
+            boot: SystemTime::now(),
+            sequence: 0,
+            source_id: std::process::id(),
+            last_template: None,
+        })
+    }
+
+    /// Exports both directions of every flow; returns how many records went out.
+    pub fn export(&mut self, flows: &[FlowRecord]) -> io::Result<usize> {
+        let records: Vec<Record> = flows.iter().flat_map(split).collect();
+
+        match self.format {
+            ExportFormat::V5 => self.export_v5(&records),
+            ExportFormat::V9 | ExportFormat::Ipfix => self.export_templated(&records),
+        }
+    }
+
+    fn uptime(&self, time: SystemTime) -> u32 {
+        time.duration_since(self.boot)
+            .unwrap_or_default()
+            .as_millis() as u32
+    }
+
+    fn export_v5(&mut self, records: &[Record]) -> io::Result<usize> {
+        let records: Vec<&Record> = records
+            .iter()
+            .filter(|r| r.key.source.is_ipv4() && r.key.destination.is_ipv4())
+            .collect();
+
+        for chunk in records.chunks(30) {
+            let now = SystemTime::now();
+            let since_epoch = now.duration_since(UNIX_EPOCH).unwrap_or_default();
+
+            let mut packet = vec![];
+            put_u16(&mut packet, 5);
+            put_u16(&mut packet, chunk.len() as u16);
+            put_u32(&mut packet, self.uptime(now));
+            put_u32(&mut packet, since_epoch.as_secs() as u32);
+            put_u32(&mut packet, since_epoch.subsec_nanos());
+            put_u32(&mut packet, self.sequence);
+            // engine type, engine id, sampling interval
+            packet.extend_from_slice(&[0, 0, 0, 0]);
+
+            for record in chunk {
+                packet.extend_from_slice(&address_bytes(record.key.source));
+                packet.extend_from_slice(&address_bytes(record.key.destination));
+                // next hop, input and output interface
+                packet.extend_from_slice(&[0; 8]);
+                put_u32(&mut packet, record.stats.packets as u32);
+                put_u32(&mut packet, record.stats.bytes as u32);
+                put_u32(&mut packet, self.uptime(record.first_seen));
+                put_u32(&mut packet, self.uptime(record.last_seen));
+                put_u16(&mut packet, record.key.source_port);
+                put_u16(&mut packet, record.key.destination_port);
+                packet.extend_from_slice(&[0, record.stats.tcp_flags, record.key.protocol, 0]);
+                // AS numbers, masks and padding
+                packet.extend_from_slice(&[0; 8]);
+            }
+
+            self.socket.send_to(&packet, self.collector)?;
+            self.sequence = self.sequence.wrapping_add(chunk.len() as u32);
+        }
+
+        Ok(records.len())
+    }
+
+    fn export_templated(&mut self, records: &[Record]) -> io::Result<usize> {
+        let ipfix = self.format == ExportFormat::Ipfix;
+        let mut sets: Vec<(u16, Vec<u8>, usize)> = vec![];
+
+        let template_due = self
+            .last_template
+            .is_none_or(|sent| sent.elapsed() >= TEMPLATE_INTERVAL);
+        if template_due {
+            self.last_template = Some(Instant::now());
+            sets.push((if ipfix { 2 } else { 0 }, self.templates(), 2));
+        }
+
+        for (template, ipv4) in [(TEMPLATE_IPV4, true), (TEMPLATE_IPV6, false)] {
+            let mut body = vec![];
+            let mut count = 0;
+
+            for record in records.iter().filter(|r| r.key.source.is_ipv4() == ipv4) {
+                let mut encoded = vec![];
+                self.encode(record, &mut encoded);
+
+                if body.len() + encoded.len() + 40 > MAX_PACKET_SIZE {
+                    sets.push((template, std::mem::take(&mut body), count));
+                    count = 0;
+                }
+                body.extend_from_slice(&encoded);
+                count += 1;
+            }
+
+            if count > 0 {
+                sets.push((template, body, count));
+            }
+        }
+
+        // pack the sets into as few packets as fit
+        let mut pending: Vec<(u16, Vec<u8>, usize)> = vec![];
+        let mut size = 0;
+        for set in sets {
+            if size + set.1.len() + 4 > MAX_PACKET_SIZE - 20 && !pending.is_empty() {
+                self.send_sets(&std::mem::take(&mut pending))?;
+                size = 0;
+            }
+            size += set.1.len() + 4;
+            pending.push(set);
+        }
+        if !pending.is_empty() {
+            self.send_sets(&pending)?;
+        }
+
+        Ok(records.len())
+    }
+
+    fn templates(&self) -> Vec<u8> {
+        let ipfix = self.format == ExportFormat::Ipfix;
+        let (start, end, time_size) = if ipfix {
+            (FLOW_START_MILLISECONDS, FLOW_END_MILLISECONDS, 8)
+        } else {
+            (FLOW_START_SYS_UP_TIME, FLOW_END_SYS_UP_TIME, 4)
+        };
+
+        let mut body = vec![];
+        for (template, source, destination, address_size) in [
+            (
+                TEMPLATE_IPV4,
+                SOURCE_IPV4_ADDRESS,
+                DESTINATION_IPV4_ADDRESS,
+                4,
+            ),
+            (
+                TEMPLATE_IPV6,
+                SOURCE_IPV6_ADDRESS,
+                DESTINATION_IPV6_ADDRESS,
+                16,
+            ),
+        ] {
+            let fields = [
+                (source, address_size),
+                (destination, address_size),
+                (SOURCE_TRANSPORT_PORT, 2),
+                (DESTINATION_TRANSPORT_PORT, 2),
+                (PROTOCOL_IDENTIFIER, 1),
+                (TCP_CONTROL_BITS, 1),
+                (PACKET_DELTA_COUNT, 8),
+                (OCTET_DELTA_COUNT, 8),
+                (start, time_size),
+                (end, time_size),
+            ];
+
+            put_u16(&mut body, template);
+            put_u16(&mut body, fields.len() as u16);
+            for (id, size) in fields {
+                put_u16(&mut body, id);
+                put_u16(&mut body, size);
+            }
+        }
+        body
+    }
+
+    /// Encodes one record in the field order of `templates`.
+    fn encode(&self, record: &Record, out: &mut Vec<u8>) {
+        out.extend_from_slice(&address_bytes(record.key.source));
+        out.extend_from_slice(&address_bytes(record.key.destination));
+        put_u16(out, record.key.source_port);
+        put_u16(out, record.key.destination_port);
+        out.push(record.key.protocol);
+        out.push(record.stats.tcp_flags);
+        out.extend_from_slice(&record.stats.packets.to_be_bytes());
+        out.extend_from_slice(&record.stats.bytes.to_be_bytes());
+
+        if self.format == ExportFormat::Ipfix {
+            for time in [record.first_seen, record.last_seen] {
+                let millis = time
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_millis();
+                out.extend_from_slice(&(millis as u64).to_be_bytes());
+            }
+        } else {
+            put_u32(out, self.uptime(record.first_seen));
+            put_u32(out, self.uptime(record.last_seen));
+        }
+    }
+
+    fn send_sets(&mut self, sets: &[(u16, Vec<u8>, usize)]) -> io::Result<()> {
+        let now = SystemTime::now();
+        let export_time = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() as u32;
+        let ipfix = self.format == ExportFormat::Ipfix;
+
+        let mut body = vec![];
+        for (id, set, _) in sets {
+            let padding = (4 - set.len() % 4) % 4;
+            put_u16(&mut body, *id);
+            put_u16(&mut body, (set.len() + padding + 4) as u16);
+            body.extend_from_slice(set);
+            body.extend(std::iter::repeat_n(0, padding));
+        }
+
+        let records: usize = sets.iter().map(|(_, _, count)| count).sum();
+        let data_records: usize = sets
+            .iter()
+            .filter(|(id, _, _)| *id >= 256)
+            .map(|(_, _, count)| count)
+            .sum();
+
+        let mut packet = vec![];
+        if ipfix {
+            put_u16(&mut packet, 10);
+            put_u16(&mut packet, (body.len() + 16) as u16);
+            put_u32(&mut packet, export_time);
+            put_u32(&mut packet, self.sequence);
+            put_u32(&mut packet, self.source_id);
+            self.sequence = self.sequence.wrapping_add(data_records as u32);
+        } else {
+            put_u16(&mut packet, 9);
+            put_u16(&mut packet, records as u16);
+            put_u32(&mut packet, self.uptime(now));
+            put_u32(&mut packet, export_time);
+            put_u32(&mut packet, self.sequence);
+            put_u32(&mut packet, self.source_id);
+            self.sequence = self.sequence.wrapping_add(1);
+        }
+        packet.extend_from_slice(&body);
+
+        self.socket.send_to(&packet, self.collector)?;
+        Ok(())
+    }
+}
+
+/// A bidirectional flow becomes one record per direction that saw packets.
+fn split(flow: &FlowRecord) -> Vec<Record> {
+    let mut records = vec![Record {
+        key: flow.key,
+        stats: flow.forward,
+        first_seen: flow.first_seen,
+        last_seen: flow.last_seen,
+    }];
+
+    if flow.reverse.packets > 0 {
+        records.push(Record {
+            key: flow.key.reversed(),
+            stats: flow.reverse,
+            first_seen: flow.first_seen,
+            last_seen: flow.last_seen,
+        });
+    }
+
+    records
+}
+
+fn address_bytes(address: IpAddr) -> Vec<u8> {
+    match address {
+        IpAddr::V4(address) => address.octets().to_vec(),
+        IpAddr::V6(address) => address.octets().to_vec(),
+    }
+}
+
+fn put_u16(out: &mut Vec<u8>, value: u16) {
+    out.extend_from_slice(&value.to_be_bytes());
+}
+
+fn put_u32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&value.to_be_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network_test::collector::{FlowData, FlowDecoder};
+    use crate::network_test::flow::{FlowTable, FlowTimeouts, TCP_ACK, TCP_RST, TCP_SYN};
+    use crate::network_test::util::frames::Tcp;
+
+    fn tcp_flags(flow: &FlowData) -> Option<u8> {
+        let field = flow
+            .fields
+            .iter()
+            .find(|field| field.id == TCP_CONTROL_BITS)?;
+        field.value.first().copied()
+    }
+
+    #[test]
+    fn export_to_a_local_collector() {
+        let collector = UdpSocket::bind("127.0.0.1:0").unwrap();
+        collector
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut buffer = [0; 65_535];
+
+        for (format, protocol) in [
+            (ExportFormat::V5, "NetFlow v5"),
+            (ExportFormat::V9, "NetFlow v9"),
+            (ExportFormat::Ipfix, "IPFIX"),
+        ] {
+            let mut table = FlowTable::new(FlowTimeouts::default());
+            table.update(&Tcp::new(true, TCP_SYN).frame(), SystemTime::now());
+            table.update(
+                &Tcp::new(false, TCP_RST | TCP_ACK).frame(),
+                SystemTime::now(),
+            );
+
+            let mut exporter = FlowExporter::new(collector.local_addr().unwrap(), format).unwrap();
+            assert_eq!(exporter.export(&table.drain()).unwrap(), 2);
+
+            let (length, exporter) = collector.recv_from(&mut buffer).unwrap();
+            let packet = FlowDecoder::new()
+                .decode(exporter.ip(), &buffer[..length])
+                .unwrap();
+            assert_eq!(packet.protocol, protocol);
+            assert!(packet.notes.is_empty(), "{:?}", packet.notes);
+
+            let flows: Vec<String> = packet.flows.iter().map(|f| f.to_string()).collect();
+            assert_eq!(flows.len(), 2);
+            assert!(flows[0].starts_with("TCP 10.0.0.1:1234 → 10.0.0.2:80 packets=1 bytes=54"));
+            assert!(flows[1].starts_with("TCP 10.0.0.2:80 → 10.0.0.1:1234 packets=1 bytes=54"));
+            // each direction reports only the flags it sent
+            assert_eq!(tcp_flags(&packet.flows[0]), Some(TCP_SYN));
+            assert_eq!(tcp_flags(&packet.flows[1]), Some(TCP_RST | TCP_ACK));
+        }
+    }
+}
@@ -0,0 +1,641 @@
+use std::collections::BTreeSet;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use crate::network_test::datalink::EthernetIIFrame;
+use crate::network_test::flow::{TCP_ACK, TCP_FIN, TCP_PSH, TCP_RST, TCP_SYN, TCP_URG};
+use crate::network_test::network::{Ecn, PacketType};
+use crate::network_test::transport::{TCPSegment, TcpOption, TransportSegment};
+
+/// Routers between the host and us; a TTL further below its initial value
+/// than this does not come from that initial value.
+const MAX_DISTANCE: u8 = 35;
+/// What IPv4 and IPv6 put in front of the TCP payload a MSS describes.
+const IPV4_OVERHEAD: u16 = 40;
+const IPV6_OVERHEAD: u16 = 60;
+
+/// The operating system a signature names, from a p0f label such as
+/// `s:unix:Linux:3.11 and newer`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Label {
+    /// `g` labels match a family rather than one release
+    pub generic: bool,
+    pub class: String,
+    pub name: String,
+    pub flavor: String,
+}
+
+impl Label {
+    fn parse(text: &str) -> Option<Self> {
+        let mut parts = text.splitn(4, ':');
+        let generic = match parts.next()?.trim() {
+            "s" => false,
+            "g" => true,
+            _ => return None,
+        };
+        Some(Label {
+            generic,
+            class: parts.next()?.trim().to_string(),
+            name: parts.next()?.trim().to_string(),
+            flavor: parts.next().unwrap_or("").trim().to_string(),
+        })
+    }
+}
+
+impl fmt::Display for Label {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)?;
+        if !self.flavor.is_empty() {
+            write!(f, " {}", self.flavor)?;
+        }
+        if self.generic {
+            write!(f, " (generic)")?;
+        }
+        Ok(())
+    }
+}
+
+/// How a signature constrains the window size.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Window {
+    Any,
+    Exact(u16),
+    /// a multiple of the MSS the packet announces
+    Mss(u16),
+    /// a multiple of the MTU that MSS implies
+    Mtu(u16),
+    /// any multiple of this
+    Modulo(u16),
+}
+
+/// One `sig` line of a `[tcp:request]` or `[tcp:response]` section, in the
+/// p0f 3 layout `ver:ittl:olen:mss:wsize,scale:olayout:quirks:pclass`.
+#[derive(Debug, Clone)]
+struct TcpSignature {
+    label: Label,
+    response: bool,
+    /// 4 or 6, `None` for either
+    version: Option<u8>,
+    ttl: u8,
+    /// the TTL may have been rewritten on the way, so do not trust it
+    bad_ttl: bool,
+    options_length: usize,
+    mss: Option<u16>,
+    window: Window,
+    scale: Option<u8>,
+    layout: Vec<String>,
+    quirks: BTreeSet<String>,
+    /// whether the SYN carries data, `None` for either
+    payload: Option<bool>,
+}
+
+impl TcpSignature {
+    fn parse(label: Label, response: bool, text: &str) -> Result<Self, String> {
+        let fields: Vec<&str> = text.split(':').map(str::trim).collect();
+        let [version, ttl, options_length, mss, window, layout, quirks, payload] = fields[..]
+        else {
+            return Err(format!("expected 8 fields, found {}", fields.len()));
+        };
+        let number = |field: &str, what: &str| {
+            field
+                .parse::<u16>()
+                .map_err(|_| format!("bad {} '{}'", what, field))
+        };
+        let any = |field: &str, what: &str| match field {
+            "*" => Ok(None),
+            _ => number(field, what).map(Some),
+        };
+
+        let (window, scale) = window
+            .split_once(',')
+            .ok_or(format!("window '{}' has no scale", window))?;
+        let window = if window == "*" {
+            Window::Any
+        } else if let Some(factor) = window.strip_prefix("mss*") {
+            Window::Mss(number(factor, "window")?)
+        } else if let Some(factor) = window.strip_prefix("mtu*") {
+            Window::Mtu(number(factor, "window")?)
+        } else if let Some(modulo) = window.strip_prefix('%') {
+            Window::Modulo(number(modulo, "window")?.max(1))
+        } else {
+            Window::Exact(number(window, "window")?)
+        };
+
+        let bad_ttl = ttl.ends_with('-');
+        let digits: String = ttl.chars().take_while(char::is_ascii_digit).collect();
+
+        Ok(TcpSignature {
+            label,
+            response,
+            version: match version {
+                "*" => None,
+                "4" => Some(4),
+                "6" => Some(6),
+                _ => return Err(format!("bad version '{}'", version)),
+            },
+            ttl: number(&digits, "TTL")?.min(255) as u8,
+            bad_ttl,
+            options_length: number(options_length, "options length")? as usize,
+            mss: any(mss, "MSS")?,
+            window,
+            scale: any(scale, "scale")?.map(|scale| scale as u8),
+            layout: split_list(layout),
+            quirks: split_list(quirks).into_iter().collect(),
+            payload: match payload {
+                "*" => None,
+                "0" => Some(false),
+                "+" => Some(true),
+                _ => return Err(format!("bad payload class '{}'", payload)),
+            },
+        })
+    }
+
+    /// The hops the packet crossed if it matches this signature.
+    fn matches(&self, syn: &Syn) -> Option<u8> {
+        if self.response != syn.response
+            || self.version.is_some_and(|version| version != syn.version)
+            || self.options_length != syn.options_length
+            || self.mss.is_some_and(|mss| Some(mss) != syn.mss)
+            || self.scale.is_some_and(|scale| scale != syn.scale)
+            || self.payload.is_some_and(|payload| payload != syn.payload)
+            || self.layout != syn.layout
+            || self.quirks != syn.quirks
+        {
+            return None;
+        }
+
+        let window = syn.window;
+        let window_matches = match self.window {
+            Window::Any => true,
+            Window::Exact(size) => window == size,
+            Window::Mss(factor) => syn
+                .mss
+                .is_some_and(|mss| window as u32 == mss as u32 * factor as u32),
+            Window::Mtu(factor) => syn
+                .mtu()
+                .is_some_and(|mtu| window as u32 == mtu as u32 * factor as u32),
+            Window::Modulo(modulo) => window.is_multiple_of(modulo),
+        };
+        if !window_matches {
+            return None;
+        }
+
+        match self.ttl.checked_sub(syn.ttl) {
+            Some(distance) if self.bad_ttl || distance <= MAX_DISTANCE => Some(distance),
+            _ => None,
+        }
+    }
+}
+
+fn split_list(text: &str) -> Vec<String> {
+    text.split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// What a SYN or SYN-ACK tells about the stack that sent it, in the terms
+/// of a p0f signature.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Syn {
+    response: bool,
+    version: u8,
+    ttl: u8,
+    options_length: usize,
+    mss: Option<u16>,
+    window: u16,
+    scale: u8,
+    layout: Vec<String>,
+    quirks: BTreeSet<String>,
+    payload: bool,
+}
+
+impl Syn {
+    fn from_frame(frame: &EthernetIIFrame) -> Option<Self> {
+        let mut quirks = BTreeSet::new();
+        // an IPv4 segment is parsed along with its packet, an IPv6 one here
+        let parsed;
+        let (version, ttl, options_length, segment, ecn) = match frame.get_network_packet() {
+            PacketType::IPv4(packet) => {
+                let TransportSegment::TCP(segment) = packet.get_transport_segment() else {
+                    return None;
+                };
+                let flags = packet.get_flags();
+                let id = packet.get_identification();
+                for (set, quirk) in [
+                    (flags.dont_fragment, "df"),
+                    (flags.dont_fragment && id != 0, "id+"),
+                    (!flags.dont_fragment && id == 0, "id-"),
+                    (flags.reserved, "0+"),
+                ] {
+                    if set {
+                        quirks.insert(quirk.to_string());
+                    }
+                }
+                (
+                    4,
+                    packet.get_ttl(),
+                    packet.get_header_length() - 20,
+                    segment,
+                    packet.get_ecn(),
+                )
+            }
+            PacketType::IPv6(packet) if packet.get_next_header() == 6 => {
+                parsed = TCPSegment::new(packet.get_payload())?;
+                if packet.get_flow_label() != 0 {
+                    quirks.insert(String::from("flow"));
+                }
+                (6, packet.get_hop_limit(), 0, &parsed, packet.get_ecn())
+            }
+            _ => return None,
+        };
+
+        let flags = segment.get_flags();
+        if flags & TCP_SYN == 0 || flags & (TCP_FIN | TCP_RST) != 0 {
+            return None;
+        }
+        let response = flags & TCP_ACK != 0;
+
+        let (mut layout, mut mss, mut scale) = (vec![], None, 0);
+        let mut malformed = false;
+        let raw = segment.get_option_bytes();
+        let mut offset = 0;
+        for option in segment.get_options() {
+            let kind = raw.get(offset).copied().unwrap_or(0);
+            offset += match option {
+                TcpOption::EndOfList | TcpOption::NoOperation => 1,
+                _ => raw.get(offset + 1).copied().unwrap_or(1).max(1) as usize,
+            };
+            let item = match option {
+                TcpOption::EndOfList => {
+                    let padding = &raw[offset.min(raw.len())..];
+                    if padding.iter().any(|&byte| byte != 0) {
+                        quirks.insert(String::from("opt+"));
+                    }
+                    layout.push(format!("eol+{}", padding.len()));
+                    break;
+                }
+                TcpOption::NoOperation => String::from("nop"),
+                TcpOption::MaximumSegmentSize(size) => {
+                    mss = Some(*size);
+                    String::from("mss")
+                }
+                TcpOption::WindowScale(shift) => {
+                    scale = *shift;
+                    if *shift > 14 {
+                        quirks.insert(String::from("exws"));
+                    }
+                    String::from("ws")
+                }
+                TcpOption::SackPermitted => String::from("sok"),
+                TcpOption::Sack(_) => String::from("sack"),
+                TcpOption::Timestamps { value, echo_reply } => {
+                    if *value == 0 {
+                        quirks.insert(String::from("ts1-"));
+                    }
+                    if *echo_reply != 0 && !response {
+                        quirks.insert(String::from("ts2+"));
+                    }
+                    String::from("ts")
+                }
+                TcpOption::Malformed { .. } => {
+                    malformed = true;
+                    break;
+                }
+                _ => format!("?{}", kind),
+            };
+            layout.push(item);
+        }
+        if malformed {
+            quirks.insert(String::from("bad"));
+        }
+
+        let ack_number = segment.get_acknowledgement_number();
+        for (set, quirk) in [
+            // ECE and CWR are the two high flag bits
+            (ecn != Ecn::NotEct || flags & 0xc0 != 0, "ecn"),
+            (segment.get_sequence_number() == 0, "seq-"),
+            (flags & TCP_ACK == 0 && ack_number != 0, "ack+"),
+            (flags & TCP_ACK != 0 && ack_number == 0, "ack-"),
+            (
+                flags & TCP_URG == 0 && segment.get_urgent_pointer() != 0,
+                "uptr+",
+            ),
+            (flags & TCP_URG != 0, "urgf+"),
+            (flags & TCP_PSH != 0, "pushf+"),
+        ] {
+            if set {
+                quirks.insert(quirk.to_string());
+            }
+        }
+
+        Some(Syn {
+            response,
+            version,
+            ttl,
+            options_length,
+            mss,
+            window: segment.get_window_size(),
+            scale,
+            layout,
+            quirks,
+            payload: !segment.get_data().is_empty(),
+        })
+    }
+
+    /// The MTU the MSS implies, the link type's tell.
+    fn mtu(&self) -> Option<u16> {
+        let overhead = match self.version {
+            4 => IPV4_OVERHEAD,
+            _ => IPV6_OVERHEAD,
+        };
+        self.mss.map(|mss| mss.saturating_add(overhead))
+    }
+
+    /// The initial TTL most stacks start from, the power of two (or 255)
+    /// at or above what arrived.
+    fn initial_ttl(&self) -> u8 {
+        match self.ttl {
+            0..=32 => 32,
+            33..=64 => 64,
+            65..=128 => 128,
+            _ => 255,
+        }
+    }
+}
+
+impl fmt::Display for Syn {
+    /// The packet in signature syntax, ready to paste into a database.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let optional = |value: Option<u16>| match value {
+            Some(value) => value.to_string(),
+            None => String::from("*"),
+        };
+        let quirks: Vec<&str> = self.quirks.iter().map(String::as_str).collect();
+        write!(
+            f,
+            "{}:{}:{}:{}:{},{}:{}:{}:{}",
+            self.version,
+            self.initial_ttl(),
+            self.options_length,
+            optional(self.mss),
+            self.window,
+            self.scale,
+            self.layout.join(","),
+            quirks.join(","),
+            if self.payload { "+" } else { "0" }
+        )
+    }
+}
+
+/// The verdict on one SYN or SYN-ACK.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fingerprint {
+    /// whether it answered a SYN rather than opened a connection
+    pub response: bool,
+    pub os: Option<Label>,
+    /// routers crossed, from the TTL the match started with
+    pub distance: u8,
+    /// the link type the MTU implies, from the `[mtu]` section
+    pub link: Option<String>,
+    /// the packet in signature syntax, for adding it when nothing matched
+    pub signature: String,
+}
+
+impl fmt::Display for Fingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.os {
+            Some(os) => write!(f, "{}, {} hops", os, self.distance)?,
+            None => write!(f, "unknown ({})", self.signature)?,
+        }
+        write!(
+            f,
+            " from a {}",
+            if self.response { "SYN-ACK" } else { "SYN" }
+        )
+    }
+}
+
+/// A p0f 3 style database: `[tcp:request]` and `[tcp:response]` sections
+/// of `label` lines each followed by their `sig` lines, and an `[mtu]`
+/// section naming link types. Other sections, such as p0f's HTTP ones,
+/// are skipped.
+#[derive(Debug, Clone, Default)]
+pub struct Signatures {
+    tcp: Vec<TcpSignature>,
+    mtu: Vec<(u16, String)>,
+}
+
+impl Signatures {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+        Self::parse(&text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    fn parse(text: &str) -> Result<Self, String> {
+        let mut signatures = Signatures::default();
+        let mut section = "";
+        let mut label: Option<String> = None;
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with(';') || line.starts_with('#') {
+                continue;
+            }
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                section = match name {
+                    "tcp:request" | "tcp:response" | "mtu" => name,
+                    _ => "",
+                };
+                label = None;
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                return Err(format!("line {}: expected 'key = value'", number + 1));
+            };
+            let (key, value) = (key.trim(), value.trim());
+
+            match (section, key) {
+                ("", _) => {}
+                (_, "label") => label = Some(value.to_string()),
+                ("mtu", "sig") => {
+                    let mtu = value
+                        .parse()
+                        .map_err(|_| format!("line {}: bad MTU '{}'", number + 1, value))?;
+                    let Some(label) = &label else {
+                        return Err(format!("line {}: sig before any label", number + 1));
+                    };
+                    signatures.mtu.push((mtu, label.clone()));
+                }
+                (_, "sig") => {
+                    let text = label
+                        .as_deref()
+                        .ok_or(format!("line {}: sig before any label", number + 1))?;
+                    let parsed = Label::parse(text).ok_or(format!(
+                        "line {}: bad label '{}'",
+                        number + 1,
+                        text
+                    ))?;
+                    let signature = TcpSignature::parse(parsed, section == "tcp:response", value)
+                        .map_err(|e| format!("line {}: {}", number + 1, e))?;
+                    signatures.tcp.push(signature);
+                }
+                // sys, ua_os and the like only matter to HTTP matching
+                _ => {}
+            }
+        }
+
+        Ok(signatures)
+    }
+
+    /// Fingerprints a frame if it is a SYN or a SYN-ACK. A specific
+    /// signature wins over a generic one, then the first in the file.
+    pub fn classify(&self, frame: &EthernetIIFrame) -> Option<Fingerprint> {
+        let syn = Syn::from_frame(frame)?;
+        let mut best: Option<(&TcpSignature, u8)> = None;
+        for signature in &self.tcp {
+            let Some(distance) = signature.matches(&syn) else {
+                continue;
+            };
+            let better = match best {
+                None => true,
+                Some((current, _)) => current.label.generic && !signature.label.generic,
+            };
+            if better {
+                best = Some((signature, distance));
+            }
+        }
+
+        let link = syn.mtu().and_then(|mtu| {
+            self.mtu
+                .iter()
+                .find(|(known, _)| *known == mtu)
+                .map(|(_, name)| name.clone())
+        });
+
+        Some(Fingerprint {
+            response: syn.response,
+            os: best.map(|(signature, _)| signature.label.clone()),
+            distance: best.map_or(syn.initial_ttl() - syn.ttl, |(_, distance)| distance),
+            link,
+            signature: syn.to_string(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network_test::util::frames::{Ipv4, Tcp};
+
+    const DATABASE: &str = "
+; p0f 3 style, trimmed
+[tcp:request]
+label = g:unix:Linux:2.2.x-3.x
+sig   = *:64:0:*:*,*:mss,sok,ts,nop,ws:df,id+:0
+label = s:unix:Linux:3.11 and newer
+sig   = *:64:0:*:mss*20,7:mss,sok,ts,nop,ws:df,id+:0
+
+[tcp:response]
+label = s:unix:Linux:3.x
+sig   = *:64:0:*:mss*10,*:mss,sok,ts,nop,ws:df,id+:0
+
+[http:request]
+label = s:!:nginx:1.x
+sys   = Linux
+
+[mtu]
+label = Ethernet or modem
+sig   = 1500
+";
+
+    /// An IPv4 SYN, or SYN-ACK if `response`, from a Linux host 3 hops
+    /// away, with MSS 1460, SACK permitted, timestamps, NOP and scale 7.
+    fn syn(response: bool, window: u16) -> EthernetIIFrame {
+        let flags = if response { TCP_SYN | TCP_ACK } else { TCP_SYN };
+        let tcp = Tcp {
+            window,
+            options: vec![
+                2, 4, 0x05, 0xb4, 4, 2, 8, 10, 0, 0, 0, 1, 0, 0, 0, 0, 1, 3, 3, 7,
+            ],
+            ..Tcp::new(!response, flags)
+        };
+        let ipv4 = Ipv4 {
+            identification: 0x1234,
+            fragment: 0x4000,
+            ttl: 61,
+            ..Ipv4::between(!response, 6)
+        };
+        EthernetIIFrame::new(&ipv4.frame(&tcp.segment())).unwrap()
+    }
+
+    #[test]
+    fn specific_signature_wins_over_generic() {
+        let signatures = Signatures::parse(DATABASE).unwrap();
+        let fingerprint = signatures.classify(&syn(false, 29200)).unwrap();
+        assert_eq!(
+            fingerprint.signature,
+            "4:64:0:1460:29200,7:mss,sok,ts,nop,ws:df,id+:0"
+        );
+        assert_eq!(fingerprint.distance, 3);
+        assert_eq!(fingerprint.link.as_deref(), Some("Ethernet or modem"));
+        assert_eq!(
+            fingerprint.to_string(),
+            "Linux 3.11 and newer, 3 hops from a SYN"
+        );
+
+        // a window the specific signature does not allow leaves the generic one
+        let fingerprint = signatures.classify(&syn(false, 64240)).unwrap();
+        assert_eq!(
+            fingerprint.to_string(),
+            "Linux 2.2.x-3.x (generic), 3 hops from a SYN"
+        );
+    }
+
+    #[test]
+    fn responses_match_only_response_signatures() {
+        let signatures = Signatures::parse(DATABASE).unwrap();
+        let fingerprint = signatures.classify(&syn(true, 14600)).unwrap();
+        assert_eq!(fingerprint.to_string(), "Linux 3.x, 3 hops from a SYN-ACK");
+
+        let fingerprint = signatures.classify(&syn(true, 29200)).unwrap();
+        assert_eq!(fingerprint.os, None);
+        assert_eq!(
+            fingerprint.to_string(),
+            "unknown (4:64:0:1460:29200,7:mss,sok,ts,nop,ws:df,id+:0) from a SYN-ACK"
+        );
+    }
+
+    #[test]
+    fn database_errors_name_the_line() {
+        let error = |text: &str| Signatures::parse(text).unwrap_err();
+        assert_eq!(
+            error("[tcp:request]\nsig = *:64:0:*:*,*:mss::0"),
+            "line 2: sig before any label"
+        );
+        assert_eq!(
+            error("[tcp:request]\nlabel = x:unix:Linux\nsig = *:64:0:*:*,*:mss::0"),
+            "line 3: bad label 'x:unix:Linux'"
+        );
+        assert_eq!(
+            error("[tcp:request]\nlabel = s:unix:Linux\nsig = *:64:0"),
+            "line 3: expected 8 fields, found 3"
+        );
+        assert_eq!(
+            error("[tcp:request]\nlabel = s:unix:Linux\nsig = *:64:0:*:1024:mss::0"),
+            "line 3: window '1024' has no scale"
+        );
+        assert_eq!(
+            error("[tcp:request]\nlabel = s:unix:Linux\nsig = *:64:0:*:*,*:mss::?"),
+            "line 3: bad payload class '?'"
+        );
+        assert_eq!(
+            error("[mtu]\nlabel = PPP\nsig = big"),
+            "line 3: bad MTU 'big'"
+        );
+        assert_eq!(error("[mtu]\nPPP"), "line 2: expected 'key = value'");
+    }
+}
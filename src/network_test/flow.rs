@@ -0,0 +1,516 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, SystemTime};
+
+use crate::network_test::datalink::EthernetIIFrame;
+use crate::network_test::network::PacketType;
+use crate::network_test::transport::TransportSegment;
+
+/// The 5-tuple identifying one direction of a conversation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct FlowKey {
+    pub protocol: u8,
+    pub source: IpAddr,
+    pub source_port: u16,
+    pub destination: IpAddr,
+    pub destination_port: u16,
+}
+
+impl FlowKey {
+    /// Builds the key from the IP addresses and TCP/UDP ports of a frame.
+    /// Other IP protocols get port 0, non-IP frames have no flow.
+    pub fn from_frame(frame: &EthernetIIFrame) -> Option<Self> {
+        match frame.get_network_packet() {
+            PacketType::IPv4(packet) => {
+                let (protocol, source_port, destination_port) = match packet.get_transport_segment()
+                {
+                    TransportSegment::TCP(segment) => {
+                        (6, segment.get_source_port(), segment.get_destination_port())
+                    }
+                    TransportSegment::UDP(segment) => (
+                        17,
+                        segment.get_source_port(),
+                        segment.get_destination_port(),
+                    ),
+                    _ => (packet.get_protocol(), 0, 0),
+                };
+
+                Some(FlowKey {
+                    protocol,
+                    source: IpAddr::V4(packet.get_source()),
+                    source_port,
+                    destination: IpAddr::V4(packet.get_destination()),
+                    destination_port,
+                })
+            }
+            PacketType::IPv6(packet) => {
+                let protocol = packet.get_next_header();
+                let payload = packet.get_payload();
+                let (source_port, destination_port) = match protocol {
+                    6 | 17 if payload.len() >= 4 => (
+                        u16::from_be_bytes([payload[0], payload[1]]),
+                        u16::from_be_bytes([payload[2], payload[3]]),
+                    ),
+                    _ => (0, 0),
+                };
+
+                Some(FlowKey {
+                    protocol,
+                    source: IpAddr::V6(packet.get_source()),
+                    source_port,
+                    destination: IpAddr::V6(packet.get_destination()),
+                    destination_port,
+                })
+            }
+            _ => None,
+        }
+    }
+
+    pub fn reversed(&self) -> Self {
+        FlowKey {
+            protocol: self.protocol,
+            source: self.destination,
+            source_port: self.destination_port,
+            destination: self.source,
+            destination_port: self.source_port,
+        }
+    }
+
+    pub fn protocol_name(&self) -> String {
+        match self.protocol {
+            1 => String::from("ICMP"),
+            6 => String::from("TCP"),
+            17 => String::from("UDP"),
+            58 => String::from("ICMP6"),
+            other => other.to_string(),
+        }
+    }
+
+    /// The same key for both directions of a conversation.
+    pub fn canonical(&self) -> Self {
+        let reversed = self.reversed();
+        if (reversed.source, reversed.source_port) < (self.source, self.source_port) {
+            reversed
+        } else {
+            *self
+        }
+    }
+}
+
+impl fmt::Display for FlowKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} → {}",
+            self.protocol_name(),
+            SocketAddr::new(self.source, self.source_port),
+            SocketAddr::new(self.destination, self.destination_port)
+        )
+    }
+}
+
+pub const TCP_FIN: u8 = 0x01;
+pub const TCP_SYN: u8 = 0x02;
+pub const TCP_RST: u8 = 0x04;
+pub const TCP_PSH: u8 = 0x08;
+pub const TCP_ACK: u8 = 0x10;
+pub const TCP_URG: u8 = 0x20;
+
+/// Where a TCP connection is, as far as the flags seen so far tell.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TcpState {
+    SynSent,
+    SynReceived,
+    Established,
+    /// one side sent FIN
+    Closing,
+    /// both sides sent FIN
+    Closed,
+    Reset,
+}
+
+impl fmt::Display for TcpState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            TcpState::SynSent => "SYN_SENT",
+            TcpState::SynReceived => "SYN_RECEIVED",
+            TcpState::Established => "ESTABLISHED",
+            TcpState::Closing => "CLOSING",
+            TcpState::Closed => "CLOSED",
+            TcpState::Reset => "RESET",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DirectionStats {
+    pub packets: u64,
+    pub bytes: u64,
+    /// every TCP flag seen in this direction
+    pub tcp_flags: u8,
+}
+
+/// One conversation; `key` is oriented the way its first packet went.
+#[derive(Debug, Clone)]
+pub struct FlowRecord {
+    pub key: FlowKey,
+    pub first_seen: SystemTime,
+    pub last_seen: SystemTime,
+    pub forward: DirectionStats,
+    pub reverse: DirectionStats,
+    /// `None` for anything but TCP
+    pub tcp_state: Option<TcpState>,
+    fin_forward: bool,
+    fin_reverse: bool,
+}
+
+impl FlowRecord {
+    fn new(key: FlowKey, timestamp: SystemTime) -> Self {
+        FlowRecord {
+            key,
+            first_seen: timestamp,
+            last_seen: timestamp,
+            forward: DirectionStats::default(),
+            reverse: DirectionStats::default(),
+            tcp_state: None,
+            fin_forward: false,
+            fin_reverse: false,
+        }
+    }
+
+    pub fn packets(&self) -> u64 {
+        self.forward.packets + self.reverse.packets
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.forward.bytes + self.reverse.bytes
+    }
+
+    /// Every TCP flag seen in either direction.
+    pub fn tcp_flags(&self) -> u8 {
+        self.forward.tcp_flags | self.reverse.tcp_flags
+    }
+
+    pub fn duration(&self) -> Duration {
+        self.last_seen
+            .duration_since(self.first_seen)
+            .unwrap_or_default()
+    }
+
+    /// True once the connection closed or was reset.
+    pub fn is_finished(&self) -> bool {
+        matches!(self.tcp_state, Some(TcpState::Closed | TcpState::Reset))
+    }
+
+    fn update_tcp(&mut self, flags: u8, forward: bool) {
+        if forward && flags & TCP_FIN != 0 {
+            self.fin_forward = true;
+        }
+        if !forward && flags & TCP_FIN != 0 {
+            self.fin_reverse = true;
+        }
+
+        let state = match (self.tcp_state, flags) {
+            (_, f) if f & TCP_RST != 0 => TcpState::Reset,
+            (Some(TcpState::Reset), _) => TcpState::Reset,
+            _ if self.fin_forward && self.fin_reverse => TcpState::Closed,
+            _ if self.fin_forward || self.fin_reverse => TcpState::Closing,
+            (_, f) if f & (TCP_SYN | TCP_ACK) == TCP_SYN | TCP_ACK => TcpState::SynReceived,
+            (_, f) if f & TCP_SYN != 0 => TcpState::SynSent,
+            (Some(TcpState::SynSent), _) => TcpState::SynSent,
+            // the handshake completes with the first ACK after SYN-ACK, and a
+            // flow picked up mid-stream is assumed to be established
+            _ => TcpState::Established,
+        };
+        self.tcp_state = Some(state);
+    }
+}
+
+/// How long flows live in the table before `FlowTable::expire` returns them.
+#[derive(Debug, Clone, Copy)]
+pub struct FlowTimeouts {
+    /// no packet for this long ends a flow
+    pub idle: Duration,
+    /// flows running longer than this are reported and restarted
+    pub active: Duration,
+    /// closed or reset flows stay this long, so the last ACKs after a FIN
+    /// or RST count towards them instead of starting flows of their own
+    pub linger: Duration,
+}
+
+impl Default for FlowTimeouts {
+    fn default() -> Self {
+        FlowTimeouts {
+            idle: Duration::from_secs(15),
+            active: Duration::from_secs(1800),
+            // 2×MSL would be minutes; the stray ACKs come within an RTT or two
+            linger: Duration::from_secs(4),
+        }
+    }
+}
+
+/// The source, destination, protocol and identification shared by the
+/// fragments of one IPv4 datagram.
+type FragmentKey = (IpAddr, IpAddr, u8, u16);
+
+/// Per-connection statistics keyed by the direction-independent 5-tuple.
+#[derive(Debug, Default)]
+pub struct FlowTable {
+    timeouts: FlowTimeouts,
+    flows: HashMap<FlowKey, FlowRecord>,
+    /// the flow of each fragmented datagram whose first fragment was seen,
+    /// and when, since later fragments carry no ports
+    fragments: HashMap<FragmentKey, (FlowKey, SystemTime)>,
+}
+
+impl FlowTable {
+    pub fn new(timeouts: FlowTimeouts) -> Self {
+        FlowTable {
+            timeouts,
+            flows: HashMap::new(),
+            fragments: HashMap::new(),
+        }
+    }
+
+    /// Accounts one frame; non-IP frames are ignored, and so are IPv4
+    /// fragments whose first fragment was not seen.
+    pub fn update(&mut self, frame: &EthernetIIFrame, timestamp: SystemTime) {
+        let Some(key) = self.flow_key(frame, timestamp) else {
+            return;
+        };
+
+        let record = self
+            .flows
+            .entry(key.canonical())
+            .or_insert_with(|| FlowRecord::new(key, timestamp));
+        let forward = record.key == key;
+
+        let direction = if forward {
+            &mut record.forward
+        } else {
+            &mut record.reverse
+        };
+        direction.packets += 1;
+        direction.bytes += frame.get_length() as u64;
+        record.last_seen = record.last_seen.max(timestamp);
+
+        if let Some(flags) = tcp_flags(frame) {
+            direction.tcp_flags |= flags;
+            record.update_tcp(flags, forward);
+        }
+    }
+
+    /// The flow key of a frame; a later IPv4 fragment gets the key of the
+    /// first fragment of its datagram.
+    fn flow_key(&mut self, frame: &EthernetIIFrame, timestamp: SystemTime) -> Option<FlowKey> {
+        let PacketType::IPv4(packet) = frame.get_network_packet() else {
+            return FlowKey::from_frame(frame);
+        };
+        if !packet.is_fragment() {
+            return FlowKey::from_frame(frame);
+        }
+
+        let fragment = (
+            IpAddr::V4(packet.get_source()),
+            IpAddr::V4(packet.get_destination()),
+            packet.get_protocol(),
+            packet.get_identification(),
+        );
+        if packet.get_fragment_offset() == 0 {
+            let key = FlowKey::from_frame(frame)?;
+            self.fragments.insert(fragment, (key, timestamp));
+            Some(key)
+        } else {
+            self.fragments.get(&fragment).map(|(key, _)| *key)
+        }
+    }
+
+    /// Removes and returns the flows that ended: idle too long, closed or
+    /// reset and past the linger time, or active past the active timeout.
+    pub fn expire(&mut self, now: SystemTime) -> Vec<FlowRecord> {
+        let timeouts = self.timeouts;
+        let ended = |record: &FlowRecord| {
+            let idle = now.duration_since(record.last_seen).unwrap_or_default();
+            (record.is_finished() && idle >= timeouts.linger)
+                || idle >= timeouts.idle
+                || now.duration_since(record.first_seen).unwrap_or_default() >= timeouts.active
+        };
+
+        // a datagram's fragments arrive together, so idle ones are long complete
+        self.fragments
+            .retain(|_, (_, seen)| now.duration_since(*seen).unwrap_or_default() < timeouts.idle);
+
+        let keys: Vec<FlowKey> = self
+            .flows
+            .iter()
+            .filter(|(_, record)| ended(record))
+            .map(|(key, _)| *key)
+            .collect();
+
+        keys.into_iter()
+            .filter_map(|key| self.flows.remove(&key))
+            .collect()
+    }
+
+    /// Removes and returns every flow, e.g. when the capture stops.
+    pub fn drain(&mut self) -> Vec<FlowRecord> {
+        self.fragments.clear();
+        self.flows.drain().map(|(_, record)| record).collect()
+    }
+
+    pub fn flows(&self) -> impl Iterator<Item = &FlowRecord> {
+        self.flows.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.flows.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.flows.is_empty()
+    }
+}
+
+/// The TCP flags of a frame, for IPv4 and IPv6 alike.
+pub fn tcp_flags(frame: &EthernetIIFrame) -> Option<u8> {
+    match frame.get_network_packet() {
+        PacketType::IPv4(packet) => match packet.get_transport_segment() {
+            TransportSegment::TCP(segment) => Some(segment.get_flags()),
+            _ => None,
+        },
+        PacketType::IPv6(packet) => {
+            let payload = packet.get_payload();
+            (packet.get_next_header() == 6 && payload.len() >= 14).then(|| payload[13])
+        }
+        _ => None,
+    }
+}
+
+/// Column a flow table is ordered by, largest first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum FlowSort {
+    Bytes,
+    Packets,
+    Duration,
+    First,
+    Last,
+}
+
+/// Formats `flows` as a table ordered by `sort`, at most `top` rows.
+pub fn format_table(flows: &[&FlowRecord], sort: FlowSort, top: Option<usize>) -> String {
+    let mut flows = flows.to_vec();
+    match sort {
+        FlowSort::Bytes => flows.sort_by_key(|f| std::cmp::Reverse(f.bytes())),
+        FlowSort::Packets => flows.sort_by_key(|f| std::cmp::Reverse(f.packets())),
+        FlowSort::Duration => flows.sort_by_key(|f| std::cmp::Reverse(f.duration())),
+        FlowSort::First => flows.sort_by_key(|f| f.first_seen),
+        FlowSort::Last => flows.sort_by_key(|f| std::cmp::Reverse(f.last_seen)),
+    }
+    if let Some(top) = top {
+        flows.truncate(top);
+    }
+
+    let mut context = format!(
+        "{:<5} {:<45} {:<45} {:>8} {:>10} {:>8} {:>10} {:>9} {:<12} {}\n",
+        "proto",
+        "source",
+        "destination",
+        "pkts →",
+        "bytes →",
+        "pkts ←",
+        "bytes ←",
+        "duration",
+        "state",
+        "flags"
+    );
+
+    for flow in flows {
+        let state = flow.tcp_state.map(|s| s.to_string()).unwrap_or_default();
+        let flags = [
+            (TCP_SYN, 'S'),
+            (TCP_ACK, 'A'),
+            (TCP_PSH, 'P'),
+            (TCP_FIN, 'F'),
+            (TCP_RST, 'R'),
+            (TCP_URG, 'U'),
+        ]
+        .into_iter()
+        .filter(|(bit, _)| flow.tcp_flags() & bit != 0)
+        .map(|(_, c)| c)
+        .collect::<String>();
+
+        context.push_str(&format!(
+            "{:<5} {:<45} {:<45} {:>8} {:>10} {:>8} {:>10} {:>8.3}s {:<12} {}\n",
+            flow.key.protocol_name(),
+            SocketAddr::new(flow.key.source, flow.key.source_port).to_string(),
+            SocketAddr::new(flow.key.destination, flow.key.destination_port).to_string(),
+            flow.forward.packets,
+            flow.forward.bytes,
+            flow.reverse.packets,
+            flow.reverse.bytes,
+            flow.duration().as_secs_f64(),
+            state,
+            flags
+        ));
+    }
+
+    context
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network_test::util::frames::{udp, Ipv4, Tcp};
+
+    fn at(seconds: u64) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(seconds)
+    }
+
+    #[test]
+    fn closed_flow_lingers_for_its_last_ack() {
+        let mut table = FlowTable::new(FlowTimeouts::default());
+        table.update(&Tcp::new(true, TCP_SYN).frame(), at(0));
+        table.update(&Tcp::new(false, TCP_SYN | TCP_ACK).frame(), at(0));
+        table.update(&Tcp::new(true, TCP_ACK).frame(), at(0));
+        table.update(&Tcp::new(true, TCP_FIN | TCP_ACK).frame(), at(1));
+        table.update(&Tcp::new(false, TCP_FIN | TCP_ACK).frame(), at(1));
+        assert!(table.expire(at(1)).is_empty());
+
+        table.update(&Tcp::new(true, TCP_ACK).frame(), at(1));
+        assert!(table.expire(at(2)).is_empty());
+
+        let expired = table.expire(at(5));
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].packets(), 6);
+        assert_eq!(expired[0].tcp_state, Some(TcpState::Closed));
+        assert!(table.is_empty());
+    }
+
+    #[test]
+    fn later_fragments_join_the_flow_of_the_first() {
+        // UDP 10.0.0.1:5353 → 10.0.0.2:53 in two fragments, MF set on the first
+        let fragment = |identification, fragment, data: &[u8]| {
+            let ipv4 = Ipv4 {
+                protocol: 17,
+                identification,
+                fragment,
+                ..Ipv4::default()
+            };
+            EthernetIIFrame::new(&ipv4.frame(data)).unwrap()
+        };
+        let mut table = FlowTable::new(FlowTimeouts::default());
+        let first = [udp(5353, 53, &[0xaa; 8]), vec![0xaa; 8]].concat();
+        table.update(&fragment(7, 0x2000, &first), at(0));
+        table.update(&fragment(7, 3, &[0xbb; 8]), at(0));
+        // a fragment of a datagram whose start was missed is left out
+        table.update(&fragment(8, 3, &[0xcc; 8]), at(0));
+
+        let flows: Vec<&FlowRecord> = table.flows().collect();
+        assert_eq!(flows.len(), 1);
+        assert_eq!(flows[0].key.source_port, 5353);
+        assert_eq!(flows[0].key.destination_port, 53);
+        assert_eq!(flows[0].forward.packets, 2);
+    }
+}
@@ -0,0 +1,245 @@
+use std::ops::Range;
+
+/// A named span of bytes inside a captured frame, e.g. the TCP options.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Region {
+    pub name: &'static str,
+    pub range: Range<usize>,
+}
+
+impl Region {
+    pub fn new(name: &'static str, start: usize, end: usize) -> Self {
+        Region {
+            name,
+            range: start..end,
+        }
+    }
+}
+
+/// Whether `read` dumps the raw frame and with which columns.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HexDump {
+    Off,
+    /// `-x`: offsets and hex bytes
+    Hex,
+    /// `-X`: offsets, hex bytes and printable ASCII
+    HexAscii,
+}
+
+const BYTES_PER_LINE: usize = 16;
+const LABEL_WIDTH: usize = 14;
+
+/// Dumps `bytes` 16 per line. Every region starts on a fresh line labelled
+/// with its name, so layer boundaries are visible in the dump; bytes no
+/// region claims are labelled `undissected`.
+pub fn dump(bytes: &[u8], regions: &[Region], mode: HexDump) -> String {
+    let mut context = String::new();
+
+    if mode == HexDump::Off {
+        return context;
+    }
+
+    for region in fill_gaps(regions, bytes.len()) {
+        let end = region.range.end.min(bytes.len());
+        let mut start = region.range.start;
+        let mut label = region.name;
+
+        while start < end {
+            let line_end = (start + BYTES_PER_LINE).min(end);
+            let line = &bytes[start..line_end];
+
+            context.push_str(&format!(
+                "{:<width$}0x{:04x}  ",
+                label,
+                start,
+                width = LABEL_WIDTH
+            ));
+            for (i, byte) in line.iter().enumerate() {
+                if i == BYTES_PER_LINE / 2 {
+                    context.push(' ');
+                }
+                context.push_str(&format!("{:02x} ", byte));
+            }
+
+            if mode == HexDump::HexAscii {
+                let padding = (BYTES_PER_LINE - line.len()) * 3
+                    + usize::from(line.len() <= BYTES_PER_LINE / 2);
+                context.push_str(&" ".repeat(padding));
+                context.push('|');
+                context.extend(line.iter().map(|&b| {
+                    if b.is_ascii_graphic() || b == b' ' {
+                        b as char
+                    } else {
+                        '.'
+                    }
+                }));
+                context.push('|');
+            }
+
+            context.push('\n');
+            start = line_end;
+            label = "";
+        }
+    }
+
+    context
+}
+
+fn fill_gaps(regions: &[Region], length: usize) -> Vec<Region> {
+    let mut sorted: Vec<Region> = regions
+        .iter()
+        .filter(|r| r.range.start < r.range.end)
+        .cloned()
+        .collect();
+    sorted.sort_by_key(|r| r.range.start);
+
+    let mut filled = vec![];
+    let mut cursor = 0;
+
+    for region in sorted {
+        if region.range.start < cursor {
+            continue;
+        }
+        if region.range.start > cursor {
+            filled.push(Region::new("undissected", cursor, region.range.start));
+        }
+        cursor = region.range.end;
+        filled.push(region);
+    }
+
+    if cursor < length {
+        filled.push(Region::new("undissected", cursor, length));
+    }
+
+    filled
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network_test::datalink::EthernetIIFrame;
+    use crate::network_test::util::frames::{ethernet, mac, udp, Ipv4};
+
+    fn names(regions: &[Region]) -> Vec<(&'static str, usize, usize)> {
+        regions
+            .iter()
+            .map(|region| (region.name, region.range.start, region.range.end))
+            .collect()
+    }
+
+    /// Labels of the lines that start a region.
+    fn labels(dump: &str) -> Vec<&str> {
+        dump.lines()
+            .map(|line| line[..LABEL_WIDTH].trim_end())
+            .filter(|label| !label.is_empty())
+            .collect()
+    }
+
+    #[test]
+    fn ipv4_options_get_their_own_lines() {
+        let router_alert = vec![0x94, 4, 0, 0];
+        let data = Ipv4 {
+            options: router_alert,
+            ..Ipv4::between(true, 17)
+        }
+        .frame(&udp(1234, 53, b"hello"));
+        let frame = EthernetIIFrame::new(&data).unwrap();
+        let regions = frame.regions();
+
+        assert_eq!(
+            names(&regions),
+            [
+                ("Ethernet II", 0, 14),
+                ("IPv4", 14, 34),
+                ("IPv4 options", 34, 38),
+                ("UDP", 38, 46),
+                ("UDP data", 46, 51),
+            ]
+        );
+
+        let dump = dump(&data, &regions, HexDump::Hex);
+        assert_eq!(
+            labels(&dump),
+            ["Ethernet II", "IPv4", "IPv4 options", "UDP", "UDP data"]
+        );
+        assert!(dump.contains("IPv4 options  0x0022  94 04 00 00 \n"));
+        assert!(!dump.contains('|'));
+    }
+
+    #[test]
+    fn ipv6_extension_headers_are_marked() {
+        let hop_by_hop = [17, 0, 5, 2, 0, 0, 1, 0];
+        let datagram = udp(1234, 53, b"hello");
+        let mut packet = vec![0x60, 0, 0, 0];
+        packet.extend_from_slice(&((hop_by_hop.len() + datagram.len()) as u16).to_be_bytes());
+        packet.extend_from_slice(&[0, 64]);
+        packet.extend_from_slice(&[0xfe, 0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+        packet.extend_from_slice(&[0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x16]);
+        packet.extend_from_slice(&hop_by_hop);
+        packet.extend_from_slice(&datagram);
+        let data = ethernet(mac(1), mac(2), 0x86dd, &packet);
+        let frame = EthernetIIFrame::new(&data).unwrap();
+
+        assert_eq!(
+            names(&frame.regions()),
+            [
+                ("Ethernet II", 0, 14),
+                ("IPv6", 14, 54),
+                ("IPv6 hop-by-hop", 54, 62),
+                ("IPv6 payload", 62, 75),
+            ]
+        );
+        assert!(format!("{:#}", frame).contains("IPv6 hop-by-hop (8 bytes)"));
+    }
+
+    #[test]
+    fn gaps_and_overlaps() {
+        let regions = [
+            Region::new("TCP", 20, 40),
+            Region::new("IPv4", 4, 20),
+            Region::new("inside", 30, 35),
+            Region::new("empty", 40, 40),
+        ];
+
+        assert_eq!(
+            names(&fill_gaps(&regions, 48)),
+            [
+                ("undissected", 0, 4),
+                ("IPv4", 4, 20),
+                ("TCP", 20, 40),
+                ("undissected", 40, 48),
+            ]
+        );
+        assert_eq!(names(&fill_gaps(&[], 3)), [("undissected", 0, 3)]);
+        assert_eq!(names(&fill_gaps(&[], 0)), []);
+    }
+
+    #[test]
+    fn ascii_column_lines_up() {
+        let bytes: Vec<u8> = (b'A'..=b'Z').chain([0, b' ', 0x7f]).collect();
+        let regions = [
+            Region::new("short", 0, 3),
+            Region::new("half", 3, 11),
+            Region::new("long", 11, 29),
+        ];
+
+        let dump = dump(&bytes, &regions, HexDump::HexAscii);
+        let lines: Vec<&str> = dump.lines().collect();
+        assert_eq!(lines.len(), 4);
+        for line in &lines {
+            assert_eq!(
+                line.find('|'),
+                Some(LABEL_WIDTH + 8 + 16 * 3 + 1),
+                "{}",
+                line
+            );
+        }
+        assert!(lines[0].ends_with("|ABC|"));
+        assert!(lines[1].ends_with("|DEFGHIJK|"));
+        assert!(lines[2].starts_with("long          0x000b  4c 4d"));
+        assert!(lines[3].starts_with("              0x001b  20 7f "));
+        assert!(lines[3].ends_with("| .|"));
+
+        assert_eq!(super::dump(&bytes, &regions, HexDump::Off), "");
+    }
+}
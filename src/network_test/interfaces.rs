@@ -0,0 +1,592 @@
+use std::fmt::Write as _;
+use std::fs;
+use std::net::IpAddr;
+use std::path::Path;
+
+use pnet::datalink::NetworkInterface;
+use pnet::ipnetwork::IpNetwork;
+use pnet::util::MacAddr;
+use regex::Regex;
+
+/// The `IFF_*` bits of `flags` worth naming, in the order `ip link` lists them.
+const FLAG_NAMES: [(u32, &str); 8] = [
+    (0x0008, "LOOPBACK"),
+    (0x0010, "POINTOPOINT"),
+    (0x0002, "BROADCAST"),
+    (0x1000, "MULTICAST"),
+    (0x0080, "NOARP"),
+    (0x0100, "PROMISC"),
+    (0x0001, "UP"),
+    (0x0040, "RUNNING"),
+];
+const SYSFS_NET: &str = "/sys/class/net";
+const IPV4_ROUTES: &str = "/proc/net/route";
+const IPV6_ROUTES: &str = "/proc/net/ipv6_route";
+
+/// Which interfaces `ls` shows; every condition set must hold.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct InterfaceFilter {
+    /// only interfaces that are administratively up
+    pub up: bool,
+    /// only loopback interfaces, or with `Some(false)` none of them
+    pub loopback: Option<bool>,
+    /// only interfaces with an IPv4 address
+    pub ipv4: bool,
+}
+
+impl InterfaceFilter {
+    fn matches(&self, interface: &NetworkInterface) -> bool {
+        (!self.up || interface.is_up())
+            && self
+                .loopback
+                .is_none_or(|loopback| interface.is_loopback() == loopback)
+            && (!self.ipv4 || interface.ips.iter().any(IpNetwork::is_ipv4))
+    }
+}
+
+/// Packet, byte, drop and error counts of one direction, as the kernel keeps them.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Counters {
+    pub packets: u64,
+    pub bytes: u64,
+    pub dropped: u64,
+    pub errors: u64,
+}
+
+/// One interface with what sysfs adds to the `getifaddrs` view.
+/// Fields sysfs does not have for the interface are `None`.
+#[derive(Debug, Clone)]
+pub struct InterfaceInfo {
+    pub interface: NetworkInterface,
+    pub mtu: Option<u32>,
+    /// `up`, `down`, `dormant`, `unknown` (usual for loopback and tunnels), ...
+    pub operstate: Option<String>,
+    /// in Mb/s; virtual interfaces and links that are down have none
+    pub speed: Option<u32>,
+    pub duplex: Option<String>,
+    pub driver: Option<String>,
+    pub rx: Option<Counters>,
+    pub tx: Option<Counters>,
+}
+
+impl InterfaceInfo {
+    fn read(interface: NetworkInterface) -> Self {
+        let base = Path::new(SYSFS_NET).join(&interface.name);
+        let read = |file: &str| {
+            fs::read_to_string(base.join(file))
+                .ok()
+                .map(|text| text.trim().to_string())
+                .filter(|text| !text.is_empty())
+        };
+        let counters = |direction: &str| {
+            let count = |name: &str| {
+                read(&format!("statistics/{}_{}", direction, name))?
+                    .parse()
+                    .ok()
+            };
+            Some(Counters {
+                packets: count("packets")?,
+                bytes: count("bytes")?,
+                dropped: count("dropped")?,
+                errors: count("errors")?,
+            })
+        };
+
+        InterfaceInfo {
+            mtu: read("mtu").and_then(|mtu| mtu.parse().ok()),
+            operstate: read("operstate"),
+            // reads fail or give -1 while there is no link
+            speed: read("speed")
+                .and_then(|speed| speed.parse::<i64>().ok())
+                .and_then(|speed| u32::try_from(speed).ok()),
+            duplex: read("duplex").filter(|duplex| duplex != "unknown"),
+            driver: fs::read_link(base.join("device/driver"))
+                .ok()
+                .and_then(|path| Some(path.file_name()?.to_string_lossy().to_string())),
+            rx: counters("rx"),
+            tx: counters("tx"),
+            interface,
+        }
+    }
+
+    pub fn flag_names(&self) -> Vec<&'static str> {
+        FLAG_NAMES
+            .iter()
+            .filter(|(bit, _)| self.interface.flags & bit != 0)
+            .map(|(_, name)| *name)
+            .collect()
+    }
+}
+
+/// The interfaces `filter` lets through, in index order.
+pub fn list(filter: &InterfaceFilter) -> Vec<InterfaceInfo> {
+    let mut interfaces: Vec<NetworkInterface> = pnet::datalink::interfaces()
+        .into_iter()
+        .filter(|interface| filter.matches(interface))
+        .collect();
+    interfaces.sort_by_key(|interface| interface.index);
+    interfaces.into_iter().map(InterfaceInfo::read).collect()
+}
+
+/// One block per interface, shaped after `ip -s link`.
+pub fn format_text(interfaces: &[InterfaceInfo]) -> String {
+    let mut context = String::new();
+
+    for info in interfaces {
+        let interface = &info.interface;
+        let _ = writeln!(
+            context,
+            "{}: {} <{}>",
+            interface.index,
+            interface.name,
+            info.flag_names().join(",")
+        );
+        if !interface.description.is_empty() {
+            let _ = writeln!(context, "    description  {}", interface.description);
+        }
+        if let Some(mac) = interface.mac {
+            let _ = writeln!(context, "    mac          {}", mac);
+        }
+        for ip in &interface.ips {
+            let family = if ip.is_ipv4() { "ipv4" } else { "ipv6" };
+            let _ = writeln!(context, "    {}         {}", family, ip);
+        }
+
+        let mut link = vec![];
+        if let Some(mtu) = info.mtu {
+            link.push(format!("mtu {}", mtu));
+        }
+        if let Some(state) = &info.operstate {
+            link.push(format!("state {}", state));
+        }
+        if let Some(speed) = info.speed {
+            link.push(format!("{} Mb/s", speed));
+        }
+        if let Some(duplex) = &info.duplex {
+            link.push(format!("{} duplex", duplex));
+        }
+        if let Some(driver) = &info.driver {
+            link.push(format!("driver {}", driver));
+        }
+        if !link.is_empty() {
+            let _ = writeln!(context, "    link         {}", link.join(", "));
+        }
+
+        for (direction, counters) in [("rx", info.rx), ("tx", info.tx)] {
+            if let Some(counters) = counters {
+                let _ = writeln!(
+                    context,
+                    "    {}           {} packets, {} bytes, {} dropped, {} errors",
+                    direction, counters.packets, counters.bytes, counters.dropped, counters.errors
+                );
+            }
+        }
+    }
+
+    context
+}
+
+/// A JSON array with one object per interface, for scripts; fields sysfs
+/// does not have are `null`.
+pub fn format_json(interfaces: &[InterfaceInfo]) -> String {
+    let string = |text: &str| format!("\"{}\"", escape(text));
+    let optional = |value: Option<String>| value.unwrap_or_else(|| String::from("null"));
+    let list = |items: Vec<String>| format!("[{}]", items.join(", "));
+    let counters = |counters: Option<Counters>| {
+        optional(counters.map(|c| {
+            format!(
+                "{{\"packets\": {}, \"bytes\": {}, \"dropped\": {}, \"errors\": {}}}",
+                c.packets, c.bytes, c.dropped, c.errors
+            )
+        }))
+    };
+
+    let objects: Vec<String> = interfaces
+        .iter()
+        .map(|info| {
+            let interface = &info.interface;
+            let ips = |ipv4: bool| {
+                list(
+                    interface
+                        .ips
+                        .iter()
+                        .filter(|ip| ip.is_ipv4() == ipv4)
+                        .map(|ip| string(&ip.to_string()))
+                        .collect(),
+                )
+            };
+            let fields = [
+                ("name", string(&interface.name)),
+                ("index", interface.index.to_string()),
+                ("description", string(&interface.description)),
+                (
+                    "mac",
+                    optional(interface.mac.map(|mac| string(&mac.to_string()))),
+                ),
+                ("ipv4", ips(true)),
+                ("ipv6", ips(false)),
+                (
+                    "flags",
+                    list(info.flag_names().into_iter().map(string).collect()),
+                ),
+                ("flags_raw", interface.flags.to_string()),
+                ("mtu", optional(info.mtu.map(|mtu| mtu.to_string()))),
+                ("operstate", optional(info.operstate.as_deref().map(string))),
+                (
+                    "speed_mbps",
+                    optional(info.speed.map(|speed| speed.to_string())),
+                ),
+                ("duplex", optional(info.duplex.as_deref().map(string))),
+                ("driver", optional(info.driver.as_deref().map(string))),
+                ("rx", counters(info.rx)),
+                ("tx", counters(info.tx)),
+            ];
+            let fields: Vec<String> = fields
+                .iter()
+                .map(|(name, value)| format!("    \"{}\": {}", name, value))
+                .collect();
+            format!("  {{\n{}\n  }}", fields.join(",\n"))
+        })
+        .collect();
+
+    match objects.is_empty() {
+        true => String::from("[]\n"),
+        false => format!("[\n{}\n]\n", objects.join(",\n")),
+    }
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(escaped, "\\u{:04x}", c as u32);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// What one `-n` value names.
+#[derive(Debug, Clone)]
+enum Selector {
+    Name(String),
+    Index(u32),
+    /// a glob such as `eth*` or a regex written `/^en.*/`, matched against names
+    Pattern(Regex),
+    /// every interface that is up
+    Any,
+    /// the interface the default route leaves through
+    Default,
+    Mac(MacAddr),
+    /// the interface holding this address
+    Ip(IpAddr),
+}
+
+impl Selector {
+    fn parse(text: &str) -> Result<Self, String> {
+        if text == "any" {
+            return Ok(Selector::Any);
+        }
+        if text == "default" {
+            return Ok(Selector::Default);
+        }
+        if let Ok(index) = text.parse() {
+            return Ok(Selector::Index(index));
+        }
+        if let Ok(mac) = text.parse() {
+            return Ok(Selector::Mac(mac));
+        }
+        if let Ok(ip) = text.parse() {
+            return Ok(Selector::Ip(ip));
+        }
+
+        let pattern = match text.strip_prefix('/').and_then(|t| t.strip_suffix('/')) {
+            Some(regex) => regex.to_string(),
+            None if text.contains(['*', '?', '[']) => glob_to_regex(text),
+            None => return Ok(Selector::Name(text.to_string())),
+        };
+        Regex::new(&pattern)
+            .map(Selector::Pattern)
+            .map_err(|e| format!("bad interface pattern '{}': {}", text, e))
+    }
+
+    fn matches(&self, interface: &NetworkInterface, default: Option<&str>) -> bool {
+        match self {
+            Selector::Name(name) => interface.name == *name,
+            Selector::Index(index) => interface.index == *index,
+            Selector::Pattern(regex) => regex.is_match(&interface.name),
+            Selector::Any => interface.is_up(),
+            Selector::Default => default == Some(interface.name.as_str()),
+            Selector::Mac(mac) => interface.mac == Some(*mac),
+            Selector::Ip(ip) => interface.ips.iter().any(|network| network.ip() == *ip),
+        }
+    }
+}
+
+/// Translates a shell glob into an anchored regex: `*` and `?` match any
+/// run or any single character, `[...]` classes stay as they are.
+fn glob_to_regex(glob: &str) -> String {
+    let mut regex = String::from("^");
+    let mut in_class = false;
+    for c in glob.chars() {
+        match c {
+            '[' if !in_class => {
+                in_class = true;
+                regex.push('[');
+            }
+            ']' if in_class => {
+                in_class = false;
+                regex.push(']');
+            }
+            '!' if in_class && regex.ends_with('[') => regex.push('^'),
+            _ if in_class => regex.push(c),
+            '*' => regex.push_str(".*"),
+            '?' => regex.push('.'),
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    regex.push('$');
+    regex
+}
+
+/// The interface the IPv4 default route with the lowest metric uses, or
+/// failing that the IPv6 one.
+fn default_route_interface() -> Option<String> {
+    let ipv4 = fs::read_to_string(IPV4_ROUTES).ok().and_then(|table| {
+        table
+            .lines()
+            .skip(1)
+            .filter_map(|line| {
+                let fields: Vec<&str> = line.split_whitespace().collect();
+                let [name, destination, _, _, _, _, metric, mask, ..] = fields[..] else {
+                    return None;
+                };
+                let metric: u32 = metric.parse().ok()?;
+                (destination == "00000000" && mask == "00000000").then_some((metric, name))
+            })
+            .min()
+            .map(|(_, name)| name.to_string())
+    });
+
+    ipv4.or_else(|| {
+        let table = fs::read_to_string(IPV6_ROUTES).ok()?;
+        table
+            .lines()
+            .filter_map(|line| {
+                let fields: Vec<&str> = line.split_whitespace().collect();
+                let [destination, length, .., metric, _, _, _, name] = fields[..] else {
+                    return None;
+                };
+                let is_default = destination.bytes().all(|digit| digit == b'0') && length == "00";
+                let metric = u32::from_str_radix(metric, 16).ok()?;
+                (is_default && name != "lo").then_some((metric, name))
+            })
+            .min()
+            .map(|(_, name)| name.to_string())
+    })
+}
+
+/// The interfaces a capture runs on, from the `-n` values: names, indexes,
+/// globs, `/regex/`, `any`, `default`, MAC or IP addresses.
+#[derive(Debug, Clone)]
+pub struct InterfaceSelection {
+    selectors: Vec<(String, Selector)>,
+    /// keep watching for matching interfaces that appear later
+    pub hot_plug: bool,
+}
+
+impl InterfaceSelection {
+    pub fn parse(names: &[String], hot_plug: bool) -> Result<Self, String> {
+        if names.is_empty() {
+            return Err(String::from(
+                "no interface given; name one with -n, or use -n any or -n default",
+            ));
+        }
+        let selectors = names
+            .iter()
+            .map(|name| Selector::parse(name).map(|selector| (name.clone(), selector)))
+            .collect::<Result<_, _>>()?;
+        Ok(InterfaceSelection {
+            selectors,
+            hot_plug,
+        })
+    }
+
+    /// Those of `available` any selector names, each once, in index order.
+    pub fn select(&self, available: Vec<NetworkInterface>) -> Vec<NetworkInterface> {
+        let default = self.needs_default().then(default_route_interface).flatten();
+        self.select_among(available, default.as_deref())
+    }
+
+    /// The interfaces selected now. Every selector must match one unless
+    /// hot plugging may bring it later.
+    pub fn resolve(&self) -> Result<Vec<NetworkInterface>, String> {
+        let default = self.needs_default().then(default_route_interface).flatten();
+        self.resolve_among(pnet::datalink::interfaces(), default.as_deref())
+    }
+
+    fn select_among(
+        &self,
+        available: Vec<NetworkInterface>,
+        default: Option<&str>,
+    ) -> Vec<NetworkInterface> {
+        let mut selected: Vec<NetworkInterface> = available
+            .into_iter()
+            .filter(|interface| {
+                self.selectors
+                    .iter()
+                    .any(|(_, selector)| selector.matches(interface, default))
+            })
+            .collect();
+        selected.sort_by_key(|interface| interface.index);
+        selected
+    }
+
+    fn resolve_among(
+        &self,
+        available: Vec<NetworkInterface>,
+        default: Option<&str>,
+    ) -> Result<Vec<NetworkInterface>, String> {
+        if !self.hot_plug {
+            for (text, selector) in &self.selectors {
+                if let (Selector::Default, None) = (selector, default) {
+                    return Err(String::from("there is no default route"));
+                }
+                let found = available
+                    .iter()
+                    .any(|interface| selector.matches(interface, default));
+                if !found {
+                    return Err(format!(
+                        "no interface matches '{}'; `ls` lists the available ones",
+                        text
+                    ));
+                }
+            }
+        }
+        Ok(self.select_among(available, default))
+    }
+
+    fn needs_default(&self) -> bool {
+        self.selectors
+            .iter()
+            .any(|(_, selector)| matches!(selector, Selector::Default))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UP: u32 = 0x0001;
+
+    /// lo, eth0, eth1 (down), wlan0 and docker0, in scrambled index order.
+    fn available() -> Vec<NetworkInterface> {
+        let interface =
+            |name: &str, index: u32, mac: u8, ips: &[&str], flags: u32| NetworkInterface {
+                name: name.to_string(),
+                description: String::new(),
+                index,
+                mac: (mac != 0).then_some(MacAddr(0x02, 0, 0, 0, 0, mac)),
+                ips: ips.iter().map(|ip| ip.parse().unwrap()).collect(),
+                flags,
+            };
+        vec![
+            interface("wlan0", 4, 4, &["192.168.1.20/24"], UP),
+            interface("eth0", 2, 2, &["10.0.0.1/8", "fe80::2/64"], UP),
+            interface("lo", 1, 0, &["127.0.0.1/8", "::1/128"], UP | 0x0008),
+            interface("docker0", 5, 5, &["172.17.0.1/16"], UP),
+            interface("eth1", 3, 3, &[], 0),
+        ]
+    }
+
+    fn select(names: &[&str], default: Option<&str>) -> Result<Vec<String>, String> {
+        let names: Vec<String> = names.iter().map(|name| name.to_string()).collect();
+        let selection = InterfaceSelection::parse(&names, false)?;
+        let selected = selection.resolve_among(available(), default)?;
+        Ok(selected
+            .into_iter()
+            .map(|interface| interface.name)
+            .collect())
+    }
+
+    #[test]
+    fn selectors() {
+        let cases: [(&[&str], &[&str]); 12] = [
+            (&["eth0"], &["eth0"]),
+            (&["3"], &["eth1"]),
+            (&["eth*"], &["eth0", "eth1"]),
+            (&["e?h[!0]"], &["eth1"]),
+            (&["/^(lo|docker)/"], &["lo", "docker0"]),
+            (&["02:00:00:00:00:04"], &["wlan0"]),
+            (&["10.0.0.1"], &["eth0"]),
+            (&["fe80::2"], &["eth0"]),
+            (&["::1"], &["lo"]),
+            (&["any"], &["lo", "eth0", "wlan0", "docker0"]),
+            (&["default"], &["wlan0"]),
+            // each interface once, in index order
+            (
+                &["wlan0", "/./", "4"],
+                &["lo", "eth0", "eth1", "wlan0", "docker0"],
+            ),
+        ];
+
+        for (names, expected) in cases {
+            assert_eq!(
+                select(names, Some("wlan0")).unwrap(),
+                expected,
+                "{:?}",
+                names
+            );
+        }
+    }
+
+    #[test]
+    fn unmatched_selectors_fail() {
+        let unmatched = |name: &str| {
+            format!(
+                "no interface matches '{}'; `ls` lists the available ones",
+                name
+            )
+        };
+
+        for name in [
+            "eth9",
+            "9",
+            "ppp*",
+            "/^ppp/",
+            "02:00:00:00:00:09",
+            "10.0.0.9",
+        ] {
+            assert_eq!(select(&["eth0", name], None), Err(unmatched(name)));
+        }
+        assert_eq!(
+            select(&["default"], None),
+            Err(String::from("there is no default route"))
+        );
+        assert!(select(&["/(/"], None)
+            .unwrap_err()
+            .starts_with("bad interface pattern '/(/'"));
+        assert!(select(&[], None)
+            .unwrap_err()
+            .starts_with("no interface given"));
+
+        // with hot plugging the missing ones may still come
+        let names = [String::from("eth9")];
+        let selection = InterfaceSelection::parse(&names, true).unwrap();
+        assert!(selection
+            .resolve_among(available(), None)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn globs() {
+        assert_eq!(glob_to_regex("eth*"), "^eth.*$");
+        assert_eq!(glob_to_regex("en?s[0-9]"), "^en.s[0-9]$");
+        assert_eq!(glob_to_regex("br-[!a]."), "^br\\-[^a]\\.$");
+    }
+}
@@ -0,0 +1,629 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt::Write as _;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::time::SystemTime;
+
+use pnet::util::MacAddr;
+
+use crate::network_test::datalink::EthernetIIFrame;
+use crate::network_test::discover::Vendors;
+use crate::network_test::fingerprint::Signatures;
+use crate::network_test::flow::{self, FlowKey, TCP_ACK, TCP_SYN};
+use crate::network_test::network::{ARPKind, PacketType};
+use crate::network_test::transport::TransportSegment;
+
+const ETHER_TYPE_LLDP: u16 = 0x88cc;
+/// Compression pointers followed in one DNS name before giving up on it.
+const MAX_NAME_JUMPS: usize = 16;
+
+/// Everything learned about one MAC address.
+#[derive(Debug, Clone)]
+struct Host {
+    ips: BTreeSet<IpAddr>,
+    /// names the host gave itself, through DHCP or LLDP
+    names: BTreeSet<String>,
+    /// DHCP vendor classes, LLDP system descriptions and ports
+    details: BTreeSet<String>,
+    /// the protocols that told about it
+    sources: BTreeSet<&'static str>,
+    first_seen: SystemTime,
+    last_seen: SystemTime,
+}
+
+impl Host {
+    fn new(timestamp: SystemTime) -> Self {
+        Host {
+            ips: BTreeSet::new(),
+            names: BTreeSet::new(),
+            details: BTreeSet::new(),
+            sources: BTreeSet::new(),
+            first_seen: timestamp,
+            last_seen: timestamp,
+        }
+    }
+}
+
+/// The hosts of a segment, learned without sending anything: who owns
+/// which address from ARP, DHCP, NDP and the name services, what they are
+/// called from DNS, mDNS, LLMNR, NBNS and LLDP, and which TCP ports
+/// answered a SYN.
+#[derive(Debug, Default)]
+pub struct Inventory {
+    hosts: BTreeMap<MacAddr, Host>,
+    /// names the name services gave each address
+    names: HashMap<IpAddr, BTreeSet<String>>,
+    /// TCP ports each address answered a SYN on
+    ports: HashMap<IpAddr, BTreeSet<u16>>,
+    /// the stacks each address's SYNs and SYN-ACKs look like
+    systems: HashMap<IpAddr, BTreeSet<String>>,
+    /// the link types the MSS of those packets implies
+    links: HashMap<IpAddr, BTreeSet<String>>,
+    /// the MAC an address's SYNs and SYN-ACKs came from, which is a router for remote ones
+    seen_via: HashMap<IpAddr, MacAddr>,
+    signatures: Option<Signatures>,
+}
+
+impl Inventory {
+    /// Fingerprints SYNs and SYN-ACKs against `signatures` if there are any.
+    pub fn new(signatures: Option<Signatures>) -> Self {
+        Inventory {
+            signatures,
+            ..Self::default()
+        }
+    }
+
+    /// Learns from one frame; `data` is the frame as captured.
+    pub fn update(&mut self, frame: &EthernetIIFrame, data: &[u8], timestamp: SystemTime) {
+        let mac = frame.get_source();
+        // group addresses never send, and all zeroes is a placeholder
+        if mac.0 & 0x01 != 0 || mac == MacAddr::zero() {
+            return;
+        }
+        let host = self
+            .hosts
+            .entry(mac)
+            .or_insert_with(|| Host::new(timestamp));
+        host.first_seen = host.first_seen.min(timestamp);
+        host.last_seen = host.last_seen.max(timestamp);
+
+        match frame.get_network_packet() {
+            PacketType::ARP(packet) => {
+                if let (Some(ip), Some(sender)) = (packet.get_sender_ip(), packet.get_sender_mac())
+                {
+                    if packet.kind() != ARPKind::Probe && !ip.is_unspecified() {
+                        self.own(sender, IpAddr::V4(ip), "ARP", timestamp);
+                    }
+                }
+            }
+            PacketType::UNDEFINED(ETHER_TYPE_LLDP) => {
+                if let Some(lldp) = data.get(14..) {
+                    self.lldp(mac, lldp, timestamp);
+                }
+            }
+            _ => self.ip(frame, mac, timestamp),
+        }
+    }
+
+    /// Binds `ip` to `mac`, as told by `source`.
+    fn own(&mut self, mac: MacAddr, ip: IpAddr, source: &'static str, timestamp: SystemTime) {
+        let host = self
+            .hosts
+            .entry(mac)
+            .or_insert_with(|| Host::new(timestamp));
+        host.ips.insert(ip);
+        host.sources.insert(source);
+    }
+
+    fn name(&mut self, ip: IpAddr, name: String) {
+        let name = name.trim_end_matches('.').to_string();
+        if !name.is_empty() {
+            self.names.entry(ip).or_default().insert(name);
+        }
+    }
+
+    fn ip(&mut self, frame: &EthernetIIFrame, mac: MacAddr, timestamp: SystemTime) {
+        let Some(key) = FlowKey::from_frame(frame) else {
+            return;
+        };
+
+        // link-local addresses never cross a router
+        let link_local = match key.source {
+            IpAddr::V4(ip) => ip.is_link_local(),
+            IpAddr::V6(ip) => ip.segments()[0] & 0xffc0 == 0xfe80,
+        };
+        if link_local {
+            self.own(mac, key.source, "IP", timestamp);
+        }
+
+        match key.protocol {
+            6 => {
+                let flags = flow::tcp_flags(frame).unwrap_or(0);
+                if flags & (TCP_SYN | TCP_ACK) == TCP_SYN | TCP_ACK {
+                    self.ports
+                        .entry(key.source)
+                        .or_default()
+                        .insert(key.source_port);
+                    self.seen_via.insert(key.source, mac);
+                }
+                let fingerprint = self
+                    .signatures
+                    .as_ref()
+                    .and_then(|signatures| signatures.classify(frame));
+                if let Some(fingerprint) = fingerprint {
+                    self.seen_via.insert(key.source, mac);
+                    if let Some(link) = &fingerprint.link {
+                        self.links
+                            .entry(key.source)
+                            .or_default()
+                            .insert(link.clone());
+                    }
+                    self.systems
+                        .entry(key.source)
+                        .or_default()
+                        .insert(fingerprint.to_string());
+                }
+            }
+            17 => {
+                let Some(payload) = udp_payload(frame) else {
+                    return;
+                };
+                let ports = (key.source_port, key.destination_port);
+                match ports {
+                    (67, 68) | (68, 67) => self.dhcp(mac, key.source, payload, timestamp),
+                    (53, _) => self.names_from(payload),
+                    (5353, _) | (5355, _) | (137, _) => {
+                        let source = match ports.0 {
+                            5353 => "mDNS",
+                            5355 => "LLMNR",
+                            _ => "NBNS",
+                        };
+                        if !key.source.is_unspecified() {
+                            self.own(mac, key.source, source, timestamp);
+                        }
+                        self.names_from(payload);
+                    }
+                    _ => {}
+                }
+            }
+            58 => {
+                if let PacketType::IPv6(packet) = frame.get_network_packet() {
+                    // neighbour solicitations and advertisements come from their owner
+                    let is_ndp = matches!(packet.get_payload().first(), Some(135 | 136));
+                    if is_ndp && !key.source.is_unspecified() {
+                        self.own(mac, key.source, "NDP", timestamp);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn dhcp(&mut self, mac: MacAddr, source: IpAddr, payload: &[u8], timestamp: SystemTime) {
+        let Some(message) = DhcpMessage::parse(payload) else {
+            return;
+        };
+
+        if message.reply {
+            // the server answers from its own address
+            if message.server == Some(source) {
+                self.own(mac, source, "DHCP", timestamp);
+            }
+            if message.kind == Some(5) && !message.your_ip.is_unspecified() {
+                self.own(
+                    message.client,
+                    IpAddr::V4(message.your_ip),
+                    "DHCP",
+                    timestamp,
+                );
+            }
+            return;
+        }
+
+        let host = self
+            .hosts
+            .entry(message.client)
+            .or_insert_with(|| Host::new(timestamp));
+        host.sources.insert("DHCP");
+        if !message.client_ip.is_unspecified() {
+            host.ips.insert(IpAddr::V4(message.client_ip));
+        }
+        host.names.extend(message.host_name);
+        host.details.extend(
+            message
+                .vendor_class
+                .map(|class| format!("DHCP vendor class {}", class)),
+        );
+    }
+
+    /// Takes the addresses and names out of a DNS-format message, which
+    /// mDNS, LLMNR and NBNS share.
+    fn names_from(&mut self, payload: &[u8]) {
+        for record in parse_dns(payload).unwrap_or_default() {
+            match record.data {
+                RecordData::Address(ip) => self.name(ip, record.name),
+                RecordData::Pointer(name) => {
+                    if let Some(ip) = reverse_address(&record.name) {
+                        self.name(ip, name);
+                    }
+                }
+                RecordData::NetBios(ips) => {
+                    let name = netbios_name(&record.name).unwrap_or(record.name);
+                    for ip in ips {
+                        self.name(IpAddr::V4(ip), name.clone());
+                    }
+                }
+            }
+        }
+    }
+
+    fn lldp(&mut self, mac: MacAddr, mut data: &[u8], timestamp: SystemTime) {
+        let mut addresses = vec![];
+        let host = self
+            .hosts
+            .entry(mac)
+            .or_insert_with(|| Host::new(timestamp));
+        host.sources.insert("LLDP");
+
+        while data.len() >= 2 {
+            let header = u16::from_be_bytes([data[0], data[1]]);
+            let (kind, length) = (header >> 9, (header & 0x01ff) as usize);
+            let Some(value) = data.get(2..2 + length) else {
+                break;
+            };
+            let text = || String::from_utf8_lossy(value).trim().to_string();
+            match kind {
+                0 => break,
+                // port ID whose subtype says it is a name
+                2 if matches!(value.first(), Some(5 | 7)) => {
+                    host.details.insert(format!(
+                        "LLDP port {}",
+                        String::from_utf8_lossy(&value[1..])
+                    ));
+                }
+                4 => {
+                    host.details.insert(format!("LLDP port {}", text()));
+                }
+                5 => {
+                    host.names.insert(text());
+                }
+                6 => {
+                    host.details.insert(text());
+                }
+                // management address: length, subtype, address
+                8 if value.len() >= 2 => match (value[1], &value[2..]) {
+                    (1, [a, b, c, d, ..]) if value[0] == 5 => {
+                        addresses.push(IpAddr::V4(Ipv4Addr::new(*a, *b, *c, *d)))
+                    }
+                    (2, rest) if value[0] == 17 && rest.len() >= 16 => {
+                        let octets = <[u8; 16]>::try_from(&rest[..16]).unwrap();
+                        addresses.push(IpAddr::V6(Ipv6Addr::from(octets)))
+                    }
+                    _ => {}
+                },
+                _ => {}
+            }
+            data = &data[2 + length..];
+        }
+
+        for ip in addresses {
+            self.own(mac, ip, "LLDP", timestamp);
+        }
+    }
+
+    /// One block per host, then the remote addresses that sent or answered SYNs.
+    pub fn report(&self, vendors: &Vendors) -> String {
+        let mut context = format!("{} hosts\n", self.hosts.len());
+        let time = |time: SystemTime| {
+            chrono::DateTime::<chrono::Local>::from(time)
+                .format("%Y-%m-%d %H:%M:%S")
+                .to_string()
+        };
+        let join = |items: Vec<String>| items.join(", ");
+
+        for (mac, host) in &self.hosts {
+            let _ = writeln!(context, "\n{}  {}", mac, vendors.lookup(*mac));
+            if !host.ips.is_empty() {
+                let ips = host.ips.iter().map(|ip| ip.to_string()).collect();
+                let _ = writeln!(context, "    addresses  {}", join(ips));
+            }
+
+            let mut names = host.names.clone();
+            for ip in &host.ips {
+                names.extend(self.names.get(ip).into_iter().flatten().cloned());
+            }
+            if !names.is_empty() {
+                let _ = writeln!(
+                    context,
+                    "    names      {}",
+                    join(names.into_iter().collect())
+                );
+            }
+            self.write_tcp(&mut context, host.ips.iter());
+            for detail in &host.details {
+                let _ = writeln!(context, "    detail     {}", detail);
+            }
+            let _ = write!(
+                context,
+                "    seen       {} to {}",
+                time(host.first_seen),
+                time(host.last_seen)
+            );
+            if !host.sources.is_empty() {
+                let sources: Vec<String> = host.sources.iter().map(|s| s.to_string()).collect();
+                let _ = write!(context, " (from {})", join(sources));
+            }
+            context.push('\n');
+        }
+
+        let owned: BTreeSet<&IpAddr> = self.hosts.values().flat_map(|host| &host.ips).collect();
+        let remote: BTreeSet<&IpAddr> = self
+            .ports
+            .keys()
+            .chain(self.systems.keys())
+            .filter(|ip| !owned.contains(ip))
+            .collect();
+        if !remote.is_empty() {
+            context.push_str("\nother addresses\n");
+            for ip in remote {
+                let _ = write!(context, "\n{}", ip);
+                if let Some(names) = self.names.get(ip) {
+                    let names: Vec<String> = names.iter().cloned().collect();
+                    let _ = write!(context, " ({})", join(names));
+                }
+                if let Some(via) = self.seen_via.get(ip) {
+                    let _ = write!(context, " via {}", via);
+                }
+                context.push('\n');
+                self.write_tcp(&mut context, [ip].into_iter());
+            }
+        }
+
+        context
+    }
+
+    /// The open ports, operating systems and link types of some addresses.
+    fn write_tcp<'a>(&self, context: &mut String, ips: impl Iterator<Item = &'a IpAddr> + Clone) {
+        let collect = |map: &HashMap<IpAddr, BTreeSet<String>>| -> BTreeSet<String> {
+            ips.clone()
+                .flat_map(|ip| map.get(ip).into_iter().flatten().cloned())
+                .collect()
+        };
+        let ports: BTreeSet<u16> = ips
+            .clone()
+            .flat_map(|ip| self.ports.get(ip).into_iter().flatten().copied())
+            .collect();
+
+        if !ports.is_empty() {
+            let ports: Vec<String> = ports.iter().map(|port| port.to_string()).collect();
+            let _ = writeln!(context, "    open TCP   {}", ports.join(", "));
+        }
+        for system in collect(&self.systems) {
+            let _ = writeln!(context, "    OS         {}", system);
+        }
+        for link in collect(&self.links) {
+            let _ = writeln!(context, "    link       {}", link);
+        }
+    }
+}
+
+/// The UDP payload of a frame, for IPv4 and IPv6 alike.
+fn udp_payload(frame: &EthernetIIFrame) -> Option<&[u8]> {
+    match frame.get_network_packet() {
+        PacketType::IPv4(packet) => match packet.get_transport_segment() {
+            TransportSegment::UDP(segment) => Some(segment.get_data()),
+            _ => None,
+        },
+        PacketType::IPv6(packet) if packet.get_next_header() == 17 => packet.get_payload().get(8..),
+        _ => None,
+    }
+}
+
+/// The parts of a DHCP message that say who a client is.
+#[derive(Debug)]
+struct DhcpMessage {
+    reply: bool,
+    /// option 53: 1 discover, 3 request, 5 ack, ...
+    kind: Option<u8>,
+    client: MacAddr,
+    client_ip: Ipv4Addr,
+    your_ip: Ipv4Addr,
+    server: Option<IpAddr>,
+    host_name: Option<String>,
+    vendor_class: Option<String>,
+}
+
+impl DhcpMessage {
+    fn parse(payload: &[u8]) -> Option<Self> {
+        // the fixed BOOTP part, then the magic cookie
+        if payload.len() < 240 || payload[236..240] != [99, 130, 83, 99] || payload[2] != 6 {
+            return None;
+        }
+        let address = |at: usize| {
+            Ipv4Addr::new(
+                payload[at],
+                payload[at + 1],
+                payload[at + 2],
+                payload[at + 3],
+            )
+        };
+        let chaddr = &payload[28..34];
+
+        let mut message = DhcpMessage {
+            reply: payload[0] == 2,
+            kind: None,
+            client: MacAddr::new(
+                chaddr[0], chaddr[1], chaddr[2], chaddr[3], chaddr[4], chaddr[5],
+            ),
+            client_ip: address(12),
+            your_ip: address(16),
+            server: None,
+            host_name: None,
+            vendor_class: None,
+        };
+
+        let mut options = &payload[240..];
+        while let Some(&code) = options.first() {
+            match code {
+                0 => {
+                    options = &options[1..];
+                    continue;
+                }
+                255 => break,
+                _ => {}
+            }
+            let length = *options.get(1)? as usize;
+            let value = options.get(2..2 + length)?;
+            let text = || {
+                String::from_utf8_lossy(value)
+                    .trim_end_matches('\0')
+                    .to_string()
+            };
+            match code {
+                12 => message.host_name = Some(text()),
+                53 => message.kind = value.first().copied(),
+                54 if length == 4 => {
+                    message.server = Some(IpAddr::V4(Ipv4Addr::new(
+                        value[0], value[1], value[2], value[3],
+                    )))
+                }
+                60 => message.vendor_class = Some(text()),
+                // client FQDN: flags, two obsolete codes, then the name in ASCII
+                81 if length > 3 && value[0] & 0x04 == 0 && message.host_name.is_none() => {
+                    message.host_name = Some(String::from_utf8_lossy(&value[3..]).to_string())
+                }
+                _ => {}
+            }
+            options = &options[2 + length..];
+        }
+
+        Some(message)
+    }
+}
+
+#[derive(Debug)]
+enum RecordData {
+    Address(IpAddr),
+    Pointer(String),
+    /// NBNS name records, one address per flags and address pair
+    NetBios(Vec<Ipv4Addr>),
+}
+
+#[derive(Debug)]
+struct Record {
+    name: String,
+    data: RecordData,
+}
+
+/// The A, AAAA, PTR and NB records of every section of a DNS-format message.
+fn parse_dns(message: &[u8]) -> Option<Vec<Record>> {
+    let count = |at: usize| {
+        Some(u16::from_be_bytes([
+            *message.get(at)?,
+            *message.get(at + 1)?,
+        ]))
+    };
+    let questions = count(4)?;
+    let records = count(6)? as usize + count(8)? as usize + count(10)? as usize;
+
+    let mut at = 12;
+    for _ in 0..questions {
+        at = read_name(message, at)?.1 + 4;
+    }
+
+    let mut found = vec![];
+    for _ in 0..records {
+        let (name, end) = read_name(message, at)?;
+        let header = message.get(end..end + 10)?;
+        let kind = u16::from_be_bytes([header[0], header[1]]);
+        let length = u16::from_be_bytes([header[8], header[9]]) as usize;
+        let data = message.get(end + 10..end + 10 + length)?;
+        at = end + 10 + length;
+
+        let data = match (kind, data.len()) {
+            (1, 4) => RecordData::Address(IpAddr::V4(Ipv4Addr::new(
+                data[0], data[1], data[2], data[3],
+            ))),
+            (28, 16) => RecordData::Address(IpAddr::V6(Ipv6Addr::from(
+                <[u8; 16]>::try_from(data).unwrap(),
+            ))),
+            (12, _) => match read_name(message, end + 10) {
+                Some((target, _)) => RecordData::Pointer(target),
+                None => continue,
+            },
+            (32, _) => RecordData::NetBios(
+                data.chunks_exact(6)
+                    .map(|entry| Ipv4Addr::new(entry[2], entry[3], entry[4], entry[5]))
+                    .collect(),
+            ),
+            _ => continue,
+        };
+        found.push(Record { name, data });
+    }
+
+    Some(found)
+}
+
+/// The name at `at`, following compression pointers, and where the data after it starts.
+fn read_name(message: &[u8], mut at: usize) -> Option<(String, usize)> {
+    let mut labels: Vec<String> = vec![];
+    let mut end = None;
+    let mut jumps = 0;
+
+    loop {
+        let length = *message.get(at)? as usize;
+        match length {
+            0 => break,
+            _ if length & 0xc0 == 0xc0 => {
+                let pointer = ((length & 0x3f) << 8) | *message.get(at + 1)? as usize;
+                end.get_or_insert(at + 2);
+                jumps += 1;
+                if jumps > MAX_NAME_JUMPS {
+                    return None;
+                }
+                at = pointer;
+            }
+            _ => {
+                let label = message.get(at + 1..at + 1 + length)?;
+                labels.push(String::from_utf8_lossy(label).to_string());
+                at += 1 + length;
+            }
+        }
+    }
+
+    Some((labels.join("."), end.unwrap_or(at + 1)))
+}
+
+/// The address a PTR owner name such as `4.3.2.1.in-addr.arpa` stands for.
+fn reverse_address(name: &str) -> Option<IpAddr> {
+    let name = name.to_ascii_lowercase();
+    if let Some(digits) = name.strip_suffix(".in-addr.arpa") {
+        let mut octets: Vec<u8> = digits
+            .split('.')
+            .map(|digit| digit.parse().ok())
+            .collect::<Option<_>>()?;
+        octets.reverse();
+        let octets: [u8; 4] = octets.try_into().ok()?;
+        return Some(IpAddr::V4(Ipv4Addr::from(octets)));
+    }
+
+    let nibbles = name.strip_suffix(".ip6.arpa")?;
+    let digits: String = nibbles.split('.').rev().collect();
+    (digits.len() == 32)
+        .then(|| u128::from_str_radix(&digits, 16).ok())
+        .flatten()
+        .map(|address| IpAddr::V6(Ipv6Addr::from(address)))
+}
+
+/// Decodes the first-level encoding of a NetBIOS name: 32 letters, each
+/// the high or low nibble of a byte plus `A`, the last byte the service.
+fn netbios_name(encoded: &str) -> Option<String> {
+    let label = encoded.split('.').next()?.as_bytes();
+    if label.len() != 32 || !label.iter().all(|c| (b'A'..=b'P').contains(c)) {
+        return None;
+    }
+    let bytes: Vec<u8> = label
+        .chunks_exact(2)
+        .map(|pair| ((pair[0] - b'A') << 4) | (pair[1] - b'A'))
+        .collect();
+    Some(String::from_utf8_lossy(&bytes[..15]).trim_end().to_string())
+}
@@ -0,0 +1,371 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::network_test::datalink::EthernetIIFrame;
+use crate::network_test::network::PacketType;
+use crate::network_test::stats::Counter;
+use crate::network_test::tpacket::KernelStats;
+use crate::network_test::util;
+
+/// Prefix of every exported metric name.
+const NAMESPACE: &str = "network_test";
+
+#[derive(Debug, Default)]
+struct InterfaceMetrics {
+    frames: Counter,
+    /// frames that could not be dissected
+    malformed: u64,
+    /// reads the channel failed
+    read_errors: u64,
+    ipv4_checksum_errors: u64,
+    /// what the kernel counted for the capture sockets
+    socket: KernelStats,
+    ether_types: BTreeMap<u16, Counter>,
+    ip_protocols: BTreeMap<u8, Counter>,
+}
+
+/// Capture counters exposed in the Prometheus text format.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    interfaces: Mutex<BTreeMap<String, InterfaceMetrics>>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Makes `interface` show up with zeroes before its first frame.
+    pub fn register(&self, interface: &str) {
+        let mut interfaces = self.interfaces.lock().unwrap();
+        interfaces.entry(interface.to_string()).or_default();
+    }
+
+    /// Counts one dissected frame; `data` is the frame as captured.
+    pub fn record_frame(&self, interface: &str, data: &[u8], frame: &EthernetIIFrame) {
+        let length = data.len();
+        let mut interfaces = self.interfaces.lock().unwrap();
+        let metrics = interfaces.entry(interface.to_string()).or_default();
+
+        metrics.frames.add(length);
+        metrics
+            .ether_types
+            .entry(frame.get_ether_type())
+            .or_default()
+            .add(length);
+
+        let protocol = match frame.get_network_packet() {
+            PacketType::IPv4(packet) => {
+                // the header length sits in the low nibble of the first byte
+                let header = data.get(14..).and_then(|ip| {
+                    let length = (*ip.first()? & 0x0f) as usize * 4;
+                    ip.get(..length)
+                });
+                if header.is_some_and(|header| util::internet_checksum(header) != 0) {
+                    metrics.ipv4_checksum_errors += 1;
+                }
+                Some(packet.get_protocol())
+            }
+            PacketType::IPv6(packet) => Some(packet.get_next_header()),
+            _ => None,
+        };
+        if let Some(protocol) = protocol {
+            metrics
+                .ip_protocols
+                .entry(protocol)
+                .or_default()
+                .add(length);
+        }
+    }
+
+    pub fn record_malformed(&self, interface: &str) {
+        let mut interfaces = self.interfaces.lock().unwrap();
+        interfaces
+            .entry(interface.to_string())
+            .or_default()
+            .malformed += 1;
+    }
+
+    pub fn record_read_error(&self, interface: &str) {
+        let mut interfaces = self.interfaces.lock().unwrap();
+        interfaces
+            .entry(interface.to_string())
+            .or_default()
+            .read_errors += 1;
+    }
+
+    /// Adds what the kernel counted for a capture socket since last asked.
+    pub fn record_kernel(&self, interface: &str, stats: KernelStats) {
+        let mut interfaces = self.interfaces.lock().unwrap();
+        let socket = &mut interfaces.entry(interface.to_string()).or_default().socket;
+        socket.packets += stats.packets;
+        socket.drops += stats.drops;
+        socket.freezes += stats.freezes;
+    }
+
+    /// Every metric in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let interfaces = self.interfaces.lock().unwrap();
+        let mut out = String::new();
+
+        let mut family = |name: &str, help: &str, samples: Vec<(String, u64)>| {
+            let _ = writeln!(out, "# HELP {}_{} {}", NAMESPACE, name, help);
+            let _ = writeln!(out, "# TYPE {}_{} counter", NAMESPACE, name);
+            for (labels, value) in samples {
+                let _ = writeln!(out, "{}_{}{{{}}} {}", NAMESPACE, name, labels, value);
+            }
+        };
+        let per_interface = |value: &dyn Fn(&InterfaceMetrics) -> u64| {
+            interfaces
+                .iter()
+                .map(|(name, metrics)| (format!("interface=\"{}\"", escape(name)), value(metrics)))
+                .collect::<Vec<_>>()
+        };
+
+        family(
+            "packets_total",
+            "Frames captured and dissected.",
+            per_interface(&|m| m.frames.packets),
+        );
+        family(
+            "bytes_total",
+            "Bytes of frames captured and dissected.",
+            per_interface(&|m| m.frames.bytes),
+        );
+        family(
+            "parse_errors_total",
+            "Frames that could not be dissected.",
+            per_interface(&|m| m.malformed),
+        );
+        family(
+            "ipv4_checksum_errors_total",
+            "IPv4 headers whose checksum did not verify. TCP, UDP and ICMP checksums are not checked, since checksum offload leaves them unfilled in outgoing frames.",
+            per_interface(&|m| m.ipv4_checksum_errors),
+        );
+        family(
+            "read_errors_total",
+            "Reads the capture channel failed.",
+            per_interface(&|m| m.read_errors),
+        );
+        family(
+            "socket_drops_total",
+            "Frames the kernel dropped because the capture socket's buffer or ring was full, from PACKET_STATISTICS.",
+            per_interface(&|m| m.socket.drops),
+        );
+        family(
+            "socket_queue_freezes_total",
+            "Times the capture socket's ring filled up and the kernel stopped queueing to it; 0 for the socket backend.",
+            per_interface(&|m| m.socket.freezes),
+        );
+        family(
+            "kernel_rx_dropped_total",
+            "Frames the interface itself dropped, before any capture socket saw them: rx_dropped read from sysfs at scrape time. Capture losses are socket_drops_total.",
+            interfaces
+                .keys()
+                .filter_map(|name| {
+                    let dropped = std::fs::read_to_string(format!(
+                        "/sys/class/net/{}/statistics/rx_dropped",
+                        name
+                    ))
+                    .ok()?;
+                    Some((
+                        format!("interface=\"{}\"", escape(name)),
+                        dropped.trim().parse().ok()?,
+                    ))
+                })
+                .collect(),
+        );
+
+        let ether_types = |value: fn(&Counter) -> u64| {
+            interfaces
+                .iter()
+                .flat_map(|(name, metrics)| {
+                    metrics
+                        .ether_types
+                        .iter()
+                        .map(move |(ether_type, counter)| {
+                            (
+                                format!(
+                                    "interface=\"{}\",ethertype=\"0x{:04x}\"",
+                                    escape(name),
+                                    ether_type
+                                ),
+                                value(counter),
+                            )
+                        })
+                })
+                .collect::<Vec<_>>()
+        };
+        family(
+            "ethertype_packets_total",
+            "Frames per EtherType.",
+            ether_types(|c| c.packets),
+        );
+        family(
+            "ethertype_bytes_total",
+            "Bytes per EtherType.",
+            ether_types(|c| c.bytes),
+        );
+
+        let ip_protocols = |value: fn(&Counter) -> u64| {
+            interfaces
+                .iter()
+                .flat_map(|(name, metrics)| {
+                    metrics.ip_protocols.iter().map(move |(protocol, counter)| {
+                        (
+                            format!("interface=\"{}\",protocol=\"{}\"", escape(name), protocol),
+                            value(counter),
+                        )
+                    })
+                })
+                .collect::<Vec<_>>()
+        };
+        family(
+            "ip_protocol_packets_total",
+            "IPv4 and IPv6 packets per IP protocol number.",
+            ip_protocols(|c| c.packets),
+        );
+        family(
+            "ip_protocol_bytes_total",
+            "Bytes of IPv4 and IPv6 packets per IP protocol number.",
+            ip_protocols(|c| c.bytes),
+        );
+
+        out
+    }
+}
+
+fn escape(label: &str) -> String {
+    label
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Serves `GET /metrics` on `listen` from a background thread and returns
+/// the address it listens on, which tells the port if `listen` left it 0.
+pub fn serve(listen: SocketAddr, metrics: Arc<Metrics>) -> io::Result<SocketAddr> {
+    let listener = TcpListener::bind(listen)?;
+    let address = listener.local_addr()?;
+    println!("metrics on http://{}/metrics", address);
+
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            if let Err(e) = respond(stream, &metrics) {
+                eprintln!("cannot answer a metrics request: {}", e);
+            }
+        }
+    });
+
+    Ok(address)
+}
+
+fn respond(stream: TcpStream, metrics: &Metrics) -> io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut reader = BufReader::new(&stream);
+
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+    // skip the headers, nothing in them matters here
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 2 {
+        line.clear();
+    }
+
+    let mut parts = request.split_whitespace();
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some(path)) if path == "/metrics" || path.starts_with("/metrics?") => {
+            ("200 OK", metrics.render())
+        }
+        (Some("GET"), _) => ("404 Not Found", String::from("try /metrics\n")),
+        _ => ("405 Method Not Allowed", String::new()),
+    };
+
+    let mut stream = &stream;
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network_test::flow::TCP_SYN;
+    use crate::network_test::util::frames::{Ipv4, Tcp};
+    use std::io::Read;
+
+    /// A SYN from the client, its IPv4 header checksum set to `checksum`.
+    fn syn(checksum: [u8; 2]) -> Vec<u8> {
+        let ipv4 = Ipv4 {
+            checksum,
+            ..Ipv4::default()
+        };
+        ipv4.frame(&Tcp::new(true, TCP_SYN).segment())
+    }
+
+    fn scrape(address: SocketAddr, path: &str) -> String {
+        let mut stream = TcpStream::connect(address).unwrap();
+        write!(stream, "GET {} HTTP/1.1\r\nHost: test\r\n\r\n", path).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn scrape_over_http() {
+        let metrics = Arc::new(Metrics::new());
+        metrics.register("idle0");
+
+        let good = syn([0x66, 0xcd]);
+        assert_eq!(util::internet_checksum(&good[14..34]), 0);
+        metrics.record_frame("test0", &good, &EthernetIIFrame::new(&good).unwrap());
+        let bad = syn([0x12, 0x34]);
+        metrics.record_frame("test0", &bad, &EthernetIIFrame::new(&bad).unwrap());
+        metrics.record_malformed("test0");
+        metrics.record_kernel(
+            "test0",
+            KernelStats {
+                packets: 10,
+                drops: 3,
+                freezes: 1,
+            },
+        );
+
+        let address = serve("127.0.0.1:0".parse().unwrap(), Arc::clone(&metrics)).unwrap();
+        let response = scrape(address, "/metrics");
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("Content-Type: text/plain; version=0.0.4"));
+        for line in [
+            "# TYPE network_test_packets_total counter",
+            "network_test_packets_total{interface=\"test0\"} 2",
+            "network_test_packets_total{interface=\"idle0\"} 0",
+            "network_test_bytes_total{interface=\"test0\"} 108",
+            "network_test_parse_errors_total{interface=\"test0\"} 1",
+            "network_test_ipv4_checksum_errors_total{interface=\"test0\"} 1",
+            "network_test_socket_drops_total{interface=\"test0\"} 3",
+            "network_test_socket_queue_freezes_total{interface=\"test0\"} 1",
+            "network_test_ethertype_packets_total{interface=\"test0\",ethertype=\"0x0800\"} 2",
+            "network_test_ip_protocol_bytes_total{interface=\"test0\",protocol=\"6\"} 108",
+        ] {
+            assert!(response.lines().any(|l| l == line), "missing {}", line);
+        }
+
+        assert!(scrape(address, "/").starts_with("HTTP/1.1 404 Not Found"));
+    }
+
+    #[test]
+    fn label_values_are_escaped() {
+        assert_eq!(escape("a\"b\\c\nd"), "a\\\"b\\\\c\\nd");
+    }
+}
@@ -1,147 +1,543 @@
+pub mod arp_watch;
+pub mod capture;
+pub mod collector;
 mod datalink;
+pub mod discover;
+pub mod export;
+pub mod fingerprint;
+pub mod flow;
+mod hexdump;
+pub mod interfaces;
+pub mod inventory;
+mod metrics;
 mod network;
+mod packet_socket;
+pub mod pcap;
+pub mod probe;
+pub mod rotation;
+pub mod stats;
+pub mod store;
+pub mod tcp_analysis;
+pub mod tpacket;
 mod transport;
+pub mod tui;
 mod util;
 
 extern crate pnet;
 
+use arp_watch::ArpWatch;
+use capture::{CaptureLimits, ChannelOptions, FrameHandler};
+use collector::FlowDecoder;
 use datalink::EthernetIIFrame;
-use pnet::datalink::Channel::Ethernet;
+use discover::Vendors;
+use export::{ExportFormat, FlowExporter};
+use fingerprint::Signatures;
+use flow::{FlowRecord, FlowSort, FlowTable, FlowTimeouts};
+pub use hexdump::HexDump;
+use interfaces::{InterfaceFilter, InterfaceSelection};
+use inventory::Inventory;
+use metrics::Metrics;
+use pcap::PcapReader;
 use pnet::datalink::NetworkInterface;
 use pnet::util::MacAddr;
+use rotation::{RotatingFile, RotationOptions};
+use stats::TrafficStats;
+use tcp_analysis::TcpAnalyzer;
 
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex, RwLock};
-use std::thread::{self, JoinHandle};
-use std::time::Duration;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
 
-use std::fs;
 use std::io::Write;
 
 // static mut THREAD_HANDLER: Vec<JoinHandle<()>> = vec![];
 // static mut PACKET_BOX: RwLock<HashMap<NetworkInterface, Vec<EthernetIIFrame>>> = RwLock::new(HashMap::new());
 
-pub fn get_interface_names() -> String {
-    let mut context = String::new();
-
-    for interface in pnet::datalink::interfaces() {
-        context.push_str(&format!(
-            "[ Name : {} ]\ndescript : {}\nmacAddr : {:?}\nips : {:?}\nflags : {}\n",
-            interface.name,
-            interface.description,
-            if let Some(mac_addr) = interface.mac {
-                mac_addr
-            } else {
-                MacAddr::default()
-            },
-            interface.ips,
-            interface.flags
-        ));
+/// Lists the interfaces `filter` lets through, as text or as JSON.
+pub fn get_interface_names(filter: &InterfaceFilter, json: bool) -> String {
+    let interfaces = interfaces::list(filter);
+    match json {
+        true => interfaces::format_json(&interfaces),
+        false => interfaces::format_text(&interfaces),
     }
+}
 
-    return context;
+/// How `read` prints each captured frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// one line per packet: time, src → dst, protocol, length, info
+    Summary,
+    /// every layer with named fields and hex offsets
+    Tree,
+    /// the raw `Debug` dump of `EthernetIIFrame`
+    Debug,
 }
 
-pub fn read_packet(interfaces: &[String], is_save: bool) {
-    let packet_box: Arc<RwLock<HashMap<NetworkInterface, Vec<EthernetIIFrame>>>> =
-        Arc::new(RwLock::new(HashMap::new()));
+/// Everything `read` needs besides the interface names.
+#[derive(Debug, Clone)]
+pub struct ReadOptions {
+    pub is_save: bool,
+    pub format: OutputFormat,
+    pub hex: HexDump,
+    pub rotation: RotationOptions,
+    pub limits: CaptureLimits,
+    /// serve Prometheus metrics on this address while capturing
+    pub metrics_listen: Option<SocketAddr>,
+    pub channel: ChannelOptions,
+}
 
-    let mut thread_handler: Vec<JoinHandle<()>> = vec![];
+pub fn read_packet(interfaces: &InterfaceSelection, options: ReadOptions) {
+    let started = Instant::now();
 
-    let interface_list = pnet::datalink::interfaces();
-    let interfaces = interface_list
-        .into_iter()
-        .filter(|x| interfaces.contains(&x.name));
+    let metrics = options.metrics_listen.map(|_| Arc::new(Metrics::new()));
+    if let (Some(listen), Some(metrics)) = (options.metrics_listen, metrics.as_ref()) {
+        if let Err(e) = metrics::serve(listen, Arc::clone(metrics)) {
+            eprintln!("cannot serve metrics on {}: {}", listen, e);
+            return;
+        }
+    }
 
-    interfaces.for_each(|interface| {
-        let map = Arc::clone(&packet_box);
+    // the workers of an interface share one set, made for the first of them
+    let mut analyzers = Arc::new(Mutex::new(Analyzers::default()));
+    let summary = match capture::run(
+        interfaces,
+        &options.channel,
+        options.limits,
+        metrics,
+        |interface, worker| {
+            if worker == 0 {
+                analyzers = Arc::new(Mutex::new(Analyzers::default()));
+            }
+            Box::new(PacketPrinter::new(
+                interface,
+                worker,
+                Arc::clone(&analyzers),
+                &options,
+                started,
+            ))
+        },
+        || {},
+    ) {
+        Ok(summary) => summary,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
 
-        println!("action thread for {:?}", &interface.name);
+    print!("\n{}", summary);
+    let _ = std::io::stdout().flush();
+}
 
-        let handle = thread::spawn(move || capture_packet(&interface, map, is_save));
+/// What `read` follows the traffic of one interface with. Fanout may
+/// split a connection over the workers, so they all share it.
+#[derive(Default)]
+struct Analyzers {
+    /// decodes flow exports seen on the wire, templates included
+    decoder: FlowDecoder,
+    /// follows TCP connections to flag retransmissions and measure RTT
+    tcp: TcpAnalyzer,
+    /// remembers ARP bindings to catch conflicts and spoofing
+    arp: ArpWatch,
+}
 
-        thread_handler.push(handle);
-    });
+/// Prints and saves the frames one worker of an interface reads for `read`.
+struct PacketPrinter {
+    interface: String,
+    format: OutputFormat,
+    hex: HexDump,
+    started: Instant,
+    log_file: Option<RotatingFile>,
+    analyzers: Arc<Mutex<Analyzers>>,
+}
 
-    if thread_handler.len() == 0 {
-        panic!("No interface be matched");
+impl PacketPrinter {
+    fn new(
+        interface: &NetworkInterface,
+        worker: usize,
+        analyzers: Arc<Mutex<Analyzers>>,
+        options: &ReadOptions,
+        started: Instant,
+    ) -> Self {
+        let mut log_file = None;
+        if options.is_save {
+            let mut prefix = format!(
+                "{}__{}",
+                interface.name.as_str().replace("\\", "_"),
+                std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .unwrap()
+                    .as_millis()
+            );
+            // workers start within the same millisecond, so each needs its own files
+            if options.channel.workers > 1 {
+                prefix.push_str(&format!("_w{}", worker));
+            }
+
+            log_file = match RotatingFile::new(prefix, options.rotation) {
+                Err(e) => panic!("{:?}", e),
+                Ok(f) => Some(f),
+            };
+        }
+
+        PacketPrinter {
+            interface: interface.name.clone(),
+            format: options.format,
+            hex: options.hex,
+            started,
+            log_file,
+            analyzers,
+        }
     }
+}
 
-    loop {
-        thread::sleep(Duration::from_millis(1000));
+impl FrameHandler for PacketPrinter {
+    fn handle(&mut self, timestamp: SystemTime, packet: &[u8], pc: EthernetIIFrame) {
+        //print man
+        let mut pac = match self.format {
+            OutputFormat::Summary => {
+                format!("{:>12.6} {}", self.started.elapsed().as_secs_f64(), pc)
+            }
+            OutputFormat::Tree => format!(
+                "\n{:.6} {}\n{:#}",
+                self.started.elapsed().as_secs_f64(),
+                self.interface,
+                pc
+            ),
+            OutputFormat::Debug => format!("\n{:?}", pc),
+        };
+        let mut analyzers = self.analyzers.lock().unwrap();
+        if let Some(analysis) = analyzers.tcp.analyze(&pc, timestamp) {
+            match self.format {
+                _ if analysis.is_empty() => {}
+                // the scaled window goes in place of win=, the findings after the line
+                OutputFormat::Summary => {
+                    pac = analysis.scale_summary(&pac);
+                    let findings = analysis.to_string();
+                    if !findings.is_empty() {
+                        pac.push(' ');
+                        pac.push_str(&findings);
+                    }
+                }
+                _ => pac.push_str(format!("\n{:#}", analysis).trim_end()),
+            }
+        }
+        for alert in analyzers.arp.observe(&pc, timestamp) {
+            match self.format {
+                OutputFormat::Summary => pac.push_str(&format!(" [{}]", alert)),
+                _ => pac.push_str(&format!("\n{}", alert)),
+            }
+        }
+        if let Some((exporter, data)) = collector::export_payload(&pc) {
+            match (analyzers.decoder.decode(exporter, data), self.format) {
+                (Ok(export), OutputFormat::Summary) => pac.push_str(&format!(" [{}]", export)),
+                (Ok(export), _) => pac.push_str(format!("\n{:#}", export).trim_end()),
+                (Err(e), OutputFormat::Summary) => pac.push_str(&format!(" [flow export: {}]", e)),
+                (Err(e), _) => pac.push_str(&format!("\nflow export: {}", e)),
+            }
+        }
+        drop(analyzers);
+        if self.hex != HexDump::Off {
+            pac.push('\n');
+            pac.push_str(&hexdump::dump(packet, &pc.regions(), self.hex));
+        }
+        println!("{}", pac);
+
+        if let Some(log_file) = self.log_file.as_mut() {
+            if let Err(e) = log_file.write_record(&pac) {
+                eprintln!("cannot write packet data to this file: {}", e);
+            }
+        }
+    }
+
+    fn finish(&mut self) {
+        if let Some(log_file) = self.log_file.take() {
+            if let Err(e) = log_file.finish() {
+                eprintln!("cannot close the log file: {}", e);
+            }
+        }
     }
 }
 
-fn capture_packet(
-    interface: &NetworkInterface,
-    map: Arc<RwLock<HashMap<NetworkInterface, Vec<EthernetIIFrame>>>>,
-    is_save: bool,
-) {
-    // Create a new channel, dealing with layer 2 packets
-    let (mut _tx, mut rx) = match pnet::datalink::channel(&interface, Default::default()) {
-        Ok(Ethernet(tx, rx)) => (tx, rx),
-        Ok(_exception) => panic!("Unhandled channel type"),
+/// Everything `flows` needs besides the interface names.
+#[derive(Debug, Clone)]
+pub struct FlowOptions {
+    pub limits: CaptureLimits,
+    pub timeouts: FlowTimeouts,
+    pub sort: FlowSort,
+    pub top: Option<usize>,
+    /// print the table this often while capturing
+    pub interval: Option<Duration>,
+    /// send finished flows to this collector
+    pub export: Option<(SocketAddr, ExportFormat)>,
+}
+
+pub fn track_flows(interfaces: &InterfaceSelection, options: FlowOptions) {
+    let table = Arc::new(Mutex::new(FlowTable::new(options.timeouts)));
+    let mut finished: Vec<FlowRecord> = vec![];
+    let mut last_expire = Instant::now();
+    let mut last_print = Instant::now();
+
+    let mut exporter =
+        options.export.and_then(
+            |(collector, format)| match FlowExporter::new(collector, format) {
+                Ok(exporter) => Some(exporter),
+                Err(e) => {
+                    eprintln!("cannot export flows to {}: {}", collector, e);
+                    None
+                }
+            },
+        );
+    let mut export = move |flows: &[FlowRecord]| {
+        if let Some(exporter) = exporter.as_mut() {
+            if let Err(e) = exporter.export(flows) {
+                eprintln!("cannot export flows: {}", e);
+            }
+        }
+    };
+
+    let print = |finished: &[FlowRecord], table: &FlowTable| {
+        let flows: Vec<&FlowRecord> = finished.iter().chain(table.flows()).collect();
+        print!("{}", flow::format_table(&flows, options.sort, options.top));
+    };
+
+    let summary = match capture::run(
+        interfaces,
+        &ChannelOptions::default(),
+        options.limits,
+        None,
+        |_, _| {
+            Box::new(FlowCollector {
+                table: Arc::clone(&table),
+            })
+        },
+        || {
+            if last_expire.elapsed() >= Duration::from_secs(1) {
+                last_expire = Instant::now();
+                let expired = table.lock().unwrap().expire(SystemTime::now());
+                export(&expired);
+                finished.extend(expired);
+            }
+
+            if options
+                .interval
+                .is_some_and(|interval| last_print.elapsed() >= interval)
+            {
+                last_print = Instant::now();
+                println!();
+                print(&finished, &table.lock().unwrap());
+            }
+        },
+    ) {
+        Ok(summary) => summary,
         Err(e) => {
-            println!(
-                "An error occurred when creating the datalink channel: {}\ninterface: {}",
-                e, interface.description
-            );
+            eprintln!("{}", e);
             return;
         }
     };
 
-    let file_name = format!(
-        "{}__{}.txt",
-        interface.name.as_str().replace("\\", "_"),
-        std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_millis()
-    );
+    let remaining = table.lock().unwrap().drain();
+    export(&remaining);
+    finished.extend(remaining);
+    println!();
+    print(&finished, &FlowTable::default());
+    print!("\n{}", summary);
+}
+
+/// Feeds the frames of one interface into the shared flow table.
+struct FlowCollector {
+    table: Arc<Mutex<FlowTable>>,
+}
+
+impl FrameHandler for FlowCollector {
+    fn handle(&mut self, timestamp: SystemTime, _data: &[u8], frame: EthernetIIFrame) {
+        self.table.lock().unwrap().update(&frame, timestamp);
+    }
+}
 
-    println!("log file >> {}", file_name);
+/// Everything `stats` needs besides where the packets come from.
+#[derive(Debug, Clone)]
+pub struct StatsOptions {
+    pub limits: CaptureLimits,
+    /// talkers and ports listed per table
+    pub top: usize,
+    /// width of one time series row
+    pub bucket: Duration,
+}
 
-    let mut log_file = fs::OpenOptions::new()
-        .write(true)
-        .create(true)
-        .append(true)
-        .open(&file_name);
+pub fn live_stats(interfaces: &InterfaceSelection, options: StatsOptions) {
+    let stats = Arc::new(Mutex::new(TrafficStats::new(options.bucket)));
 
-    let mut log_file = match log_file {
-        Err(e) => panic!("{:?}", e),
-        Ok(f) => f,
+    let summary = match capture::run(
+        interfaces,
+        &ChannelOptions::default(),
+        options.limits,
+        None,
+        |_, _| {
+            Box::new(StatsCollector {
+                stats: Arc::clone(&stats),
+            })
+        },
+        || {},
+    ) {
+        Ok(summary) => summary,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
     };
 
-    loop {
-        match rx.next() {
-            Ok(packet) => {
-                // EthernetPacket::new(packet);
-                // let packet = EthernetPacket::new(packet).unwrap();
-                let custom_packet = datalink::EthernetIIFrame::new(packet);
-                match custom_packet {
-                    Some(pc) => {
-                        //print man
-                        let pac = format!("\n{:?}", pc);
-                        println!("{}", pac);
-
-                        //save to map
-                        // map.write().unwrap().get(interface).unwrap().push(pc);
-
-                        if is_save {
-                            if let Err(e) = writeln!(log_file, "{}", pac) {
-                                eprintln!("cannot write packet data to this file");
-                            }
-                        }
-                    }
-                    None => {
-                        println!("Problem is happened");
-                    }
-                };
-            }
+    println!();
+    print!("{}", stats.lock().unwrap().report(options.top));
+    print!("\n{}", summary);
+}
+
+/// Builds the same report from a pcap file; `limits.count` still applies.
+pub fn file_stats(path: &Path, options: StatsOptions) {
+    let stats = Arc::new(Mutex::new(TrafficStats::new(options.bucket)));
+    let mut collector = StatsCollector {
+        stats: Arc::clone(&stats),
+    };
+    let Some(summary) = replay(path, &options.limits, &mut collector) else {
+        return;
+    };
+
+    print!("{}", stats.lock().unwrap().report(options.top));
+    println!("\n{}", summary);
+}
+
+/// Hands every frame of a pcap file to `handler`, up to `limits.count`,
+/// and returns the line summing up what was read.
+fn replay(path: &Path, limits: &CaptureLimits, handler: &mut dyn FrameHandler) -> Option<String> {
+    let mut reader = match PcapReader::open(path) {
+        Ok(reader) => reader,
+        Err(e) => {
+            eprintln!("cannot read {}: {}", path.display(), e);
+            return None;
+        }
+    };
+
+    let (mut frames, mut malformed) = (0, 0);
+    while limits.count.is_none_or(|count| frames < count) {
+        match reader.next_frame() {
+            Ok(Some((timestamp, data))) => match EthernetIIFrame::new(&data) {
+                Some(frame) => {
+                    frames += 1;
+                    handler.handle(timestamp, &data, frame);
+                }
+                None => malformed += 1,
+            },
+            Ok(None) => break,
             Err(e) => {
-                // If an error occurs, we can handle it here
-                panic!("An error occurred while reading: {}", e);
+                eprintln!("stopped reading {}: {}", path.display(), e);
+                break;
             }
         }
     }
+
+    Some(format!(
+        "{} : {} frames, {} malformed",
+        path.display(),
+        frames,
+        malformed
+    ))
+}
+
+/// Feeds the frames of one interface into the shared statistics.
+struct StatsCollector {
+    stats: Arc<Mutex<TrafficStats>>,
+}
+
+impl FrameHandler for StatsCollector {
+    fn handle(&mut self, timestamp: SystemTime, _data: &[u8], frame: EthernetIIFrame) {
+        self.stats.lock().unwrap().update(&frame, timestamp);
+    }
+}
+
+/// Everything `inventory` needs besides where the packets come from.
+#[derive(Debug, Clone)]
+pub struct InventoryOptions {
+    pub limits: CaptureLimits,
+    /// a manuf, oui.txt or nmap-mac-prefixes file naming vendors
+    pub oui: Option<PathBuf>,
+    /// a p0f-style database to fingerprint SYNs and SYN-ACKs with
+    pub signatures: Option<PathBuf>,
+}
+
+/// Loads the vendor and signature databases, reporting why if one cannot be read.
+fn prepare_inventory(options: &InventoryOptions) -> Option<(Vendors, Inventory)> {
+    let loaded = Vendors::load(options.oui.as_deref()).and_then(|vendors| {
+        let signatures = options.signatures.as_deref().map(Signatures::load);
+        Ok((vendors, signatures.transpose()?))
+    });
+
+    match loaded {
+        Ok((vendors, signatures)) => Some((vendors, Inventory::new(signatures))),
+        Err(e) => {
+            eprintln!("{}", e);
+            None
+        }
+    }
+}
+
+pub fn live_inventory(interfaces: &InterfaceSelection, options: InventoryOptions) {
+    let Some((vendors, inventory)) = prepare_inventory(&options) else {
+        return;
+    };
+    let inventory = Arc::new(Mutex::new(inventory));
+
+    let summary = match capture::run(
+        interfaces,
+        &ChannelOptions::default(),
+        options.limits,
+        None,
+        |_, _| {
+            Box::new(InventoryCollector {
+                inventory: Arc::clone(&inventory),
+            })
+        },
+        || {},
+    ) {
+        Ok(summary) => summary,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
+
+    println!();
+    print!("{}", inventory.lock().unwrap().report(&vendors));
+    print!("\n{}", summary);
+}
+
+/// Builds the same inventory from a pcap file; `limits.count` still applies.
+pub fn file_inventory(path: &Path, options: InventoryOptions) {
+    let Some((vendors, inventory)) = prepare_inventory(&options) else {
+        return;
+    };
+    let inventory = Arc::new(Mutex::new(inventory));
+    let mut collector = InventoryCollector {
+        inventory: Arc::clone(&inventory),
+    };
+    let Some(summary) = replay(path, &options.limits, &mut collector) else {
+        return;
+    };
+
+    print!("{}", inventory.lock().unwrap().report(&vendors));
+    println!("\n{}", summary);
+}
+
+/// Feeds the frames of one interface into the shared inventory.
+struct InventoryCollector {
+    inventory: Arc<Mutex<Inventory>>,
+}
+
+impl FrameHandler for InventoryCollector {
+    fn handle(&mut self, timestamp: SystemTime, data: &[u8], frame: EthernetIIFrame) {
+        self.inventory
+            .lock()
+            .unwrap()
+            .update(&frame, data, timestamp);
+    }
 }
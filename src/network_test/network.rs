@@ -1,6 +1,9 @@
 use pnet::util::MacAddr;
 
-use crate::network_test::{transport, util};
+use crate::network_test::hexdump::Region;
+use crate::network_test::transport;
+use crate::network_test::util::{self, Tlv};
+use std::fmt;
 use std::net;
 
 #[derive(Debug)]
@@ -10,7 +13,7 @@ pub enum PacketType {
     IPv4(IPv4Packet),
     X25PLP,
     ARP(ARPPacket),
-    RARP,
+    RARP(ARPPacket),
     NetwareIPX,
     NetBIOS,
     IPv6(IPv6Packet),
@@ -32,20 +35,81 @@ pub struct IPv4Packet {
     sender_ip: net::Ipv4Addr,
     receiver_ip: net::Ipv4Addr,
     option: Vec<u8>,
+    /// `option` decoded, each with its offset inside `option`
+    options: Vec<(usize, IPv4Option)>,
     payload: transport::TransportSegment,
 }
 
+/// One IPv4 option. Parsing stops at the first `Malformed` one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IPv4Option {
+    EndOfList,
+    NoOperation,
+    /// RFC 1108 basic security: classification level and authority flags
+    Security {
+        classification: u8,
+        authority: Vec<u8>,
+    },
+    /// the route so far; `pointer` is the 1-based offset of the next slot
+    RecordRoute {
+        pointer: u8,
+        route: Vec<net::Ipv4Addr>,
+    },
+    LooseSourceRoute {
+        pointer: u8,
+        route: Vec<net::Ipv4Addr>,
+    },
+    StrictSourceRoute {
+        pointer: u8,
+        route: Vec<net::Ipv4Addr>,
+    },
+    /// `flag` 0 records timestamps only, 1 and 3 address/timestamp pairs
+    Timestamp {
+        pointer: u8,
+        overflow: u8,
+        flag: u8,
+        entries: Vec<(Option<net::Ipv4Addr>, u32)>,
+    },
+    RouterAlert(u16),
+    Unknown {
+        kind: u8,
+        data: Vec<u8>,
+    },
+    Malformed {
+        kind: u8,
+        reason: String,
+    },
+}
+
 impl IPv4Packet {
     pub fn new(byte_array: &[u8]) -> Option<Self> {
-        let mut iter = byte_array.iter().map(|&s| s);
+        Self::parse(byte_array, true)
+    }
+
+    /// Parses the datagram an ICMP error quotes, of which only the first
+    /// 8 transport bytes are usually left; a cut-off segment is kept as
+    /// `UNDEFINED` instead of failing the whole packet.
+    pub fn new_quoted(byte_array: &[u8]) -> Option<Self> {
+        Self::parse(byte_array, false)
+    }
+
+    fn parse(byte_array: &[u8], strict: bool) -> Option<Self> {
+        if byte_array.len() < 20 {
+            return None;
+        }
 
-        let mut byte: u8 = iter.next().unwrap();
+        let mut iter = byte_array.iter().copied();
+
+        let mut byte: u8 = iter.next()?;
 
         //- 버전(4비트) - header length(4비트)
         let (version, header_length) = util::splice_byte(4, byte);
+        if header_length < 5 {
+            return None;
+        }
 
         // - diff_serv(1바이트)
-        let diff_serv = iter.next().unwrap();
+        let diff_serv = iter.next()?;
 
         // - total length(2바이트)
         let total_length: u16 = util::assemble_byte(&mut iter.by_ref().take(2));
@@ -54,21 +118,16 @@ impl IPv4Packet {
         let identification: u16 = util::assemble_byte(&mut iter.by_ref().take(2));
 
         // - flag(3비트) - fragment offset(13비트)
-        byte = iter.next().unwrap();
+        byte = iter.next()?;
 
         let (flag, other) = util::splice_byte(3, byte);
-        let fragment: u16 = util::assemble_byte(&mut [other, iter.next().unwrap()].into_iter());
+        let fragment: u16 = util::assemble_byte(&mut [other, iter.next()?].into_iter());
 
         // - TTL(1바이트)
-        let ttl = iter.next().unwrap();
+        let ttl = iter.next()?;
 
         // - protocol type(8비트)
-        let protocol_type = match iter.next().unwrap() {
-            1 => transport::ProtocolType::ICMP,
-            6 => transport::ProtocolType::TCP,
-            17 => transport::ProtocolType::UDP,
-            x => transport::ProtocolType::UNDEFINED(x),
-        };
+        let protocol_type = transport::ProtocolType::from(iter.next()?);
 
         // - header checksum(16비트)
         let header_checksum: u16 = util::assemble_byte(&mut iter.by_ref().take(2));
@@ -83,23 +142,35 @@ impl IPv4Packet {
             .by_ref()
             .take((header_length * 4 - 20) as usize)
             .collect();
+        let options = IPv4Option::parse_all(&option);
 
-        let data: Vec<u8> = iter.collect();
-        let payload = match &protocol_type {
+        // frames shorter than 60 bytes carry Ethernet padding after the packet
+        let data: Vec<u8> = match (total_length as usize).checked_sub(header_length as usize * 4) {
+            Some(length) if total_length != 0 => iter.take(length).collect(),
+            _ => iter.collect(),
+        };
+        let segment = match &protocol_type {
+            // only the first fragment carries the transport header
+            _ if fragment != 0 => Some(transport::TransportSegment::UNDEFINED),
             transport::ProtocolType::ICMP => {
-                transport::TransportSegment::ICMP(transport::ICMPSegment::new(&data).unwrap())
+                transport::ICMPSegment::new(&data).map(transport::TransportSegment::ICMP)
             }
             transport::ProtocolType::TCP => {
-                transport::TransportSegment::TCP(transport::TCPSegment::new(&data).unwrap())
+                transport::TCPSegment::new(&data).map(transport::TransportSegment::TCP)
             }
             transport::ProtocolType::UDP => {
-                transport::TransportSegment::UDP(transport::UDPSegment::new(&data).unwrap())
+                transport::UDPSegment::new(&data).map(transport::TransportSegment::UDP)
             }
             _ => {
                 // println!("do nothing");
-                transport::TransportSegment::UNDEFINED
+                Some(transport::TransportSegment::UNDEFINED)
             }
         };
+        let payload = match segment {
+            Some(segment) => segment,
+            None if strict => return None,
+            None => transport::TransportSegment::UNDEFINED,
+        };
         // - data(세그먼트) 나머지 전부
 
         Some(IPv4Packet {
@@ -116,11 +187,300 @@ impl IPv4Packet {
             sender_ip,
             receiver_ip,
             option,
+            options,
             payload,
         })
     }
 }
 
+impl IPv4Option {
+    /// Decodes an option area, stopping after `EndOfList` or a malformed option.
+    pub fn parse_all(bytes: &[u8]) -> Vec<(usize, IPv4Option)> {
+        util::parse_options(
+            bytes,
+            |tlv| match tlv {
+                Tlv::EndOfList => IPv4Option::EndOfList,
+                Tlv::NoOperation => IPv4Option::NoOperation,
+                Tlv::Option { kind, value } => IPv4Option::parse(kind, value),
+                Tlv::Malformed { kind, reason } => IPv4Option::malformed(kind, &reason),
+            },
+            // a value that does not fit its kind stops the walk too
+            |option| matches!(option, IPv4Option::Malformed { .. }),
+        )
+    }
+
+    /// Decodes one option from its type and the bytes after the length byte.
+    fn parse(kind: u8, value: &[u8]) -> IPv4Option {
+        let address = |at: &[u8]| net::Ipv4Addr::new(at[0], at[1], at[2], at[3]);
+        let route = |value: &[u8]| -> Result<(u8, Vec<net::Ipv4Addr>), String> {
+            match value.split_first() {
+                Some((&pointer, addresses))
+                    if addresses.len().is_multiple_of(4) && pointer >= 4 =>
+                {
+                    Ok((pointer, addresses.chunks(4).map(address).collect()))
+                }
+                Some((&pointer, addresses)) if addresses.len().is_multiple_of(4) => {
+                    Err(format!("pointer {} is below 4", pointer))
+                }
+                _ => Err(format!(
+                    "length {} is not 3 plus 4 per address",
+                    value.len() + 2
+                )),
+            }
+        };
+
+        let parsed = match kind {
+            130 if !value.is_empty() => Ok(IPv4Option::Security {
+                classification: value[0],
+                authority: value[1..].to_vec(),
+            }),
+            130 => Err(String::from("no classification level")),
+            7 => route(value).map(|(pointer, route)| IPv4Option::RecordRoute { pointer, route }),
+            131 => {
+                route(value).map(|(pointer, route)| IPv4Option::LooseSourceRoute { pointer, route })
+            }
+            137 => route(value)
+                .map(|(pointer, route)| IPv4Option::StrictSourceRoute { pointer, route }),
+            68 if value.len() >= 2 => {
+                let (pointer, overflow, flag) = (value[0], value[1] >> 4, value[1] & 0x0f);
+                let entry_size = if flag == 0 { 4 } else { 8 };
+                let entries = &value[2..];
+
+                if pointer < 5 {
+                    Err(format!("pointer {} is below 5", pointer))
+                } else if !matches!(flag, 0 | 1 | 3) {
+                    Err(format!("unknown flag {}", flag))
+                } else if !entries.len().is_multiple_of(entry_size) {
+                    Err(format!(
+                        "length {} does not fit entries of {} bytes",
+                        value.len() + 2,
+                        entry_size
+                    ))
+                } else {
+                    let entries = entries
+                        .chunks(entry_size)
+                        .map(|entry| {
+                            let time = entry.len() - 4;
+                            (
+                                (flag != 0).then(|| address(entry)),
+                                u32::from_be_bytes([
+                                    entry[time],
+                                    entry[time + 1],
+                                    entry[time + 2],
+                                    entry[time + 3],
+                                ]),
+                            )
+                        })
+                        .collect();
+                    Ok(IPv4Option::Timestamp {
+                        pointer,
+                        overflow,
+                        flag,
+                        entries,
+                    })
+                }
+            }
+            68 => Err(format!("length {} is below 4", value.len() + 2)),
+            148 if value.len() == 2 => Ok(IPv4Option::RouterAlert(u16::from_be_bytes([
+                value[0], value[1],
+            ]))),
+            148 => Err(format!("length {} should be 4", value.len() + 2)),
+            kind => Ok(IPv4Option::Unknown {
+                kind,
+                data: value.to_vec(),
+            }),
+        };
+
+        parsed.unwrap_or_else(|reason| IPv4Option::malformed(kind, &reason))
+    }
+
+    fn malformed(kind: u8, reason: &str) -> IPv4Option {
+        IPv4Option::Malformed {
+            kind,
+            reason: reason.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for IPv4Option {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let route =
+            |f: &mut fmt::Formatter<'_>, name: &str, pointer: &u8, route: &[net::Ipv4Addr]| {
+                let route: Vec<String> = route.iter().map(|address| address.to_string()).collect();
+                write!(f, "{} [{}] pointer={}", name, route.join(", "), pointer)
+            };
+
+        match self {
+            IPv4Option::EndOfList => write!(f, "EOL"),
+            IPv4Option::NoOperation => write!(f, "NOP"),
+            IPv4Option::Security {
+                classification,
+                authority,
+            } => {
+                let level = match classification {
+                    0x01 => "Reserved 4",
+                    0x3d => "Top Secret",
+                    0x5a => "Secret",
+                    0x96 => "Confidential",
+                    0x66 => "Reserved 3",
+                    0xcc => "Reserved 2",
+                    0xab => "Unclassified",
+                    0xf1 => "Reserved 1",
+                    _ => "Unknown",
+                };
+                write!(f, "Security {} authority=", level)?;
+                authority.iter().try_for_each(|b| write!(f, "{:02x}", b))
+            }
+            IPv4Option::RecordRoute { pointer, route: r } => route(f, "Record Route", pointer, r),
+            IPv4Option::LooseSourceRoute { pointer, route: r } => {
+                route(f, "Loose Source Route", pointer, r)
+            }
+            IPv4Option::StrictSourceRoute { pointer, route: r } => {
+                route(f, "Strict Source Route", pointer, r)
+            }
+            IPv4Option::Timestamp {
+                pointer,
+                overflow,
+                flag,
+                entries,
+            } => {
+                let entries: Vec<String> = entries
+                    .iter()
+                    .map(|(address, time)| match address {
+                        Some(address) => format!("{}@{}", address, time),
+                        None => time.to_string(),
+                    })
+                    .collect();
+                write!(
+                    f,
+                    "Timestamp [{}] pointer={} overflow={} flag={}",
+                    entries.join(", "),
+                    pointer,
+                    overflow,
+                    flag
+                )
+            }
+            IPv4Option::RouterAlert(value) => write!(f, "Router Alert {}", value),
+            IPv4Option::Unknown { kind, data } => {
+                write!(f, "Option{}({} bytes)", kind, data.len() + 2)
+            }
+            IPv4Option::Malformed { kind, reason } => {
+                write!(f, "Malformed option {}: {}", kind, reason)
+            }
+        }
+    }
+}
+
+/// The three flag bits of an IPv4 header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IPv4Flags {
+    /// must be zero; RFC 3514 jokingly calls it the evil bit
+    pub reserved: bool,
+    pub dont_fragment: bool,
+    pub more_fragments: bool,
+}
+
+impl fmt::Display for IPv4Flags {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names: Vec<&str> = [
+            (self.reserved, "reserved"),
+            (self.dont_fragment, "DF"),
+            (self.more_fragments, "MF"),
+        ]
+        .into_iter()
+        .filter(|(set, _)| *set)
+        .map(|(_, name)| name)
+        .collect();
+
+        match names.is_empty() {
+            true => write!(f, "none"),
+            false => write!(f, "{}", names.join(", ")),
+        }
+    }
+}
+
+/// A Differentiated Services code point, the upper six bits of the
+/// IPv4 `diff_serv` byte and the IPv6 `traffic_class`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Dscp(pub u8);
+
+impl Dscp {
+    /// The standard name of the code point, if it has one.
+    pub fn name(&self) -> Option<String> {
+        match self.0 {
+            0 => Some(String::from("CS0")),
+            1 => Some(String::from("LE")),
+            44 => Some(String::from("VOICE-ADMIT")),
+            46 => Some(String::from("EF")),
+            // AFxy: class x in the upper three bits, drop precedence y in the next two
+            point if point & 0b111 == 0 => Some(format!("CS{}", point >> 3)),
+            point if (1..=4).contains(&(point >> 3)) && point & 1 == 0 && point & 0b110 != 0 => {
+                Some(format!("AF{}{}", point >> 3, (point >> 1) & 0b11))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Dscp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.name() {
+            Some(name) => write!(f, "{}", name),
+            None => write!(f, "{}", self.0),
+        }
+    }
+}
+
+/// The Explicit Congestion Notification code point, the lower two bits
+/// of the IPv4 `diff_serv` byte and the IPv6 `traffic_class`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ecn {
+    NotEct,
+    Ect1,
+    Ect0,
+    /// congestion experienced
+    Ce,
+}
+
+impl Ecn {
+    fn from_bits(bits: u8) -> Self {
+        match bits & 0b11 {
+            0 => Ecn::NotEct,
+            1 => Ecn::Ect1,
+            2 => Ecn::Ect0,
+            _ => Ecn::Ce,
+        }
+    }
+}
+
+impl fmt::Display for Ecn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Ecn::NotEct => write!(f, "Not-ECT"),
+            Ecn::Ect1 => write!(f, "ECT(1)"),
+            Ecn::Ect0 => write!(f, "ECT(0)"),
+            Ecn::Ce => write!(f, "CE"),
+        }
+    }
+}
+
+/// Splits a DS field or traffic class byte into its code points.
+fn traffic_class(byte: u8) -> (Dscp, Ecn) {
+    (Dscp(byte >> 2), Ecn::from_bits(byte))
+}
+
+/// The marks worth showing in a one-line summary: DSCP unless it is
+/// the default, and ECN only when congestion was experienced.
+fn write_marks(f: &mut fmt::Formatter<'_>, dscp: Dscp, ecn: Ecn) -> fmt::Result {
+    if dscp.0 != 0 {
+        write!(f, " [DSCP {}]", dscp)?;
+    }
+    if ecn == Ecn::Ce {
+        write!(f, " [ECN CE]")?;
+    }
+    Ok(())
+}
+
 #[derive(Debug)]
 pub struct ARPPacket {
     hardware_type: u16,
@@ -128,27 +488,59 @@ pub struct ARPPacket {
     hardware_address_length: u8,
     protocol_address_length: u8,
     operation: u16,
-    sender_hardware_address: MacAddr,
-    sender_protocol_address: u32,
-    target_hardware_address: MacAddr,
-    target_protocol_address: u32,
+    sender_hardware_address: Vec<u8>,
+    sender_protocol_address: Vec<u8>,
+    target_hardware_address: Vec<u8>,
+    target_protocol_address: Vec<u8>,
+}
+
+/// What an ARP or RARP packet is for, telling the RFC 5227 special cases apart.
+///
+/// RFC 5227 makes an announcement the same packet as a gratuitous request,
+/// so a request for the sender's own address with a zero target MAC is
+/// always an `Announcement`; `Gratuitous` is left with replies and requests
+/// naming a target MAC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ARPKind {
+    Request,
+    Reply,
+    /// announces the sender's own binding unasked, sender IP equal to target IP
+    Gratuitous,
+    /// checks whether an address is in use, sent from 0.0.0.0
+    Probe,
+    /// a request for the sender's own address, claiming it after probing
+    Announcement,
+    ReverseRequest,
+    ReverseReply,
+    Unknown,
 }
 
 impl ARPPacket {
     pub fn new(byte_array: &[u8]) -> Option<Self> {
-        let mut iter = byte_array.iter().map(|&s| s);
+        if byte_array.len() < 8 {
+            return None;
+        }
+
+        let mut iter = byte_array.iter().copied();
 
         let hardware_type: u16 = util::assemble_byte(&mut iter.by_ref().take(2));
         let protocol_type: u16 = util::assemble_byte(&mut iter.by_ref().take(2));
-        let hardware_address_length: u8 = iter.next().unwrap();
-        let protocol_address_length: u8 = iter.next().unwrap();
+        let hardware_address_length: u8 = iter.next()?;
+        let protocol_address_length: u8 = iter.next()?;
         let operation: u16 = util::assemble_byte(&mut iter.by_ref().take(2));
-        let sender_hardware_address: MacAddr =
-            util::mapping_mac_addr(iter.by_ref().take(6).collect());
-        let sender_protocol_address: u32 = util::assemble_byte(&mut iter.by_ref().take(4));
-        let target_hardware_address: MacAddr =
-            util::mapping_mac_addr(iter.by_ref().take(6).collect());
-        let target_protocol_address: u32 = util::assemble_byte(&mut iter.by_ref().take(4));
+
+        // the addresses are as long as the header says, both pairs of them
+        let (hlen, plen) = (
+            hardware_address_length as usize,
+            protocol_address_length as usize,
+        );
+        if byte_array.len() < 8 + 2 * (hlen + plen) {
+            return None;
+        }
+        let sender_hardware_address: Vec<u8> = iter.by_ref().take(hlen).collect();
+        let sender_protocol_address: Vec<u8> = iter.by_ref().take(plen).collect();
+        let target_hardware_address: Vec<u8> = iter.by_ref().take(hlen).collect();
+        let target_protocol_address: Vec<u8> = iter.by_ref().take(plen).collect();
 
         Some(ARPPacket {
             hardware_type,
@@ -179,21 +571,29 @@ pub struct IPv6Packet {
 
 impl IPv6Packet {
     pub fn new(byte_array: &[u8]) -> Option<Self> {
-        let mut iter = byte_array.iter().map(|&s| s);
+        if byte_array.len() < 40 {
+            return None;
+        }
+
+        let mut iter = byte_array.iter().copied();
 
-        let (version, rest) = util::splice_byte(4, iter.next().unwrap());
-        let (first, last) = util::splice_byte(4, iter.next().unwrap());
+        let (version, rest) = util::splice_byte(4, iter.next()?);
+        let (first, last) = util::splice_byte(4, iter.next()?);
 
-        let traffic_class: u8 = rest << 4 + first;
+        let traffic_class: u8 = (rest << 4) + first;
 
         let flow_label: u32 =
             util::assemble_byte(&mut [last].into_iter().chain(iter.by_ref().take(2)));
         let payload_length: u16 = util::assemble_byte(&mut iter.by_ref().take(2));
-        let next_header: u8 = iter.next().unwrap();
-        let hop_limit: u8 = iter.next().unwrap();
+        let next_header: u8 = iter.next()?;
+        let hop_limit: u8 = iter.next()?;
         let source_address: u128 = util::assemble_byte(&mut iter.by_ref().take(16));
         let destination_address: u128 = util::assemble_byte(&mut iter.by_ref().take(16));
-        let payload = iter.collect();
+        // a zero length means a jumbogram, whose length is in an extension header
+        let payload = match payload_length {
+            0 => iter.collect(),
+            length => iter.take(length as usize).collect(),
+        };
 
         Some(IPv6Packet {
             version,
@@ -208,3 +608,835 @@ impl IPv6Packet {
         })
     }
 }
+
+impl PacketType {
+    /// Name of the highest layer that was dissected in this packet.
+    pub fn protocol_name(&self) -> &'static str {
+        match self {
+            PacketType::Length(_) => "LLC",
+            PacketType::XNSIDP => "XNS-IDP",
+            PacketType::IPv4(packet) => packet.payload.name().unwrap_or("IPv4"),
+            PacketType::X25PLP => "X.25",
+            PacketType::ARP(_) => "ARP",
+            PacketType::RARP(_) => "RARP",
+            PacketType::NetwareIPX => "IPX",
+            PacketType::NetBIOS => "NetBIOS",
+            PacketType::IPv6(_) => "IPv6",
+            PacketType::UNDEFINED(_) => "Unknown",
+        }
+    }
+
+    pub fn source(&self) -> Option<String> {
+        match self {
+            PacketType::IPv4(packet) => Some(packet.sender_ip.to_string()),
+            PacketType::IPv6(packet) => Some(packet.get_source().to_string()),
+            _ => None,
+        }
+    }
+
+    pub fn destination(&self) -> Option<String> {
+        match self {
+            PacketType::IPv4(packet) => Some(packet.receiver_ip.to_string()),
+            PacketType::IPv6(packet) => Some(packet.get_destination().to_string()),
+            _ => None,
+        }
+    }
+
+    pub fn write_tree(&self, f: &mut fmt::Formatter<'_>, base: usize) -> fmt::Result {
+        match self {
+            PacketType::IPv4(packet) => packet.write_tree(f, base),
+            PacketType::ARP(packet) | PacketType::RARP(packet) => packet.write_tree(f, base),
+            PacketType::IPv6(packet) => packet.write_tree(f, base),
+            other => writeln!(
+                f,
+                "{} @0x{:04x}\n    {}",
+                other.protocol_name(),
+                base,
+                other
+            ),
+        }
+    }
+
+    /// Appends the byte ranges of this packet, which starts at `base`.
+    pub fn regions(&self, base: usize, regions: &mut Vec<Region>) {
+        match self {
+            PacketType::IPv4(packet) => packet.regions(base, regions),
+            PacketType::ARP(packet) | PacketType::RARP(packet) => packet.regions(base, regions),
+            PacketType::IPv6(packet) => packet.regions(base, regions),
+            _ => {}
+        }
+    }
+}
+
+/// `{}` prints the one-line info column, `{:#}` prints the layer tree.
+impl fmt::Display for PacketType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if f.alternate() {
+            return self.write_tree(f, 0);
+        }
+
+        match self {
+            PacketType::Length(length) => write!(f, "802.3 length={}", length),
+            PacketType::IPv4(packet) => write!(f, "{}", packet),
+            PacketType::ARP(packet) | PacketType::RARP(packet) => write!(f, "{}", packet),
+            PacketType::IPv6(packet) => write!(f, "{}", packet),
+            PacketType::UNDEFINED(ether_type) => write!(f, "EtherType 0x{:04x}", ether_type),
+            other => write!(f, "{} (not dissected)", other.protocol_name()),
+        }
+    }
+}
+
+impl IPv4Packet {
+    pub fn get_source(&self) -> net::Ipv4Addr {
+        self.sender_ip
+    }
+
+    pub fn get_destination(&self) -> net::Ipv4Addr {
+        self.receiver_ip
+    }
+
+    pub fn get_protocol(&self) -> u8 {
+        self.protocol_type.number()
+    }
+
+    pub fn get_ttl(&self) -> u8 {
+        self.ttl
+    }
+
+    pub fn get_identification(&self) -> u16 {
+        self.identification
+    }
+
+    pub fn get_header_length(&self) -> usize {
+        self.header_length as usize * 4
+    }
+
+    pub fn get_transport_segment(&self) -> &transport::TransportSegment {
+        &self.payload
+    }
+
+    pub fn get_options(&self) -> impl Iterator<Item = &IPv4Option> {
+        self.options.iter().map(|(_, option)| option)
+    }
+
+    pub fn get_flags(&self) -> IPv4Flags {
+        IPv4Flags {
+            reserved: self.flag & 0b100 != 0,
+            dont_fragment: self.flag & 0b010 != 0,
+            more_fragments: self.flag & 0b001 != 0,
+        }
+    }
+
+    pub fn get_dscp(&self) -> Dscp {
+        traffic_class(self.diff_serv).0
+    }
+
+    pub fn get_ecn(&self) -> Ecn {
+        traffic_class(self.diff_serv).1
+    }
+
+    /// Where this fragment's data starts in the original payload, in bytes.
+    pub fn get_fragment_offset(&self) -> usize {
+        self.fragment as usize * 8
+    }
+
+    /// Whether this packet is one piece of a fragmented datagram.
+    pub fn is_fragment(&self) -> bool {
+        self.fragment != 0 || self.get_flags().more_fragments
+    }
+
+    pub fn regions(&self, base: usize, regions: &mut Vec<Region>) {
+        let option = base + 20;
+        let payload = option + self.option.len();
+
+        regions.push(Region::new("IPv4", base, option));
+        regions.push(Region::new("IPv4 options", option, payload));
+        self.payload.regions(payload, regions);
+    }
+
+    pub fn write_tree(&self, f: &mut fmt::Formatter<'_>, base: usize) -> fmt::Result {
+        writeln!(f, "IPv4 @0x{:04x}", base)?;
+        util::write_field(f, base, "version", self.version)?;
+        util::write_field(
+            f,
+            base,
+            "header_length",
+            format!(
+                "{} ({} bytes)",
+                self.header_length,
+                self.header_length as usize * 4
+            ),
+        )?;
+        util::write_field(
+            f,
+            base + 1,
+            "diff_serv",
+            format!(
+                "0x{:02x} (DSCP {}, ECN {})",
+                self.diff_serv,
+                self.get_dscp(),
+                self.get_ecn()
+            ),
+        )?;
+        util::write_field(f, base + 2, "total_length", self.total_length)?;
+        util::write_field(
+            f,
+            base + 4,
+            "identification",
+            format!("0x{:04x}", self.identification),
+        )?;
+        util::write_field(
+            f,
+            base + 6,
+            "flag",
+            format!("0b{:03b} ({})", self.flag, self.get_flags()),
+        )?;
+        util::write_field(
+            f,
+            base + 6,
+            "fragment",
+            format!("{} ({} bytes)", self.fragment, self.get_fragment_offset()),
+        )?;
+        util::write_field(f, base + 8, "ttl", self.ttl)?;
+        util::write_field(f, base + 9, "protocol_type", &self.protocol_type)?;
+        util::write_field(
+            f,
+            base + 10,
+            "header_checksum",
+            format!("0x{:04x}", self.header_checksum),
+        )?;
+        util::write_field(f, base + 12, "sender_ip", self.sender_ip)?;
+        util::write_field(f, base + 16, "receiver_ip", self.receiver_ip)?;
+        for (offset, option) in &self.options {
+            util::write_field(f, base + 20 + offset, "option", option)?;
+        }
+        self.payload
+            .write_tree(f, base + self.header_length as usize * 4)
+    }
+}
+
+impl fmt::Display for IPv4Packet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if f.alternate() {
+            return self.write_tree(f, 0);
+        }
+
+        match self.payload {
+            transport::TransportSegment::UNDEFINED => {
+                write!(f, "protocol={} ttl={}", self.protocol_type, self.ttl)?
+            }
+            ref segment => write!(f, "{}", segment)?,
+        }
+
+        if self.is_fragment() {
+            write!(
+                f,
+                " [fragment id=0x{:04x} offset={}{}]",
+                self.identification,
+                self.get_fragment_offset(),
+                if self.get_flags().more_fragments {
+                    " MF"
+                } else {
+                    ""
+                }
+            )?;
+        }
+        write_marks(f, self.get_dscp(), self.get_ecn())?;
+
+        // options are rare enough that every one is worth a look
+        for option in self.get_options() {
+            if !matches!(option, IPv4Option::NoOperation | IPv4Option::EndOfList) {
+                write!(f, " [IP option: {}]", option)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ARPPacket {
+    pub fn get_operation(&self) -> u16 {
+        self.operation
+    }
+
+    pub fn get_sender_mac(&self) -> Option<MacAddr> {
+        mac_addr(&self.sender_hardware_address)
+    }
+
+    pub fn get_target_mac(&self) -> Option<MacAddr> {
+        mac_addr(&self.target_hardware_address)
+    }
+
+    /// The sender's address, when the packet resolves IPv4.
+    pub fn get_sender_ip(&self) -> Option<net::Ipv4Addr> {
+        self.ipv4_addr(&self.sender_protocol_address)
+    }
+
+    pub fn get_target_ip(&self) -> Option<net::Ipv4Addr> {
+        self.ipv4_addr(&self.target_protocol_address)
+    }
+
+    fn ipv4_addr(&self, bytes: &[u8]) -> Option<net::Ipv4Addr> {
+        match (self.protocol_type, bytes) {
+            (0x0800, &[a, b, c, d]) => Some(net::Ipv4Addr::new(a, b, c, d)),
+            _ => None,
+        }
+    }
+
+    pub fn kind(&self) -> ARPKind {
+        let is_zero = |bytes: &[u8]| bytes.iter().all(|byte| *byte == 0);
+        let own_address = self.sender_protocol_address == self.target_protocol_address;
+
+        // sender IP == target IP is gratuitous, an announcement if a request to nobody
+        match self.operation {
+            1 if is_zero(&self.sender_protocol_address) => ARPKind::Probe,
+            1 if own_address && is_zero(&self.target_hardware_address) => ARPKind::Announcement,
+            1 | 2 if own_address => ARPKind::Gratuitous,
+            1 => ARPKind::Request,
+            2 => ARPKind::Reply,
+            3 => ARPKind::ReverseRequest,
+            4 => ARPKind::ReverseReply,
+            _ => ARPKind::Unknown,
+        }
+    }
+
+    pub fn operation_name(&self) -> &'static str {
+        match self.operation {
+            1 => "request",
+            2 => "reply",
+            3 => "RARP request",
+            4 => "RARP reply",
+            _ => "unknown",
+        }
+    }
+
+    /// A MAC address as such, anything else as colon-separated hex.
+    fn hardware_address(bytes: &[u8]) -> String {
+        match mac_addr(bytes) {
+            Some(mac) => mac.to_string(),
+            None => hex_address(bytes),
+        }
+    }
+
+    fn protocol_address(&self, bytes: &[u8]) -> String {
+        match (self.protocol_type, bytes.len()) {
+            (0x0800, 4) => self.ipv4_addr(bytes).unwrap().to_string(),
+            (0x86dd, 16) => net::Ipv6Addr::from(<[u8; 16]>::try_from(bytes).unwrap()).to_string(),
+            _ => hex_address(bytes),
+        }
+    }
+
+    fn length(&self) -> usize {
+        8 + 2 * (self.hardware_address_length as usize + self.protocol_address_length as usize)
+    }
+
+    pub fn regions(&self, base: usize, regions: &mut Vec<Region>) {
+        regions.push(Region::new(self.name(), base, base + self.length()));
+    }
+
+    /// RARP reuses the ARP format with its own operations.
+    fn name(&self) -> &'static str {
+        match self.operation {
+            3 | 4 => "RARP",
+            _ => "ARP",
+        }
+    }
+
+    pub fn write_tree(&self, f: &mut fmt::Formatter<'_>, base: usize) -> fmt::Result {
+        let (hlen, plen) = (
+            self.hardware_address_length as usize,
+            self.protocol_address_length as usize,
+        );
+
+        writeln!(f, "{} @0x{:04x}", self.name(), base)?;
+        util::write_field(f, base, "hardware_type", self.hardware_type)?;
+        util::write_field(
+            f,
+            base + 2,
+            "protocol_type",
+            format!("0x{:04x}", self.protocol_type),
+        )?;
+        util::write_field(
+            f,
+            base + 4,
+            "hardware_address_length",
+            self.hardware_address_length,
+        )?;
+        util::write_field(
+            f,
+            base + 5,
+            "protocol_address_length",
+            self.protocol_address_length,
+        )?;
+        let operation = match self.kind() {
+            kind @ (ARPKind::Gratuitous | ARPKind::Probe | ARPKind::Announcement) => {
+                format!("{} ({}, {})", self.operation, self.operation_name(), kind)
+            }
+            _ => format!("{} ({})", self.operation, self.operation_name()),
+        };
+        util::write_field(f, base + 6, "operation", operation)?;
+        util::write_field(
+            f,
+            base + 8,
+            "sender_hardware_address",
+            Self::hardware_address(&self.sender_hardware_address),
+        )?;
+        util::write_field(
+            f,
+            base + 8 + hlen,
+            "sender_protocol_address",
+            self.protocol_address(&self.sender_protocol_address),
+        )?;
+        util::write_field(
+            f,
+            base + 8 + hlen + plen,
+            "target_hardware_address",
+            Self::hardware_address(&self.target_hardware_address),
+        )?;
+        util::write_field(
+            f,
+            base + 8 + 2 * hlen + plen,
+            "target_protocol_address",
+            self.protocol_address(&self.target_protocol_address),
+        )
+    }
+}
+
+fn mac_addr(bytes: &[u8]) -> Option<MacAddr> {
+    match *bytes {
+        [a, b, c, d, e, f] => Some(MacAddr::new(a, b, c, d, e, f)),
+        _ => None,
+    }
+}
+
+fn hex_address(bytes: &[u8]) -> String {
+    let bytes: Vec<String> = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+    bytes.join(":")
+}
+
+impl fmt::Display for ARPKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ARPKind::Request => "request",
+            ARPKind::Reply => "reply",
+            ARPKind::Gratuitous => "gratuitous",
+            ARPKind::Probe => "probe",
+            ARPKind::Announcement => "announcement",
+            ARPKind::ReverseRequest => "reverse request",
+            ARPKind::ReverseReply => "reverse reply",
+            ARPKind::Unknown => "unknown",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl fmt::Display for ARPPacket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if f.alternate() {
+            return self.write_tree(f, 0);
+        }
+
+        let sender = self.protocol_address(&self.sender_protocol_address);
+        let target = self.protocol_address(&self.target_protocol_address);
+        let sender_hardware = Self::hardware_address(&self.sender_hardware_address);
+        let target_hardware = Self::hardware_address(&self.target_hardware_address);
+
+        match self.kind() {
+            ARPKind::Request => write!(f, "who-has {} tell {}", target, sender),
+            ARPKind::Reply => write!(f, "{} is-at {}", sender, sender_hardware),
+            ARPKind::Gratuitous => {
+                write!(f, "gratuitous {} is-at {}", sender, sender_hardware)
+            }
+            ARPKind::Probe => write!(f, "probe who-has {} from {}", target, sender_hardware),
+            ARPKind::Announcement => {
+                write!(f, "announcement {} is-at {}", sender, sender_hardware)
+            }
+            ARPKind::ReverseRequest => {
+                write!(f, "who-is {} tell {}", target_hardware, sender_hardware)
+            }
+            ARPKind::ReverseReply => write!(f, "{} at {}", target_hardware, target),
+            ARPKind::Unknown => {
+                write!(f, "{} operation={}", self.operation_name(), self.operation)
+            }
+        }
+    }
+}
+
+impl IPv6Packet {
+    pub fn get_source(&self) -> net::Ipv6Addr {
+        net::Ipv6Addr::from(self.source_address)
+    }
+
+    pub fn get_destination(&self) -> net::Ipv6Addr {
+        net::Ipv6Addr::from(self.destination_address)
+    }
+
+    pub fn get_next_header(&self) -> u8 {
+        self.next_header
+    }
+
+    pub fn get_hop_limit(&self) -> u8 {
+        self.hop_limit
+    }
+
+    pub fn get_flow_label(&self) -> u32 {
+        self.flow_label
+    }
+
+    pub fn get_payload(&self) -> &[u8] {
+        &self.payload
+    }
+
+    pub fn get_dscp(&self) -> Dscp {
+        traffic_class(self.traffic_class).0
+    }
+
+    pub fn get_ecn(&self) -> Ecn {
+        traffic_class(self.traffic_class).1
+    }
+
+    /// Walks the extension headers at the start of the payload and
+    /// returns each one's name and length, stopping at the first header
+    /// that is not an extension or does not fit.
+    pub fn extension_headers(&self) -> Vec<(&'static str, usize)> {
+        let mut headers = vec![];
+        let mut next_header = self.next_header;
+        let mut offset = 0;
+
+        while let Some(&[following, length]) = self.payload.get(offset..offset + 2) {
+            let (name, length) = match next_header {
+                0 => ("IPv6 hop-by-hop", (length as usize + 1) * 8),
+                43 => ("IPv6 routing", (length as usize + 1) * 8),
+                44 => ("IPv6 fragment", 8),
+                51 => ("IPv6 AH", (length as usize + 2) * 4),
+                60 => ("IPv6 dest options", (length as usize + 1) * 8),
+                _ => break,
+            };
+            if offset + length > self.payload.len() {
+                break;
+            }
+
+            headers.push((name, length));
+            next_header = following;
+            offset += length;
+        }
+
+        headers
+    }
+
+    pub fn regions(&self, base: usize, regions: &mut Vec<Region>) {
+        let mut offset = base + 40;
+
+        regions.push(Region::new("IPv6", base, offset));
+        for (name, length) in self.extension_headers() {
+            regions.push(Region::new(name, offset, offset + length));
+            offset += length;
+        }
+        regions.push(Region::new(
+            "IPv6 payload",
+            offset,
+            base + 40 + self.payload.len(),
+        ));
+    }
+
+    pub fn write_tree(&self, f: &mut fmt::Formatter<'_>, base: usize) -> fmt::Result {
+        writeln!(f, "IPv6 @0x{:04x}", base)?;
+        util::write_field(f, base, "version", self.version)?;
+        util::write_field(
+            f,
+            base,
+            "traffic_class",
+            format!(
+                "0x{:02x} (DSCP {}, ECN {})",
+                self.traffic_class,
+                self.get_dscp(),
+                self.get_ecn()
+            ),
+        )?;
+        util::write_field(
+            f,
+            base + 1,
+            "flow_label",
+            format!("0x{:05x}", self.flow_label),
+        )?;
+        util::write_field(f, base + 4, "payload_length", self.payload_length)?;
+        util::write_field(f, base + 6, "next_header", self.next_header)?;
+        util::write_field(f, base + 7, "hop_limit", self.hop_limit)?;
+        util::write_field(f, base + 8, "source_address", self.get_source())?;
+        util::write_field(f, base + 24, "destination_address", self.get_destination())?;
+        let mut offset = base + 40;
+        for (name, length) in self.extension_headers() {
+            util::write_field(
+                f,
+                offset,
+                "extension_header",
+                format!("{} ({} bytes)", name, length),
+            )?;
+            offset += length;
+        }
+        util::write_field(
+            f,
+            offset,
+            "payload",
+            format!("{} bytes", base + 40 + self.payload.len() - offset),
+        )
+    }
+}
+
+impl fmt::Display for IPv6Packet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if f.alternate() {
+            return self.write_tree(f, 0);
+        }
+
+        write!(
+            f,
+            "next_header={} hop_limit={} len={}",
+            self.next_header, self.hop_limit, self.payload_length
+        )?;
+        write_marks(f, self.get_dscp(), self.get_ecn())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network_test::datalink::EthernetIIFrame;
+    use crate::network_test::util::frames::{arp, ethernet, CLIENT, SERVER};
+
+    fn malformed(kind: u8, reason: &str) -> IPv4Option {
+        IPv4Option::Malformed {
+            kind,
+            reason: reason.to_string(),
+        }
+    }
+
+    #[test]
+    fn ipv4_options() {
+        let bytes = [
+            1, // NOP
+            7, 11, 8, 10, 0, 0, 1, 10, 0, 0, 2, // record route, one slot used
+            131, 7, 4, 192, 168, 0, 1, // loose source route
+            68, 12, 13, 0x11, 10, 0, 0, 1, 0, 0, 1, 0, // timestamps with addresses
+            130, 4, 0xab, 0x01, // security
+            148, 4, 0, 0, // router alert
+            0, 7, // end of list, then padding left alone
+        ];
+        let address = net::Ipv4Addr::new;
+        assert_eq!(
+            IPv4Option::parse_all(&bytes),
+            vec![
+                (0, IPv4Option::NoOperation),
+                (
+                    1,
+                    IPv4Option::RecordRoute {
+                        pointer: 8,
+                        route: vec![address(10, 0, 0, 1), address(10, 0, 0, 2)]
+                    }
+                ),
+                (
+                    12,
+                    IPv4Option::LooseSourceRoute {
+                        pointer: 4,
+                        route: vec![address(192, 168, 0, 1)]
+                    }
+                ),
+                (
+                    19,
+                    IPv4Option::Timestamp {
+                        pointer: 13,
+                        overflow: 1,
+                        flag: 1,
+                        entries: vec![(Some(address(10, 0, 0, 1)), 256)]
+                    }
+                ),
+                (
+                    31,
+                    IPv4Option::Security {
+                        classification: 0xab,
+                        authority: vec![0x01]
+                    }
+                ),
+                (35, IPv4Option::RouterAlert(0)),
+                (39, IPv4Option::EndOfList),
+            ]
+        );
+    }
+
+    #[test]
+    fn malformed_ipv4_options_stop_the_walk() {
+        assert_eq!(
+            IPv4Option::parse_all(&[148, 3, 0, 1]),
+            vec![(0, malformed(148, "length 3 should be 4"))]
+        );
+        assert_eq!(
+            IPv4Option::parse_all(&[137, 7, 3, 0, 0, 0, 0]),
+            vec![(0, malformed(137, "pointer 3 is below 4"))]
+        );
+        assert_eq!(
+            IPv4Option::parse_all(&[7, 6, 4, 0, 0, 0]),
+            vec![(0, malformed(7, "length 6 is not 3 plus 4 per address"))]
+        );
+        assert_eq!(
+            IPv4Option::parse_all(&[68, 4, 5, 0x02]),
+            vec![(0, malformed(68, "unknown flag 2"))]
+        );
+        assert_eq!(
+            IPv4Option::parse_all(&[68, 6, 5, 0x01, 0, 0]),
+            vec![(0, malformed(68, "length 6 does not fit entries of 8 bytes"))]
+        );
+        assert_eq!(
+            IPv4Option::parse_all(&[1, 131, 9, 4, 0]),
+            vec![
+                (0, IPv4Option::NoOperation),
+                (1, malformed(131, "length 9 runs past the 5 option bytes"))
+            ]
+        );
+    }
+
+    #[test]
+    fn ipv4_flags_marks_and_fragment_offset() {
+        let bytes = [
+            0x45, 0xbb, 0, 28, 0, 1, // DSCP EF, ECN CE
+            0xe0, 185, // reserved, DF and MF, fragment offset 185
+            64, 17, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0,
+        ];
+        let packet = IPv4Packet::new(&bytes).unwrap();
+        assert_eq!(
+            packet.get_flags(),
+            IPv4Flags {
+                reserved: true,
+                dont_fragment: true,
+                more_fragments: true
+            }
+        );
+        assert_eq!(packet.get_flags().to_string(), "reserved, DF, MF");
+        assert_eq!(packet.get_dscp(), Dscp(46));
+        assert_eq!(packet.get_ecn(), Ecn::Ce);
+        assert_eq!(packet.get_fragment_offset(), 1480);
+        assert!(packet.is_fragment());
+    }
+
+    #[test]
+    fn ipv6_traffic_class() {
+        // traffic class 0x8a: AF41 and ECT(0)
+        let mut bytes = vec![0x68, 0xa0, 0, 0, 0, 0, 59, 64];
+        bytes.extend_from_slice(&[0; 32]);
+        let packet = IPv6Packet::new(&bytes).unwrap();
+        assert_eq!(packet.get_dscp(), Dscp(34));
+        assert_eq!(packet.get_ecn(), Ecn::Ect0);
+        assert_eq!(packet.get_flow_label(), 0);
+    }
+
+    #[test]
+    fn dscp_names() {
+        let names: Vec<String> = [0, 1, 8, 10, 14, 36, 44, 46, 48, 5]
+            .into_iter()
+            .map(|point| Dscp(point).to_string())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "CS0",
+                "LE",
+                "CS1",
+                "AF11",
+                "AF13",
+                "AF42",
+                "VOICE-ADMIT",
+                "EF",
+                "CS6",
+                "5"
+            ]
+        );
+    }
+
+    const MAC_A: [u8; 6] = [0x02, 0, 0, 0, 0, 0x0a];
+    const MAC_B: [u8; 6] = [0x02, 0, 0, 0, 0, 0x0b];
+    const NOBODY: [u8; 6] = [0; 6];
+
+    #[test]
+    fn arp_request() {
+        let packet = ARPPacket::new(&arp(1, (MAC_A, CLIENT), (NOBODY, SERVER))).unwrap();
+        assert_eq!(packet.kind(), ARPKind::Request);
+        assert_eq!(
+            packet.get_sender_mac(),
+            Some(MacAddr::new(2, 0, 0, 0, 0, 0x0a))
+        );
+        assert_eq!(
+            packet.get_sender_ip(),
+            Some(net::Ipv4Addr::new(10, 0, 0, 1))
+        );
+        assert_eq!(
+            packet.get_target_ip(),
+            Some(net::Ipv4Addr::new(10, 0, 0, 2))
+        );
+        assert_eq!(packet.to_string(), "who-has 10.0.0.2 tell 10.0.0.1");
+    }
+
+    #[test]
+    fn arp_address_lengths_follow_the_header() {
+        // 8-byte hardware addresses, IPv4 protocol addresses
+        let mut bytes = vec![0, 27, 0x08, 0x00, 8, 4, 0, 2];
+        bytes.extend_from_slice(&[1, 2, 3, 4, 5, 6, 7, 8, 10, 0, 0, 1]);
+        bytes.extend_from_slice(&[8, 7, 6, 5, 4, 3, 2, 1, 10, 0, 0, 2]);
+        let packet = ARPPacket::new(&bytes).unwrap();
+        assert_eq!(packet.kind(), ARPKind::Reply);
+        assert_eq!(packet.get_sender_mac(), None);
+        assert_eq!(
+            packet.get_sender_ip(),
+            Some(net::Ipv4Addr::new(10, 0, 0, 1))
+        );
+        assert_eq!(
+            packet.get_target_ip(),
+            Some(net::Ipv4Addr::new(10, 0, 0, 2))
+        );
+
+        // 16-byte protocol addresses are not IPv4
+        let mut bytes = vec![0, 1, 0x86, 0xdd, 6, 16, 0, 1];
+        bytes.extend_from_slice(&[0; 2 * (6 + 16)]);
+        let packet = ARPPacket::new(&bytes).unwrap();
+        assert_eq!(packet.get_sender_mac(), Some(MacAddr::zero()));
+        assert_eq!(packet.get_sender_ip(), None);
+
+        assert!(ARPPacket::new(&bytes[..bytes.len() - 1]).is_none());
+    }
+
+    #[test]
+    fn arp_kinds() {
+        let kind = |operation, sender, target| {
+            ARPPacket::new(&arp(operation, sender, target))
+                .unwrap()
+                .kind()
+        };
+        let (own, other) = ([10, 0, 0, 1], [10, 0, 0, 2]);
+
+        assert_eq!(kind(1, (MAC_A, [0; 4]), (NOBODY, own)), ARPKind::Probe);
+        // a gratuitous request to nobody is an announcement
+        assert_eq!(kind(1, (MAC_A, own), (NOBODY, own)), ARPKind::Announcement);
+        assert_eq!(kind(1, (MAC_A, own), (MAC_B, own)), ARPKind::Gratuitous);
+        assert_eq!(kind(2, (MAC_A, own), (NOBODY, own)), ARPKind::Gratuitous);
+        assert_eq!(kind(2, (MAC_A, own), (MAC_B, other)), ARPKind::Reply);
+        assert_eq!(
+            kind(3, (MAC_A, [0; 4]), (MAC_A, [0; 4])),
+            ARPKind::ReverseRequest
+        );
+        assert_eq!(kind(4, (MAC_B, other), (MAC_A, own)), ARPKind::ReverseReply);
+        assert_eq!(kind(9, (MAC_A, own), (MAC_B, other)), ARPKind::Unknown);
+    }
+
+    #[test]
+    fn cut_off_arp_and_rarp_keep_the_frame() {
+        let rarp = arp(3, (MAC_A, [0; 4]), (MAC_A, [0; 4]));
+        for ether_type in [0x0806u16, 0x8035] {
+            let frame = ethernet(MAC_A, MAC_B, ether_type, &rarp[..20]);
+            let frame = EthernetIIFrame::new(&frame).unwrap();
+            assert!(matches!(
+                frame.get_network_packet(),
+                PacketType::UNDEFINED(undefined) if *undefined == ether_type
+            ));
+        }
+
+        let frame = EthernetIIFrame::new(&ethernet(MAC_A, MAC_B, 0x8035, &rarp)).unwrap();
+        assert!(matches!(frame.get_network_packet(), PacketType::RARP(_)));
+    }
+}
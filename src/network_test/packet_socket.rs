@@ -0,0 +1,207 @@
+use std::io;
+use std::mem;
+use std::time::Duration;
+
+use libc::c_int;
+use pnet::datalink::NetworkInterface;
+
+use crate::network_test::capture::{Fanout, FanoutMode};
+use crate::network_test::tpacket::KernelStats;
+
+/// Which kernel buffer of a socket to size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Buffer {
+    Receive,
+    Send,
+}
+
+impl Buffer {
+    /// The option to ask with, the one that ignores `net.core.[rw]mem_max`
+    /// given CAP_NET_ADMIN, and the one to read the size back with.
+    fn options(&self) -> (c_int, c_int) {
+        match self {
+            Buffer::Receive => (libc::SO_RCVBUF, libc::SO_RCVBUFFORCE),
+            Buffer::Send => (libc::SO_SNDBUF, libc::SO_SNDBUFFORCE),
+        }
+    }
+}
+
+/// An AF_PACKET socket of our own, so options pnet keeps to itself can be
+/// set on it. It receives nothing until `attach` binds it.
+pub struct PacketSocket {
+    fd: c_int,
+}
+
+impl PacketSocket {
+    pub fn new() -> Result<Self, String> {
+        // protocol 0 until bound, or frames of every interface pour in meanwhile
+        let fd = unsafe { libc::socket(libc::AF_PACKET, libc::SOCK_RAW, 0) };
+        if fd < 0 {
+            return Err(format!(
+                "cannot open a packet socket: {}",
+                io::Error::last_os_error()
+            ));
+        }
+        Ok(PacketSocket { fd })
+    }
+
+    pub fn fd(&self) -> c_int {
+        self.fd
+    }
+
+    pub fn set_option<T>(&self, level: c_int, name: c_int, value: &T) -> io::Result<()> {
+        let result = unsafe {
+            libc::setsockopt(
+                self.fd,
+                level,
+                name,
+                value as *const T as *const libc::c_void,
+                mem::size_of::<T>() as libc::socklen_t,
+            )
+        };
+        match result {
+            0 => Ok(()),
+            _ => Err(io::Error::last_os_error()),
+        }
+    }
+
+    /// Reads an option the kernel fills in whole, such as a counter struct.
+    pub fn get_option<T>(&self, level: c_int, name: c_int) -> io::Result<T> {
+        let mut value: T = unsafe { mem::zeroed() };
+        let mut length = mem::size_of::<T>() as libc::socklen_t;
+        let result = unsafe {
+            libc::getsockopt(
+                self.fd,
+                level,
+                name,
+                &mut value as *mut T as *mut libc::c_void,
+                &mut length,
+            )
+        };
+        match result {
+            0 => Ok(value),
+            _ => Err(io::Error::last_os_error()),
+        }
+    }
+
+    /// Asks for a `size` byte kernel buffer, past `net.core.[rw]mem_max`
+    /// if we may, and returns the size the kernel reports back. That is
+    /// twice what it granted, the other half being its bookkeeping.
+    pub fn set_buffer(&self, buffer: Buffer, size: usize) -> io::Result<usize> {
+        let (option, force) = buffer.options();
+        let size = size.min(c_int::MAX as usize / 2) as c_int;
+        if self.set_option(libc::SOL_SOCKET, force, &size).is_err() {
+            self.set_option(libc::SOL_SOCKET, option, &size)?;
+        }
+        self.buffer(buffer)
+    }
+
+    pub fn buffer(&self, buffer: Buffer) -> io::Result<usize> {
+        let size: c_int = self.get_option(libc::SOL_SOCKET, buffer.options().0)?;
+        Ok(size.max(0) as usize)
+    }
+
+    /// Makes `receive` give up after `timeout` without a frame.
+    pub fn set_read_timeout(&self, timeout: Duration) -> io::Result<()> {
+        let timeout = libc::timeval {
+            tv_sec: timeout.as_secs() as libc::time_t,
+            tv_usec: timeout.subsec_micros() as libc::suseconds_t,
+        };
+        self.set_option(libc::SOL_SOCKET, libc::SO_RCVTIMEO, &timeout)
+    }
+
+    /// Binds to `interface` for every protocol, then enters promiscuous
+    /// mode and joins the fanout group if asked to.
+    pub fn attach(
+        &self,
+        interface: &NetworkInterface,
+        promiscuous: bool,
+        fanout: Option<Fanout>,
+    ) -> Result<(), String> {
+        let error = |what: &str, e: io::Error| format!("cannot {}: {}", what, e);
+
+        let mut address: libc::sockaddr_ll = unsafe { mem::zeroed() };
+        address.sll_family = libc::AF_PACKET as u16;
+        address.sll_protocol = (libc::ETH_P_ALL as u16).to_be();
+        address.sll_ifindex = interface.index as c_int;
+        let bound = unsafe {
+            libc::bind(
+                self.fd,
+                &address as *const libc::sockaddr_ll as *const libc::sockaddr,
+                mem::size_of::<libc::sockaddr_ll>() as libc::socklen_t,
+            )
+        };
+        if bound < 0 {
+            let what = format!("bind to {}", interface.name);
+            return Err(error(&what, io::Error::last_os_error()));
+        }
+
+        if promiscuous {
+            let mut membership: libc::packet_mreq = unsafe { mem::zeroed() };
+            membership.mr_ifindex = interface.index as c_int;
+            membership.mr_type = libc::PACKET_MR_PROMISC as u16;
+            self.set_option(libc::SOL_PACKET, libc::PACKET_ADD_MEMBERSHIP, &membership)
+                .map_err(|e| error("enter promiscuous mode", e))?;
+        }
+
+        if let Some(fanout) = fanout {
+            let mut mode = match fanout.mode {
+                FanoutMode::Hash => libc::PACKET_FANOUT_HASH,
+                FanoutMode::LoadBalance => libc::PACKET_FANOUT_LB,
+                FanoutMode::Cpu => libc::PACKET_FANOUT_CPU,
+                FanoutMode::Rollover => libc::PACKET_FANOUT_ROLLOVER,
+                FanoutMode::Random => libc::PACKET_FANOUT_RND,
+                FanoutMode::QueueMapping => libc::PACKET_FANOUT_QM,
+            };
+            if fanout.defrag {
+                mode |= libc::PACKET_FANOUT_FLAG_DEFRAG;
+            }
+            if fanout.rollover {
+                mode |= libc::PACKET_FANOUT_FLAG_ROLLOVER;
+            }
+            let argument = (fanout.group as u32 | (mode << 16)) as c_int;
+            self.set_option(libc::SOL_PACKET, libc::PACKET_FANOUT, &argument)
+                .map_err(|e| error(&format!("join fanout group {}", fanout.group), e))?;
+        }
+
+        Ok(())
+    }
+
+    /// The kernel's counts since the previous call; reading them resets
+    /// them. Sockets with a ring read theirs with `Ring::statistics`.
+    pub fn statistics(&self) -> io::Result<KernelStats> {
+        let stats: libc::tpacket_stats =
+            self.get_option(libc::SOL_PACKET, libc::PACKET_STATISTICS)?;
+
+        Ok(KernelStats {
+            packets: stats.tp_packets as u64,
+            drops: stats.tp_drops as u64,
+            freezes: 0,
+        })
+    }
+
+    /// Reads one frame into `buffer` and returns its length, cut to the
+    /// buffer if the frame was longer.
+    pub fn receive(&self, buffer: &mut [u8]) -> io::Result<usize> {
+        let length = unsafe {
+            libc::recv(
+                self.fd,
+                buffer.as_mut_ptr() as *mut libc::c_void,
+                buffer.len(),
+                libc::MSG_TRUNC,
+            )
+        };
+        match length {
+            length if length < 0 => Err(io::Error::last_os_error()),
+            length => Ok((length as usize).min(buffer.len())),
+        }
+    }
+}
+
+impl Drop for PacketSocket {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.fd);
+        }
+    }
+}
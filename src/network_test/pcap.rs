@@ -0,0 +1,210 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, ErrorKind, Read};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The only link type the dissectors understand.
+const LINKTYPE_ETHERNET: u32 = 1;
+/// Larger records only occur in damaged files.
+const MAX_FRAME_SIZE: usize = 256 * 1024;
+
+/// Reads frames from a classic libpcap file, as written by tcpdump `-w`.
+pub struct PcapReader {
+    reader: BufReader<File>,
+    big_endian: bool,
+    /// timestamps carry nanoseconds instead of microseconds
+    nanos: bool,
+}
+
+impl PcapReader {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut header = [0; 24];
+        reader.read_exact(&mut header)?;
+
+        let (big_endian, nanos) = match header[..4] {
+            [0xa1, 0xb2, 0xc3, 0xd4] => (true, false),
+            [0xd4, 0xc3, 0xb2, 0xa1] => (false, false),
+            [0xa1, 0xb2, 0x3c, 0x4d] => (true, true),
+            [0x4d, 0x3c, 0xb2, 0xa1] => (false, true),
+            [0x0a, 0x0d, 0x0d, 0x0a] => {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidData,
+                    "pcapng is not supported, convert it with `editcap -F pcap`",
+                ))
+            }
+            _ => return Err(io::Error::new(ErrorKind::InvalidData, "not a pcap file")),
+        };
+
+        let pcap = PcapReader {
+            reader,
+            big_endian,
+            nanos,
+        };
+        let link_type = pcap.u32(&header[20..24]);
+        if link_type != LINKTYPE_ETHERNET {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("link type {} is not Ethernet", link_type),
+            ));
+        }
+
+        Ok(pcap)
+    }
+
+    fn u32(&self, bytes: &[u8]) -> u32 {
+        let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+        if self.big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        }
+    }
+
+    /// The next frame and when it was captured, or `None` at the end of the file.
+    pub fn next_frame(&mut self) -> io::Result<Option<(SystemTime, Vec<u8>)>> {
+        // only a file that ends between records ends cleanly
+        if self.reader.fill_buf()?.is_empty() {
+            return Ok(None);
+        }
+        let mut header = [0; 16];
+        self.reader.read_exact(&mut header)?;
+
+        let seconds = self.u32(&header[0..4]) as u64;
+        let fraction = self.u32(&header[4..8]);
+        let captured = self.u32(&header[8..12]) as usize;
+
+        let fraction = if self.nanos {
+            Duration::from_nanos(fraction as u64)
+        } else {
+            Duration::from_micros(fraction as u64)
+        };
+        let timestamp = UNIX_EPOCH + Duration::from_secs(seconds) + fraction;
+
+        if captured > MAX_FRAME_SIZE {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("record of {} bytes, the file is probably corrupt", captured),
+            ));
+        }
+        let mut data = vec![0; captured];
+        self.reader.read_exact(&mut data)?;
+
+        Ok(Some((timestamp, data)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A capture file under the system temp dir, removed on drop.
+    struct Capture(std::path::PathBuf);
+
+    impl Capture {
+        fn new(name: &str, bytes: &[u8]) -> Self {
+            let path = std::env::temp_dir().join(format!(
+                "network-test-pcap-{}-{}.pcap",
+                std::process::id(),
+                name
+            ));
+            std::fs::write(&path, bytes).unwrap();
+            Capture(path)
+        }
+    }
+
+    impl Drop for Capture {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    /// A file with one 4-byte frame at 1.5 s, written in either byte order.
+    fn file(big_endian: bool, nanos: bool) -> Vec<u8> {
+        let word = |value: u32| match big_endian {
+            true => value.to_be_bytes(),
+            false => value.to_le_bytes(),
+        };
+        let half = |value: u16| match big_endian {
+            true => value.to_be_bytes(),
+            false => value.to_le_bytes(),
+        };
+
+        let mut bytes = word(if nanos { 0xa1b2_3c4d } else { 0xa1b2_c3d4 }).to_vec();
+        bytes.extend_from_slice(&half(2));
+        bytes.extend_from_slice(&half(4));
+        bytes.extend_from_slice(&[0; 8]);
+        bytes.extend_from_slice(&word(65535));
+        bytes.extend_from_slice(&word(LINKTYPE_ETHERNET));
+
+        bytes.extend_from_slice(&word(1));
+        bytes.extend_from_slice(&word(if nanos { 500_000_000 } else { 500_000 }));
+        bytes.extend_from_slice(&word(4));
+        bytes.extend_from_slice(&word(60));
+        bytes.extend_from_slice(&[1, 2, 3, 4]);
+        bytes
+    }
+
+    fn read_all(bytes: &[u8], name: &str) -> io::Result<Vec<(SystemTime, Vec<u8>)>> {
+        let capture = Capture::new(name, bytes);
+        let mut reader = PcapReader::open(&capture.0)?;
+        let mut frames = vec![];
+        while let Some(frame) = reader.next_frame()? {
+            frames.push(frame);
+        }
+        Ok(frames)
+    }
+
+    #[test]
+    fn both_byte_orders_and_resolutions() {
+        let expected = vec![(UNIX_EPOCH + Duration::from_millis(1500), vec![1, 2, 3, 4])];
+        for (big_endian, nanos) in [(false, false), (true, false), (false, true), (true, true)] {
+            let name = format!("order-{}-{}", big_endian, nanos);
+            let frames = read_all(&file(big_endian, nanos), &name).unwrap();
+            assert_eq!(
+                frames, expected,
+                "big endian {}, nanos {}",
+                big_endian, nanos
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        let error = |bytes: &[u8], name: &str| read_all(bytes, name).unwrap_err().to_string();
+
+        assert_eq!(error(&[0; 24], "zeros"), "not a pcap file");
+        let short = read_all(&[0xd4, 0xc3, 0xb2, 0xa1, 2, 0], "short").unwrap_err();
+        assert_eq!(short.kind(), ErrorKind::UnexpectedEof);
+
+        let mut pcapng = file(false, false);
+        pcapng[..4].copy_from_slice(&[0x0a, 0x0d, 0x0d, 0x0a]);
+        assert!(error(&pcapng, "pcapng").starts_with("pcapng is not supported"));
+
+        let mut raw_ip = file(true, false);
+        raw_ip[20..24].copy_from_slice(&101u32.to_be_bytes());
+        assert_eq!(error(&raw_ip, "raw"), "link type 101 is not Ethernet");
+    }
+
+    #[test]
+    fn truncated_records() {
+        let whole = file(false, false);
+
+        // cut inside the data and inside the record header
+        for (length, name) in [(whole.len() - 1, "data"), (24 + 10, "header")] {
+            let error = read_all(&whole[..length], name).unwrap_err();
+            assert_eq!(
+                error.kind(),
+                ErrorKind::UnexpectedEof,
+                "cut in the {}",
+                name
+            );
+        }
+        assert_eq!(read_all(&whole[..24], "empty").unwrap(), []);
+
+        let mut huge = whole.clone();
+        huge[32..36].copy_from_slice(&(MAX_FRAME_SIZE as u32 + 1).to_le_bytes());
+        let error = read_all(&huge, "huge").unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,800 @@
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use pnet::datalink::{DataLinkReceiver, NetworkInterface};
+use pnet::packet::ip::IpNextHeaderProtocols;
+use pnet::packet::Packet;
+use pnet::transport::{self, TransportChannelType, TransportProtocol, TransportSender};
+
+use crate::network_test::capture;
+use crate::network_test::datalink::EthernetIIFrame;
+use crate::network_test::flow::{TCP_ACK, TCP_RST, TCP_SYN};
+use crate::network_test::network::{IPv6Packet, PacketType};
+use crate::network_test::transport::{ICMPMessage, TCPSegment, TransportSegment, UDPSegment};
+use crate::network_test::util;
+
+/// Destination port of UDP probes unless one is given, as in traceroute(8).
+const UDP_PORT: u16 = 33434;
+const TCP_PORT: u16 = 80;
+/// Bytes after the ICMP or UDP header, making an echo request 64 bytes like ping(8).
+const PAYLOAD_SIZE: usize = 56;
+const PAYLOAD_PATTERN: &[u8] = b"network-test ";
+
+/// What a probe is made of.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ProbeProtocol {
+    /// ICMP or ICMPv6 echo request
+    Icmp,
+    /// TCP SYN, answered by SYN-ACK or RST
+    Tcp,
+    /// UDP datagram, answered by port unreachable when nothing listens
+    Udp,
+}
+
+/// Everything `ping` needs besides the target.
+#[derive(Debug, Clone)]
+pub struct PingOptions {
+    pub protocol: ProbeProtocol,
+    /// destination port of TCP and UDP probes
+    pub port: Option<u16>,
+    /// stop after this many probes; Ctrl-C stops earlier
+    pub count: Option<u64>,
+    pub interval: Duration,
+    /// how long to wait for each answer
+    pub timeout: Duration,
+    pub ttl: u8,
+    /// capture answers here instead of on the interface owning the source address
+    pub interface: Option<String>,
+}
+
+/// Everything `traceroute` needs besides the target.
+#[derive(Debug, Clone)]
+pub struct TracerouteOptions {
+    pub protocol: ProbeProtocol,
+    pub port: Option<u16>,
+    pub first_ttl: u8,
+    pub max_hops: u8,
+    /// probes sent per hop
+    pub queries: usize,
+    pub timeout: Duration,
+    pub interface: Option<String>,
+}
+
+/// How a probe was answered.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum ReplyKind {
+    EchoReply,
+    SynAck,
+    Reset,
+    /// a UDP datagram back from the probed port
+    Datagram,
+    TimeExceeded,
+    Unreachable {
+        /// port unreachable, which means the target itself answered
+        port: bool,
+        name: &'static str,
+        /// traceroute's annotation, e.g. `!H`
+        mark: &'static str,
+    },
+}
+
+#[derive(Debug, Clone)]
+struct Reply {
+    /// the probe answered, when the answer tells
+    sequence: Option<u16>,
+    from: IpAddr,
+    ttl: u8,
+    /// bytes of the answer above the IP header
+    length: usize,
+    kind: ReplyKind,
+    received: Instant,
+}
+
+impl Reply {
+    /// The target itself answered the probe.
+    fn is_answer(&self, target: IpAddr) -> bool {
+        self.from == target
+            && match self.kind {
+                ReplyKind::TimeExceeded => false,
+                ReplyKind::Unreachable { port, .. } => port,
+                _ => true,
+            }
+    }
+
+    fn describe(&self) -> String {
+        match &self.kind {
+            ReplyKind::EchoReply => format!("{} bytes from {}", self.length, self.from),
+            ReplyKind::SynAck => format!("SYN-ACK from {}", self.from),
+            ReplyKind::Reset => format!("RST from {}", self.from),
+            ReplyKind::Datagram => format!("{} bytes of UDP from {}", self.length, self.from),
+            ReplyKind::TimeExceeded => format!("Time exceeded from {}", self.from),
+            ReplyKind::Unreachable { name, .. } => format!("{} from {}", name, self.from),
+        }
+    }
+}
+
+/// `pnet` sends anything that is a `Packet`.
+struct RawProbe<'a>(&'a [u8]);
+
+impl Packet for RawProbe<'_> {
+    fn packet(&self) -> &[u8] {
+        self.0
+    }
+
+    fn payload(&self) -> &[u8] {
+        &[]
+    }
+}
+
+/// Sends probes through a raw socket and picks their answers out of a
+/// capture on the interface they come back on.
+///
+/// Every probe of a run belongs to the same flow, as in Paris traceroute:
+/// the ports, the ICMP identifier and the ICMP checksum never change, so
+/// load balancers keep them on one path. Probes are told apart by fields
+/// that ICMP errors quote back but that do not take part in flow hashing:
+/// the ICMP sequence, the TCP sequence number and the UDP checksum.
+struct Prober {
+    target: IpAddr,
+    source: IpAddr,
+    protocol: ProbeProtocol,
+    source_port: u16,
+    port: u16,
+    identifier: u16,
+    /// TCP sequence number of probe 0
+    base_sequence: u32,
+    /// probe sequence of every UDP checksum sent
+    checksums: HashMap<u16, u16>,
+    sender: TransportSender,
+    receiver: Box<dyn DataLinkReceiver>,
+    interface: String,
+}
+
+impl Prober {
+    fn new(
+        target: IpAddr,
+        protocol: ProbeProtocol,
+        port: Option<u16>,
+        interface: Option<&str>,
+    ) -> Result<Self, String> {
+        let source = source_for(target)?;
+        let interface = find_interface(target, source, interface)?;
+
+        let next_header = match (protocol, target) {
+            (ProbeProtocol::Icmp, IpAddr::V4(_)) => IpNextHeaderProtocols::Icmp,
+            (ProbeProtocol::Icmp, IpAddr::V6(_)) => IpNextHeaderProtocols::Icmpv6,
+            (ProbeProtocol::Tcp, _) => IpNextHeaderProtocols::Tcp,
+            (ProbeProtocol::Udp, _) => IpNextHeaderProtocols::Udp,
+        };
+        let channel = match target {
+            IpAddr::V4(_) => TransportProtocol::Ipv4(next_header),
+            IpAddr::V6(_) => TransportProtocol::Ipv6(next_header),
+        };
+        let (sender, _) = transport::transport_channel(4096, TransportChannelType::Layer4(channel))
+            .map_err(|e| {
+                format!(
+                    "cannot open a raw socket, which needs root or CAP_NET_RAW: {}",
+                    e
+                )
+            })?;
+        let receiver = capture::open_receiver(&interface)?;
+
+        let process = std::process::id();
+        let clock = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        Ok(Prober {
+            target,
+            source,
+            protocol,
+            // above the usual ephemeral range, so no local socket shares the flow
+            source_port: 61000 + (process % 4000) as u16,
+            port: port.unwrap_or(match protocol {
+                ProbeProtocol::Tcp => TCP_PORT,
+                _ => UDP_PORT,
+            }),
+            identifier: process as u16,
+            base_sequence: clock.subsec_nanos(),
+            checksums: HashMap::new(),
+            sender,
+            receiver,
+            interface: interface.name,
+        })
+    }
+
+    fn protocol_number(&self) -> u8 {
+        match (self.protocol, self.target) {
+            (ProbeProtocol::Icmp, IpAddr::V4(_)) => 1,
+            (ProbeProtocol::Icmp, IpAddr::V6(_)) => 58,
+            (ProbeProtocol::Tcp, _) => 6,
+            (ProbeProtocol::Udp, _) => 17,
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self.protocol {
+            ProbeProtocol::Icmp => String::from("ICMP echo"),
+            ProbeProtocol::Tcp => format!("TCP SYN to port {}", self.port),
+            ProbeProtocol::Udp => format!("UDP to port {}", self.port),
+        }
+    }
+
+    /// The transport header and payload of probe `sequence`.
+    fn build(&self, sequence: u16) -> Vec<u8> {
+        let mut payload: Vec<u8> = PAYLOAD_PATTERN
+            .iter()
+            .copied()
+            .cycle()
+            .take(PAYLOAD_SIZE)
+            .collect();
+
+        let (mut probe, checksum_at) = match self.protocol {
+            ProbeProtocol::Icmp => {
+                let icmp_type = if self.target.is_ipv4() { 8 } else { 128 };
+                let mut probe = vec![icmp_type, 0, 0, 0];
+                probe.extend(self.identifier.to_be_bytes());
+                probe.extend(sequence.to_be_bytes());
+                // the complement of the sequence cancels it out of the checksum
+                payload[..2].copy_from_slice(&(!sequence).to_be_bytes());
+                probe.extend(payload);
+                (probe, 2)
+            }
+            ProbeProtocol::Udp => {
+                // the sequence in the payload is what makes the checksums differ
+                payload[..2].copy_from_slice(&sequence.to_be_bytes());
+                let mut probe = Vec::new();
+                probe.extend(self.source_port.to_be_bytes());
+                probe.extend(self.port.to_be_bytes());
+                probe.extend(((8 + payload.len()) as u16).to_be_bytes());
+                probe.extend([0, 0]);
+                probe.extend(payload);
+                (probe, 6)
+            }
+            ProbeProtocol::Tcp => {
+                let mut probe = Vec::new();
+                probe.extend(self.source_port.to_be_bytes());
+                probe.extend(self.port.to_be_bytes());
+                probe.extend(
+                    self.base_sequence
+                        .wrapping_add(sequence as u32)
+                        .to_be_bytes(),
+                );
+                probe.extend([0; 4]);
+                // 24 bytes of header, the last 4 an MSS option
+                probe.extend([6 << 4, TCP_SYN]);
+                probe.extend(64240u16.to_be_bytes());
+                probe.extend([0, 0, 0, 0]);
+                probe.extend([2, 4, 0x05, 0xb4]);
+                (probe, 16)
+            }
+        };
+
+        let checksum = match (self.protocol, self.target) {
+            (ProbeProtocol::Icmp, IpAddr::V4(_)) => util::internet_checksum(&probe),
+            _ => {
+                let pseudo = util::pseudo_header(
+                    self.source,
+                    self.target,
+                    self.protocol_number(),
+                    probe.len(),
+                );
+                match util::internet_checksum(&[pseudo, probe.clone()].concat()) {
+                    // zero means no checksum to UDP
+                    0 => 0xffff,
+                    checksum => checksum,
+                }
+            }
+        };
+        probe[checksum_at..checksum_at + 2].copy_from_slice(&checksum.to_be_bytes());
+        probe
+    }
+
+    /// Sends probe `sequence` with `ttl` and returns when it left.
+    fn send(&mut self, sequence: u16, ttl: u8) -> Result<Instant, String> {
+        let probe = self.build(sequence);
+        if self.protocol == ProbeProtocol::Udp {
+            self.checksums
+                .insert(u16::from_be_bytes([probe[6], probe[7]]), sequence);
+        }
+
+        self.sender
+            .set_ttl(ttl)
+            .map_err(|e| format!("cannot set the TTL to {}: {}", ttl, e))?;
+        let sent = Instant::now();
+        self.sender
+            .send_to(RawProbe(&probe), self.target)
+            .map_err(|e| format!("cannot send a probe to {}: {}", self.target, e))?;
+        Ok(sent)
+    }
+
+    /// The next answer to any probe, or `None` once `deadline` passed or `stop` was set.
+    fn receive(&mut self, deadline: Instant, stop: &AtomicBool) -> Option<Reply> {
+        while Instant::now() < deadline && !stop.load(Ordering::Relaxed) {
+            match self.receiver.next() {
+                Ok(data) => {
+                    let received = Instant::now();
+                    let reply = EthernetIIFrame::new(data).and_then(|frame| self.classify(&frame));
+                    if let Some(mut reply) = reply {
+                        reply.received = received;
+                        return Some(reply);
+                    }
+                }
+                Err(e) if e.kind() == ErrorKind::TimedOut => {}
+                Err(e) => {
+                    eprintln!("An error occurred while reading: {}", e);
+                    return None;
+                }
+            }
+        }
+        None
+    }
+
+    /// Turns `frame` into a reply if it answers one of the probes.
+    fn classify(&self, frame: &EthernetIIFrame) -> Option<Reply> {
+        let reply = |sequence, from, ttl, length, kind| {
+            Some(Reply {
+                sequence,
+                from,
+                ttl,
+                length,
+                kind,
+                received: Instant::now(),
+            })
+        };
+
+        match frame.get_network_packet() {
+            PacketType::IPv4(packet) => {
+                let from = IpAddr::V4(packet.get_source());
+                let ttl = packet.get_ttl();
+                match packet.get_transport_segment() {
+                    TransportSegment::ICMP(segment) => match segment.get_message() {
+                        ICMPMessage::Echo {
+                            identifier,
+                            sequence,
+                            payload,
+                        } if segment.get_type() == 0
+                            && self.protocol == ProbeProtocol::Icmp
+                            && *identifier == self.identifier
+                            && from == self.target =>
+                        {
+                            reply(
+                                Some(*sequence),
+                                from,
+                                ttl,
+                                payload.len() + 8,
+                                ReplyKind::EchoReply,
+                            )
+                        }
+                        ICMPMessage::TimeExceeded {
+                            original: Some(original),
+                        }
+                        | ICMPMessage::DestinationUnreachable {
+                            original: Some(original),
+                            ..
+                        } => {
+                            let quoted = &original.packet;
+                            if IpAddr::V4(quoted.get_destination()) != self.target
+                                || quoted.get_protocol() != self.protocol_number()
+                            {
+                                return None;
+                            }
+                            let sequence = self.quoted(&original.transport)?;
+                            let code = segment.get_code();
+                            let kind = match segment.get_type() {
+                                11 => ReplyKind::TimeExceeded,
+                                _ => ReplyKind::Unreachable {
+                                    port: code == 3,
+                                    name: segment.code_name().unwrap_or("Destination unreachable"),
+                                    mark: match code {
+                                        0 | 6 => "!N",
+                                        1 | 7 => "!H",
+                                        2 => "!P",
+                                        3 => "",
+                                        4 => "!F",
+                                        5 => "!S",
+                                        9 | 10 | 13 => "!X",
+                                        _ => "!",
+                                    },
+                                },
+                            };
+                            reply(Some(sequence), from, ttl, 0, kind)
+                        }
+                        _ => None,
+                    },
+                    TransportSegment::TCP(segment) => {
+                        let sequence = self.tcp_answer(from, segment)?;
+                        let kind = match segment.get_flags() & TCP_RST {
+                            0 => ReplyKind::SynAck,
+                            _ => ReplyKind::Reset,
+                        };
+                        reply(Some(sequence), from, ttl, 0, kind)
+                    }
+                    TransportSegment::UDP(segment) => {
+                        self.udp_answer(from, segment)?;
+                        let length = segment.get_data().len() + 8;
+                        reply(None, from, ttl, length, ReplyKind::Datagram)
+                    }
+                    _ => None,
+                }
+            }
+            PacketType::IPv6(packet) => {
+                let from = IpAddr::V6(packet.get_source());
+                let ttl = packet.get_hop_limit();
+                let payload = packet.get_payload();
+                match packet.get_next_header() {
+                    58 if payload.len() >= 8 => {
+                        let (icmp_type, code) = (payload[0], payload[1]);
+                        let word = |at: usize| u16::from_be_bytes([payload[at], payload[at + 1]]);
+                        match icmp_type {
+                            129 if self.protocol == ProbeProtocol::Icmp
+                                && word(4) == self.identifier
+                                && from == self.target =>
+                            {
+                                reply(
+                                    Some(word(6)),
+                                    from,
+                                    ttl,
+                                    payload.len(),
+                                    ReplyKind::EchoReply,
+                                )
+                            }
+                            1 | 3 => {
+                                let quoted = IPv6Packet::new(&payload[8..])?;
+                                if IpAddr::V6(quoted.get_destination()) != self.target
+                                    || quoted.get_next_header() != self.protocol_number()
+                                {
+                                    return None;
+                                }
+                                let sequence = self.quoted(quoted.get_payload())?;
+                                let kind = match icmp_type {
+                                    3 => ReplyKind::TimeExceeded,
+                                    _ => {
+                                        let (name, mark) = icmpv6_unreachable(code);
+                                        ReplyKind::Unreachable {
+                                            port: code == 4,
+                                            name,
+                                            mark,
+                                        }
+                                    }
+                                };
+                                reply(Some(sequence), from, ttl, 0, kind)
+                            }
+                            _ => None,
+                        }
+                    }
+                    6 => {
+                        let segment = TCPSegment::new(payload)?;
+                        let sequence = self.tcp_answer(from, &segment)?;
+                        let kind = match segment.get_flags() & TCP_RST {
+                            0 => ReplyKind::SynAck,
+                            _ => ReplyKind::Reset,
+                        };
+                        reply(Some(sequence), from, ttl, 0, kind)
+                    }
+                    17 => {
+                        let segment = UDPSegment::new(payload)?;
+                        self.udp_answer(from, &segment)?;
+                        let length = segment.get_data().len() + 8;
+                        reply(None, from, ttl, length, ReplyKind::Datagram)
+                    }
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// The probe a SYN-ACK or RST from the target answers.
+    fn tcp_answer(&self, from: IpAddr, segment: &TCPSegment) -> Option<u16> {
+        let flags = segment.get_flags();
+        let answers = self.protocol == ProbeProtocol::Tcp
+            && from == self.target
+            && segment.get_source_port() == self.port
+            && segment.get_destination_port() == self.source_port
+            && flags & TCP_ACK != 0
+            && (flags & TCP_RST != 0 || flags & TCP_SYN != 0);
+        if !answers {
+            return None;
+        }
+        let acknowledged = segment.get_acknowledgement_number().wrapping_sub(1);
+        let sequence = acknowledged.wrapping_sub(self.base_sequence);
+        (sequence <= u16::MAX as u32).then_some(sequence as u16)
+    }
+
+    fn udp_answer(&self, from: IpAddr, segment: &UDPSegment) -> Option<()> {
+        (self.protocol == ProbeProtocol::Udp
+            && from == self.target
+            && segment.get_source_port() == self.port
+            && segment.get_destination_port() == self.source_port)
+            .then_some(())
+    }
+
+    /// The probe whose first transport bytes an ICMP error quotes.
+    fn quoted(&self, transport: &[u8]) -> Option<u16> {
+        if transport.len() < 8 {
+            return None;
+        }
+        let word = |at: usize| u16::from_be_bytes([transport[at], transport[at + 1]]);
+        let ports = word(0) == self.source_port && word(2) == self.port;
+
+        match self.protocol {
+            ProbeProtocol::Icmp if word(4) == self.identifier => Some(word(6)),
+            ProbeProtocol::Udp if ports => self.checksums.get(&word(6)).copied(),
+            ProbeProtocol::Tcp if ports => {
+                let number =
+                    u32::from_be_bytes([transport[4], transport[5], transport[6], transport[7]]);
+                let sequence = number.wrapping_sub(self.base_sequence);
+                (sequence <= u16::MAX as u32).then_some(sequence as u16)
+            }
+            _ => None,
+        }
+    }
+}
+
+fn icmpv6_unreachable(code: u8) -> (&'static str, &'static str) {
+    match code {
+        0 => ("No route to destination", "!N"),
+        1 => ("Communication administratively prohibited", "!X"),
+        2 => ("Beyond scope of source address", "!S"),
+        3 => ("Address unreachable", "!H"),
+        4 => ("Port unreachable", ""),
+        5 => ("Source address failed ingress/egress policy", "!X"),
+        6 => ("Reject route to destination", "!X"),
+        _ => ("Destination unreachable", "!"),
+    }
+}
+
+fn resolve(target: &str) -> Result<IpAddr, String> {
+    if let Ok(address) = target.parse() {
+        return Ok(address);
+    }
+    (target, 0)
+        .to_socket_addrs()
+        .map_err(|e| format!("cannot resolve {}: {}", target, e))?
+        .next()
+        .map(|address| address.ip())
+        .ok_or(format!("{} has no address", target))
+}
+
+/// The address the kernel would send from to reach `target`.
+fn source_for(target: IpAddr) -> Result<IpAddr, String> {
+    let any: SocketAddr = match target {
+        IpAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        IpAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    // connecting a UDP socket only looks up the route, nothing is sent
+    let socket = UdpSocket::bind(any).map_err(|e| format!("cannot open a socket: {}", e))?;
+    socket
+        .connect((target, 9))
+        .map_err(|e| format!("no route to {}: {}", target, e))?;
+    socket
+        .local_addr()
+        .map(|address| address.ip())
+        .map_err(|e| format!("no route to {}: {}", target, e))
+}
+
+/// The interface answers from `target` arrive on: `name` if given, the
+/// loopback for local addresses, otherwise the one owning `source`.
+fn find_interface(
+    target: IpAddr,
+    source: IpAddr,
+    name: Option<&str>,
+) -> Result<NetworkInterface, String> {
+    let interfaces = pnet::datalink::interfaces();
+    let owns = |interface: &NetworkInterface, address: IpAddr| {
+        interface.ips.iter().any(|network| network.ip() == address)
+    };
+
+    let found = match name {
+        Some(name) => interfaces.into_iter().find(|x| x.name == name),
+        None if target.is_loopback() || interfaces.iter().any(|x| owns(x, target)) => {
+            interfaces.into_iter().find(|x| x.is_loopback())
+        }
+        None => interfaces.into_iter().find(|x| owns(x, source)),
+    };
+    found.ok_or(match name {
+        Some(name) => format!("no interface is named {}", name),
+        None => format!("no interface has the address {}", source),
+    })
+}
+
+/// Set by Ctrl-C and SIGTERM.
+fn stop_signal() -> Arc<AtomicBool> {
+    let stop = Arc::new(AtomicBool::new(false));
+    let handler_stop = Arc::clone(&stop);
+    if let Err(e) = ctrlc::set_handler(move || handler_stop.store(true, Ordering::Relaxed)) {
+        eprintln!("cannot install the signal handler: {}", e);
+    }
+    stop
+}
+
+fn milliseconds(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+/// Probes `target` until `count` probes were sent or Ctrl-C, then prints
+/// loss and RTT statistics.
+pub fn ping(target: &str, options: PingOptions) -> Result<(), String> {
+    let target = resolve(target)?;
+    let mut prober = Prober::new(
+        target,
+        options.protocol,
+        options.port,
+        options.interface.as_deref(),
+    )?;
+    let stop = stop_signal();
+
+    println!(
+        "PING {} from {} via {}, {}",
+        target,
+        prober.source,
+        prober.interface,
+        prober.describe()
+    );
+
+    let mut rtts: Vec<Duration> = vec![];
+    let mut sent: u64 = 0;
+    let mut sequence: u16 = 0;
+    while !stop.load(Ordering::Relaxed) && options.count.is_none_or(|count| sent < count) {
+        let started = prober.send(sequence, options.ttl)?;
+        sent += 1;
+
+        let mut answered = false;
+        while let Some(reply) = prober.receive(started + options.timeout, &stop) {
+            // late answers to earlier probes no longer count
+            if reply.sequence.is_some_and(|answered| answered != sequence) {
+                continue;
+            }
+            let rtt = reply.received.duration_since(started);
+            println!(
+                "{}: seq={} ttl={} time={:.3} ms",
+                reply.describe(),
+                sequence,
+                reply.ttl,
+                milliseconds(rtt)
+            );
+            if reply.is_answer(target) {
+                rtts.push(rtt);
+            }
+            answered = true;
+            break;
+        }
+        if !answered && !stop.load(Ordering::Relaxed) {
+            println!("no answer to seq={}", sequence);
+        }
+
+        sequence = sequence.wrapping_add(1);
+        if options.count.is_some_and(|count| sent >= count) {
+            break;
+        }
+        while !stop.load(Ordering::Relaxed) && started.elapsed() < options.interval {
+            thread::sleep(
+                Duration::from_millis(50).min(options.interval.saturating_sub(started.elapsed())),
+            );
+        }
+    }
+
+    println!("\n--- {} ping statistics ---", target);
+    let received = rtts.len() as u64;
+    let loss = match sent {
+        0 => 0.0,
+        sent => (sent - received) as f64 * 100.0 / sent as f64,
+    };
+    println!(
+        "{} probes sent, {} answered, {:.1}% loss",
+        sent, received, loss
+    );
+    if !rtts.is_empty() {
+        let values: Vec<f64> = rtts.iter().copied().map(milliseconds).collect();
+        let count = values.len() as f64;
+        let average = values.iter().sum::<f64>() / count;
+        let variance = values
+            .iter()
+            .map(|value| (value - average).powi(2))
+            .sum::<f64>()
+            / count;
+        println!(
+            "rtt min/avg/max/stddev = {:.3}/{:.3}/{:.3}/{:.3} ms",
+            values.iter().copied().fold(f64::INFINITY, f64::min),
+            average,
+            values.iter().copied().fold(0.0, f64::max),
+            variance.sqrt()
+        );
+    }
+
+    Ok(())
+}
+
+/// Prints the hops towards `target`, raising the TTL or hop limit one at a
+/// time until the target answers, a hop reports it unreachable or
+/// `max_hops` is passed.
+pub fn traceroute(target: &str, options: TracerouteOptions) -> Result<(), String> {
+    let target = resolve(target)?;
+    let mut prober = Prober::new(
+        target,
+        options.protocol,
+        options.port,
+        options.interface.as_deref(),
+    )?;
+    let stop = stop_signal();
+
+    println!(
+        "traceroute to {} from {} via {}, {} hops max, {}",
+        target,
+        prober.source,
+        prober.interface,
+        options.max_hops,
+        prober.describe()
+    );
+
+    let mut sequence: u16 = 0;
+    for ttl in options.first_ttl.max(1)..=options.max_hops {
+        if stop.load(Ordering::Relaxed) {
+            break;
+        }
+
+        let mut probes: Vec<(u16, Instant)> = vec![];
+        for _ in 0..options.queries {
+            probes.push((sequence, prober.send(sequence, ttl)?));
+            sequence = sequence.wrapping_add(1);
+        }
+
+        let deadline = Instant::now() + options.timeout;
+        let mut replies: HashMap<u16, Reply> = HashMap::new();
+        while replies.len() < probes.len() {
+            let Some(reply) = prober.receive(deadline, &stop) else {
+                break;
+            };
+            // a UDP answer does not say which probe it answers; take the oldest
+            let answered = reply.sequence.or_else(|| {
+                probes
+                    .iter()
+                    .map(|(sequence, _)| *sequence)
+                    .find(|sequence| !replies.contains_key(sequence))
+            });
+            if let Some(answered) = answered {
+                if probes.iter().any(|(sequence, _)| *sequence == answered) {
+                    replies.entry(answered).or_insert(reply);
+                }
+            }
+        }
+
+        let mut line = format!("{:>2} ", ttl);
+        let mut last_from = None;
+        let mut done = false;
+        for (sequence, sent) in &probes {
+            match replies.get(sequence) {
+                Some(reply) => {
+                    if last_from != Some(reply.from) {
+                        line.push_str(&format!(" {}", reply.from));
+                        last_from = Some(reply.from);
+                    }
+                    line.push_str(&format!(
+                        "  {:.3} ms",
+                        milliseconds(reply.received.duration_since(*sent))
+                    ));
+                    if let ReplyKind::Unreachable { mark, .. } = reply.kind {
+                        if !mark.is_empty() {
+                            line.push_str(&format!(" {}", mark));
+                        }
+                    }
+                    done |= reply.is_answer(target)
+                        || matches!(reply.kind, ReplyKind::Unreachable { .. });
+                }
+                None => line.push_str("  *"),
+            }
+        }
+        println!("{}", line);
+
+        if done {
+            break;
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,279 @@
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
+/// When `--save` starts a new file and how many old ones it keeps.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RotationOptions {
+    pub max_bytes: Option<u64>,
+    pub max_age: Option<Duration>,
+    /// files beyond this many, the open one included, are deleted oldest first
+    pub max_files: Option<usize>,
+    /// gzip each file once it is closed
+    pub compress: bool,
+}
+
+/// A log file that rolls over into `<prefix>_<seq>_<YYYYmmddHHMMSS>.txt`.
+pub struct RotatingFile {
+    prefix: String,
+    options: RotationOptions,
+    sequence: u32,
+    current: Option<(PathBuf, BufWriter<File>)>,
+    opened: Instant,
+    written: u64,
+    closed: VecDeque<PathBuf>,
+}
+
+impl RotatingFile {
+    pub fn new(prefix: String, options: RotationOptions) -> io::Result<Self> {
+        let mut file = RotatingFile {
+            prefix,
+            options,
+            sequence: 0,
+            current: None,
+            opened: Instant::now(),
+            written: 0,
+            closed: VecDeque::new(),
+        };
+
+        file.open_next()?;
+        Ok(file)
+    }
+
+    /// Writes one record; records are never split across files.
+    pub fn write_record(&mut self, record: &str) -> io::Result<()> {
+        let length = record.len() as u64 + 1;
+        let full = self
+            .options
+            .max_bytes
+            .is_some_and(|max| self.written > 0 && self.written + length > max);
+        let expired = self
+            .options
+            .max_age
+            .is_some_and(|age| self.opened.elapsed() >= age);
+
+        if full || expired {
+            self.close_current()?;
+            self.open_next()?;
+        }
+
+        if let Some((_, writer)) = self.current.as_mut() {
+            writeln!(writer, "{}", record)?;
+            self.written += length;
+        }
+
+        Ok(())
+    }
+
+    /// Flushes and closes the current file, compressing it if asked to.
+    pub fn finish(mut self) -> io::Result<()> {
+        self.close_current()
+    }
+
+    fn open_next(&mut self) -> io::Result<()> {
+        self.sequence += 1;
+
+        let path = PathBuf::from(format!(
+            "{}_{:05}_{}.txt",
+            self.prefix,
+            self.sequence,
+            chrono::Local::now().format("%Y%m%d%H%M%S")
+        ));
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)?;
+
+        println!("log file >> {}", path.display());
+
+        self.current = Some((path, BufWriter::new(file)));
+        self.opened = Instant::now();
+        self.written = 0;
+
+        // the file just opened counts towards the limit
+        if let Some(max_files) = self.options.max_files {
+            while self.closed.len() + 1 > max_files.max(1) {
+                if let Some(oldest) = self.closed.pop_front() {
+                    fs::remove_file(&oldest)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn close_current(&mut self) -> io::Result<()> {
+        let Some((path, mut writer)) = self.current.take() else {
+            return Ok(());
+        };
+        writer.flush()?;
+        drop(writer);
+
+        let path = if self.options.compress {
+            compress(path)?
+        } else {
+            path
+        };
+        self.closed.push_back(path);
+
+        Ok(())
+    }
+}
+
+fn compress(path: PathBuf) -> io::Result<PathBuf> {
+    let mut compressed = path.clone().into_os_string();
+    compressed.push(".gz");
+    let compressed = PathBuf::from(compressed);
+
+    let mut encoder = GzEncoder::new(File::create(&compressed)?, Compression::default());
+    io::copy(&mut File::open(&path)?, &mut encoder)?;
+    encoder.finish()?;
+    fs::remove_file(&path)?;
+
+    Ok(compressed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    /// A fresh directory under the system temp dir, removed on drop.
+    struct Scratch(PathBuf);
+
+    impl Scratch {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!(
+                "network-test-rotation-{}-{}",
+                std::process::id(),
+                name
+            ));
+            let _ = fs::remove_dir_all(&path);
+            fs::create_dir_all(&path).unwrap();
+            Scratch(path)
+        }
+
+        fn prefix(&self) -> String {
+            self.0.join("log").display().to_string()
+        }
+
+        /// File names and contents, in sequence order.
+        fn files(&self) -> Vec<(String, String)> {
+            let mut files: Vec<(String, String)> = fs::read_dir(&self.0)
+                .unwrap()
+                .map(|entry| {
+                    let path = entry.unwrap().path();
+                    let mut contents = String::new();
+                    match path.extension().is_some_and(|extension| extension == "gz") {
+                        true => flate2::read::GzDecoder::new(File::open(&path).unwrap())
+                            .read_to_string(&mut contents),
+                        false => File::open(&path).unwrap().read_to_string(&mut contents),
+                    }
+                    .unwrap();
+                    let name = path.file_name().unwrap().to_string_lossy().into_owned();
+                    (name, contents)
+                })
+                .collect();
+            files.sort();
+            files
+        }
+    }
+
+    impl Drop for Scratch {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// `log_<seq>_<YYYYmmddHHMMSS>.txt`
+    fn assert_name(name: &str, sequence: u32, extension: &str) {
+        let rest = name
+            .strip_prefix(&format!("log_{:05}_", sequence))
+            .and_then(|rest| rest.strip_suffix(extension))
+            .unwrap_or_else(|| panic!("{}", name));
+        assert!(
+            rest.len() == 14 && rest.bytes().all(|b| b.is_ascii_digit()),
+            "{}",
+            name
+        );
+    }
+
+    #[test]
+    fn rolls_over_by_size_without_splitting_records() {
+        let scratch = Scratch::new("size");
+        let options = RotationOptions {
+            max_bytes: Some(10),
+            ..RotationOptions::default()
+        };
+        let mut file = RotatingFile::new(scratch.prefix(), options).unwrap();
+        for record in ["abcd", "efgh", "ij", "a record longer than the limit", "k"] {
+            file.write_record(record).unwrap();
+        }
+        file.finish().unwrap();
+
+        let files = scratch.files();
+        let contents: Vec<&str> = files.iter().map(|(_, text)| text.as_str()).collect();
+        assert_eq!(
+            contents,
+            [
+                "abcd\nefgh\n",
+                "ij\n",
+                "a record longer than the limit\n",
+                "k\n"
+            ]
+        );
+        for (sequence, (name, _)) in (1..).zip(&files) {
+            assert_name(name, sequence, ".txt");
+        }
+    }
+
+    #[test]
+    fn rolls_over_by_age() {
+        let scratch = Scratch::new("age");
+        let options = RotationOptions {
+            max_age: Some(Duration::from_millis(20)),
+            ..RotationOptions::default()
+        };
+        let mut file = RotatingFile::new(scratch.prefix(), options).unwrap();
+        file.write_record("first").unwrap();
+        file.write_record("second").unwrap();
+        std::thread::sleep(Duration::from_millis(30));
+        file.write_record("third").unwrap();
+        file.finish().unwrap();
+
+        let files = scratch.files();
+        let contents: Vec<&str> = files.iter().map(|(_, text)| text.as_str()).collect();
+        assert_eq!(contents, ["first\nsecond\n", "third\n"]);
+        assert_name(&files[1].0, 2, ".txt");
+    }
+
+    #[test]
+    fn prunes_beyond_max_files() {
+        let scratch = Scratch::new("prune");
+        let options = RotationOptions {
+            max_bytes: Some(1),
+            max_files: Some(3),
+            compress: true,
+            ..RotationOptions::default()
+        };
+        let mut file = RotatingFile::new(scratch.prefix(), options).unwrap();
+        for record in ["1", "2", "3", "4", "5", "6"] {
+            file.write_record(record).unwrap();
+            // the open file counts, so closed ones never exceed two
+            assert!(scratch.files().len() <= 3);
+        }
+        file.finish().unwrap();
+
+        let files = scratch.files();
+        let contents: Vec<&str> = files.iter().map(|(_, text)| text.as_str()).collect();
+        assert_eq!(contents, ["4\n", "5\n", "6\n"]);
+        for (sequence, (name, _)) in (4..).zip(&files) {
+            assert_name(name, sequence, ".txt.gz");
+        }
+    }
+}
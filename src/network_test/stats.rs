@@ -0,0 +1,387 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::hash::Hash;
+use std::net::IpAddr;
+use std::time::{Duration, SystemTime};
+
+use pnet::util::MacAddr;
+
+use crate::network_test::datalink::EthernetIIFrame;
+use crate::network_test::flow::FlowKey;
+use crate::network_test::network::PacketType;
+
+/// Upper bounds of the packet size histogram, as in Wireshark's packet lengths.
+const SIZE_BUCKETS: [usize; 7] = [63, 127, 255, 511, 1023, 1517, usize::MAX];
+
+/// Application protocols guessed from well-known ports.
+const WELL_KNOWN_PORTS: [(u8, u16, &str); 22] = [
+    (6, 21, "FTP"),
+    (6, 22, "SSH"),
+    (6, 23, "Telnet"),
+    (6, 25, "SMTP"),
+    (6, 53, "DNS"),
+    (17, 53, "DNS"),
+    (17, 67, "DHCP"),
+    (17, 68, "DHCP"),
+    (6, 80, "HTTP"),
+    (17, 123, "NTP"),
+    (17, 161, "SNMP"),
+    (6, 443, "TLS"),
+    (17, 443, "QUIC"),
+    (17, 2055, "NetFlow"),
+    (6, 3306, "MySQL"),
+    (17, 4739, "IPFIX"),
+    (17, 5353, "mDNS"),
+    (6, 5432, "PostgreSQL"),
+    (17, 6343, "sFlow"),
+    (6, 8080, "HTTP"),
+    (17, 9995, "NetFlow"),
+    (17, 9996, "NetFlow"),
+];
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Counter {
+    pub packets: u64,
+    pub bytes: u64,
+}
+
+impl Counter {
+    pub fn add(&mut self, bytes: usize) {
+        self.packets += 1;
+        self.bytes += bytes as u64;
+    }
+}
+
+/// Aggregate view of a capture: protocol hierarchy, top talkers and ports,
+/// packet sizes and a rate time series.
+#[derive(Debug)]
+pub struct TrafficStats {
+    /// width of one time series bucket
+    bucket: Duration,
+    first: Option<SystemTime>,
+    last: Option<SystemTime>,
+    total: Counter,
+    /// every prefix of every layer path, e.g. `Ethernet/IPv4/TCP/TLS`
+    hierarchy: BTreeMap<Vec<String>, Counter>,
+    macs: HashMap<MacAddr, Counter>,
+    ips: HashMap<IpAddr, Counter>,
+    ports: HashMap<(u8, u16), Counter>,
+    sizes: [Counter; SIZE_BUCKETS.len()],
+    series: BTreeMap<u64, Counter>,
+}
+
+impl TrafficStats {
+    pub fn new(bucket: Duration) -> Self {
+        TrafficStats {
+            bucket: bucket.max(Duration::from_millis(1)),
+            first: None,
+            last: None,
+            total: Counter::default(),
+            hierarchy: BTreeMap::new(),
+            macs: HashMap::new(),
+            ips: HashMap::new(),
+            ports: HashMap::new(),
+            sizes: [Counter::default(); SIZE_BUCKETS.len()],
+            series: BTreeMap::new(),
+        }
+    }
+
+    pub fn update(&mut self, frame: &EthernetIIFrame, timestamp: SystemTime) {
+        let length = frame.get_length();
+        let first = *self.first.get_or_insert(timestamp);
+        self.last = Some(self.last.map_or(timestamp, |last| last.max(timestamp)));
+
+        self.total.add(length);
+
+        let layers = layers(frame);
+        for depth in 1..=layers.len() {
+            self.hierarchy
+                .entry(layers[..depth].to_vec())
+                .or_default()
+                .add(length);
+        }
+
+        // loopback traffic has the same address on both ends; count it once
+        let (source, destination) = (frame.get_source(), frame.get_destination());
+        self.macs.entry(source).or_default().add(length);
+        if destination != source {
+            self.macs.entry(destination).or_default().add(length);
+        }
+
+        if let Some(key) = FlowKey::from_frame(frame) {
+            self.ips.entry(key.source).or_default().add(length);
+            if key.destination != key.source {
+                self.ips.entry(key.destination).or_default().add(length);
+            }
+            if matches!(key.protocol, 6 | 17) {
+                let ports = (key.protocol, key.source_port);
+                self.ports.entry(ports).or_default().add(length);
+                if key.destination_port != key.source_port {
+                    let ports = (key.protocol, key.destination_port);
+                    self.ports.entry(ports).or_default().add(length);
+                }
+            }
+        }
+
+        let size = SIZE_BUCKETS.iter().position(|max| length <= *max).unwrap();
+        self.sizes[size].add(length);
+
+        // frames of other interfaces may arrive slightly out of order
+        let offset = timestamp.duration_since(first).unwrap_or_default();
+        let index = (offset.as_nanos() / self.bucket.as_nanos()) as u64;
+        self.series.entry(index).or_default().add(length);
+    }
+
+    /// The report, listing at most `top` talkers and ports.
+    pub fn report(&self, top: usize) -> String {
+        let mut context = String::new();
+        let total = self.total;
+        let elapsed = match (self.first, self.last) {
+            (Some(first), Some(last)) => last.duration_since(first).unwrap_or_default(),
+            _ => Duration::ZERO,
+        };
+
+        context.push_str(&format!(
+            "{} packets, {} bytes in {:.3}s\n",
+            total.packets,
+            total.bytes,
+            elapsed.as_secs_f64()
+        ));
+
+        context.push_str(&format!(
+            "\n{:<32} {:>10} {:>7} {:>12} {:>7}\n",
+            "protocol hierarchy", "packets", "%", "bytes", "%"
+        ));
+        for (path, counter) in &self.hierarchy {
+            let name = format!(
+                "{:indent$}{}",
+                "",
+                path.last().unwrap(),
+                indent = (path.len() - 1) * 2
+            );
+            context.push_str(&format!("{:<32} {}\n", name, Share(*counter, total)));
+        }
+
+        context.push_str(&top_table("MAC address", &self.macs, top, total));
+        context.push_str(&top_table("IP address", &self.ips, top, total));
+        context.push_str(&top_table(
+            "port",
+            &self
+                .ports
+                .iter()
+                .map(|(&(protocol, port), &counter)| {
+                    let name = if protocol == 6 { "tcp" } else { "udp" };
+                    (format!("{}/{}", name, port), counter)
+                })
+                .collect(),
+            top,
+            total,
+        ));
+
+        context.push_str(&format!(
+            "\n{:<32} {:>10} {:>7} {:>12} {:>7}\n",
+            "packet size", "packets", "%", "bytes", "%"
+        ));
+        let mut lower = 0;
+        for (max, counter) in SIZE_BUCKETS.iter().zip(self.sizes) {
+            let range = match *max {
+                usize::MAX => format!("{}+", lower),
+                max => format!("{}-{}", lower, max),
+            };
+            context.push_str(&format!("{:<32} {}\n", range, Share(counter, total)));
+            lower = max.saturating_add(1);
+        }
+
+        context.push_str(&format!(
+            "\n{:<32} {:>10} {:>14}\n",
+            "time", "packets/s", "bits/s"
+        ));
+        let seconds = self.bucket.as_secs_f64();
+        if let (Some(&start), Some(&end)) = (self.series.keys().next(), self.series.keys().last()) {
+            for index in start..=end {
+                let counter = self.series.get(&index).copied().unwrap_or_default();
+                context.push_str(&format!(
+                    "{:<32} {:>10.1} {:>14.0}\n",
+                    format!("{:.3}s", index as f64 * seconds),
+                    counter.packets as f64 / seconds,
+                    counter.bytes as f64 * 8.0 / seconds
+                ));
+            }
+        }
+
+        context
+    }
+}
+
+/// A counter with its share of the total, as `packets % bytes %` columns.
+struct Share(Counter, Counter);
+
+impl fmt::Display for Share {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let percent = |part: u64, whole: u64| {
+            if whole == 0 {
+                0.0
+            } else {
+                part as f64 * 100.0 / whole as f64
+            }
+        };
+        write!(
+            f,
+            "{:>10} {:>6.1}% {:>12} {:>6.1}%",
+            self.0.packets,
+            percent(self.0.packets, self.1.packets),
+            self.0.bytes,
+            percent(self.0.bytes, self.1.bytes)
+        )
+    }
+}
+
+/// The `top` entries with the most bytes; an address counts every packet it sent or received.
+fn top_table<K: fmt::Display + Eq + Hash>(
+    title: &str,
+    counters: &HashMap<K, Counter>,
+    top: usize,
+    total: Counter,
+) -> String {
+    let mut entries: Vec<(&K, &Counter)> = counters.iter().collect();
+    entries.sort_by_key(|(_, counter)| std::cmp::Reverse(counter.bytes));
+
+    let mut context = format!(
+        "\ntop {:<28} {:>10} {:>7} {:>12} {:>7}\n",
+        title, "packets", "%", "bytes", "%"
+    );
+    for (key, counter) in entries.into_iter().take(top) {
+        context.push_str(&format!(
+            "{:<32} {}\n",
+            key.to_string(),
+            Share(*counter, total)
+        ));
+    }
+    context
+}
+
+/// Names of the layers of a frame, outermost first.
+fn layers(frame: &EthernetIIFrame) -> Vec<String> {
+    let packet = frame.get_network_packet();
+    let mut layers = vec![String::from("Ethernet")];
+
+    layers.push(String::from(match packet {
+        PacketType::IPv4(_) => "IPv4",
+        PacketType::IPv6(_) => "IPv6",
+        other => other.protocol_name(),
+    }));
+
+    if let Some(key) = FlowKey::from_frame(frame) {
+        layers.push(match key.protocol {
+            1 | 6 | 17 | 58 => key.protocol_name(),
+            other => format!("IP proto {}", other),
+        });
+
+        let application = WELL_KNOWN_PORTS
+            .iter()
+            .filter(|(protocol, port, _)| {
+                *protocol == key.protocol
+                    && (*port == key.source_port || *port == key.destination_port)
+            })
+            .min_by_key(|(_, port, _)| *port);
+        if let Some((_, _, name)) = application {
+            layers.push(String::from(*name));
+        }
+    }
+
+    layers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network_test::util::frames::{arp, ethernet, mac, udp, Ipv4, Tcp, CLIENT, SERVER};
+    use std::time::UNIX_EPOCH;
+
+    fn datagram(port: u16, length: usize) -> EthernetIIFrame {
+        let data = Ipv4::between(true, 17).frame(&udp(40000, port, &vec![0; length]));
+        EthernetIIFrame::new(&data).unwrap()
+    }
+
+    fn capture() -> TrafficStats {
+        let request = arp(1, (mac(1), CLIENT), ([0; 6], SERVER));
+        let frames = [
+            Tcp::new(true, 0x02).frame(),
+            Tcp::new(false, 0x12).frame(),
+            datagram(53, 100),
+            datagram(5000, 1500),
+            EthernetIIFrame::new(&ethernet(mac(1), [0xff; 6], 0x0806, &request)).unwrap(),
+        ];
+
+        let mut stats = TrafficStats::new(Duration::from_secs(1));
+        for (millis, frame) in (0..).step_by(400).zip(&frames) {
+            stats.update(frame, UNIX_EPOCH + Duration::from_millis(millis));
+        }
+        stats
+    }
+
+    #[test]
+    fn protocol_hierarchy_counts_every_prefix() {
+        let stats = capture();
+        let hierarchy: Vec<(String, u64)> = stats
+            .hierarchy
+            .iter()
+            .map(|(path, counter)| (path.join("/"), counter.packets))
+            .collect();
+
+        assert_eq!(
+            hierarchy,
+            [
+                ("Ethernet".to_string(), 5),
+                ("Ethernet/ARP".to_string(), 1),
+                ("Ethernet/IPv4".to_string(), 4),
+                ("Ethernet/IPv4/TCP".to_string(), 2),
+                ("Ethernet/IPv4/TCP/HTTP".to_string(), 2),
+                ("Ethernet/IPv4/UDP".to_string(), 2),
+                ("Ethernet/IPv4/UDP/DNS".to_string(), 1),
+            ]
+        );
+
+        let report = stats.report(10);
+        assert!(report.starts_with("5 packets, 1834 bytes in 1.600s\n"));
+        assert!(report.contains(
+            "\n      HTTP                                2   40.0%          108    5.9%\n"
+        ));
+    }
+
+    #[test]
+    fn size_histogram_buckets() {
+        let stats = capture();
+        let packets: Vec<u64> = stats.sizes.iter().map(|counter| counter.packets).collect();
+        // 54 + 54 + 42, 142 and 1542 bytes
+        assert_eq!(packets, [3, 0, 1, 0, 0, 0, 1]);
+
+        let report = stats.report(10);
+        assert!(
+            report.contains("\n0-63                                      3   60.0%          150")
+        );
+        assert!(
+            report.contains("\n128-255                                   1   20.0%          142")
+        );
+        assert!(
+            report.contains("\n1518+                                     1   20.0%         1542")
+        );
+    }
+
+    #[test]
+    fn time_series_fills_empty_buckets() {
+        let stats = capture();
+        let series: Vec<(u64, u64)> = stats
+            .series
+            .iter()
+            .map(|(&index, counter)| (index, counter.packets))
+            .collect();
+        assert_eq!(series, [(0, 3), (1, 2)]);
+
+        let mut late = capture();
+        late.update(&datagram(53, 0), UNIX_EPOCH + Duration::from_secs(3));
+        assert!(late
+            .report(10)
+            .contains(&format!("{:<32} {:>10.1}", "2.000s", 0.0)));
+    }
+}
@@ -0,0 +1,377 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime};
+
+use crate::network_test::datalink::EthernetIIFrame;
+use crate::network_test::flow::FlowKey;
+
+/// One captured frame as kept by the `PacketStore`.
+#[derive(Debug)]
+pub struct StoredPacket {
+    /// per-interface sequence number, starting at 1
+    pub number: u64,
+    pub timestamp: SystemTime,
+    pub data: Vec<u8>,
+    pub frame: EthernetIIFrame,
+    /// direction-independent flow of the frame, if it is TCP/UDP/IP
+    pub flow: Option<FlowKey>,
+}
+
+/// How much traffic each interface keeps before the oldest packets go.
+#[derive(Debug, Clone, Copy)]
+pub struct StoreLimits {
+    pub max_packets: usize,
+    pub max_bytes: usize,
+    pub max_age: Option<Duration>,
+}
+
+impl Default for StoreLimits {
+    fn default() -> Self {
+        StoreLimits {
+            max_packets: 10_000,
+            max_bytes: 64 * 1024 * 1024,
+            max_age: None,
+        }
+    }
+}
+
+/// Filters for `PacketStore::query`; unset fields match everything.
+#[derive(Debug, Clone, Default)]
+pub struct Query {
+    pub interface: Option<String>,
+    pub since: Option<SystemTime>,
+    pub until: Option<SystemTime>,
+    /// matched in both directions
+    pub flow: Option<FlowKey>,
+    /// keep only the newest `limit` matches
+    pub limit: Option<usize>,
+}
+
+#[derive(Default)]
+struct Ring {
+    packets: VecDeque<Arc<StoredPacket>>,
+    by_flow: HashMap<FlowKey, VecDeque<u64>>,
+    bytes: usize,
+    next_number: u64,
+    /// number of the newest packet that arrived with an earlier timestamp
+    /// than the one before it, as happens with several workers on one
+    /// interface; the ring is in order again once its predecessor is gone
+    last_inversion: u64,
+}
+
+/// Ring buffers of recent packets, one per interface, that capture threads
+/// append to while other components query them.
+pub struct PacketStore {
+    limits: StoreLimits,
+    rings: RwLock<HashMap<String, Arc<Mutex<Ring>>>>,
+}
+
+impl PacketStore {
+    pub fn new(limits: StoreLimits) -> Self {
+        PacketStore {
+            limits,
+            rings: RwLock::new(HashMap::new()),
+        }
+    }
+
+    pub fn insert(
+        &self,
+        interface: &str,
+        timestamp: SystemTime,
+        data: &[u8],
+        frame: EthernetIIFrame,
+    ) {
+        let ring = self.ring(interface);
+        let mut ring = ring.lock().unwrap();
+
+        ring.next_number += 1;
+        let flow = FlowKey::from_frame(&frame).map(|key| key.canonical());
+        let packet = StoredPacket {
+            number: ring.next_number,
+            timestamp,
+            data: data.to_vec(),
+            frame,
+            flow,
+        };
+
+        if let Some(key) = flow {
+            ring.by_flow
+                .entry(key)
+                .or_default()
+                .push_back(packet.number);
+        }
+        if ring
+            .packets
+            .back()
+            .is_some_and(|last| last.timestamp > timestamp)
+        {
+            ring.last_inversion = packet.number;
+        }
+        ring.bytes += packet.data.len();
+        ring.packets.push_back(Arc::new(packet));
+
+        self.evict(&mut ring, timestamp);
+    }
+
+    /// Returns a snapshot of the matching packets, oldest first by
+    /// timestamp. The packets are shared, so iterating them never blocks
+    /// capture.
+    pub fn query(&self, query: &Query) -> Vec<Arc<StoredPacket>> {
+        let rings: Vec<Arc<Mutex<Ring>>> = {
+            let rings = self.rings.read().unwrap();
+            match &query.interface {
+                Some(name) => rings.get(name).cloned().into_iter().collect(),
+                None => rings.values().cloned().collect(),
+            }
+        };
+
+        let mut found = vec![];
+        for ring in rings {
+            found.extend(Self::query_ring(&ring.lock().unwrap(), query));
+        }
+
+        found.sort_by_key(|packet| packet.timestamp);
+        if let Some(limit) = query.limit {
+            let skip = found.len().saturating_sub(limit);
+            found.drain(..skip);
+        }
+
+        found
+    }
+
+    fn query_ring(ring: &Ring, query: &Query) -> Vec<Arc<StoredPacket>> {
+        let Some(first) = ring.packets.front().map(|packet| packet.number) else {
+            return vec![];
+        };
+        let in_time = |packet: &StoredPacket| {
+            query.since.is_none_or(|since| packet.timestamp >= since)
+                && query.until.is_none_or(|until| packet.timestamp <= until)
+        };
+
+        match query.flow {
+            Some(key) => ring
+                .by_flow
+                .get(&key.canonical())
+                .into_iter()
+                .flatten()
+                .map(|number| Arc::clone(&ring.packets[(number - first) as usize]))
+                .filter(|packet| in_time(packet))
+                .collect(),
+            None if ring.last_inversion > first => ring
+                .packets
+                .iter()
+                .filter(|packet| in_time(packet))
+                .cloned()
+                .collect(),
+            None => {
+                // timestamps only grew so far, so the time range is a slice
+                let start = match query.since {
+                    Some(since) => ring.packets.partition_point(|p| p.timestamp < since),
+                    None => 0,
+                };
+                let end = match query.until {
+                    Some(until) => ring.packets.partition_point(|p| p.timestamp <= until),
+                    None => ring.packets.len(),
+                };
+
+                ring.packets.range(start..end.max(start)).cloned().collect()
+            }
+        }
+    }
+
+    fn ring(&self, interface: &str) -> Arc<Mutex<Ring>> {
+        if let Some(ring) = self.rings.read().unwrap().get(interface) {
+            return Arc::clone(ring);
+        }
+
+        Arc::clone(
+            self.rings
+                .write()
+                .unwrap()
+                .entry(interface.to_string())
+                .or_default(),
+        )
+    }
+
+    fn evict(&self, ring: &mut Ring, now: SystemTime) {
+        while let Some(oldest) = ring.packets.front() {
+            let too_old = self
+                .limits
+                .max_age
+                .is_some_and(|age| now.duration_since(oldest.timestamp).unwrap_or_default() > age);
+            let too_many =
+                ring.packets.len() > self.limits.max_packets || ring.bytes > self.limits.max_bytes;
+
+            if !(too_old || too_many) {
+                break;
+            }
+
+            let oldest = ring.packets.pop_front().unwrap();
+            ring.bytes -= oldest.data.len();
+
+            if let Some(key) = oldest.flow {
+                if let Some(numbers) = ring.by_flow.get_mut(&key) {
+                    numbers.pop_front();
+                    if numbers.is_empty() {
+                        ring.by_flow.remove(&key);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network_test::util::frames::{udp, Ipv4};
+
+    /// A client UDP datagram from `port` with `length` bytes of payload.
+    fn datagram(port: u16, length: usize) -> (Vec<u8>, EthernetIIFrame) {
+        let data = Ipv4::between(true, 17).frame(&udp(port, 53, &vec![0; length]));
+        let frame = EthernetIIFrame::new(&data).unwrap();
+        (data, frame)
+    }
+
+    fn at(seconds: u64) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(seconds)
+    }
+
+    fn insert(store: &PacketStore, port: u16, length: usize, seconds: u64) {
+        let (data, frame) = datagram(port, length);
+        store.insert("eth0", at(seconds), &data, frame);
+    }
+
+    fn numbers(packets: &[Arc<StoredPacket>]) -> Vec<u64> {
+        packets.iter().map(|packet| packet.number).collect()
+    }
+
+    fn flow(port: u16) -> Query {
+        Query {
+            flow: FlowKey::from_frame(&datagram(port, 0).1),
+            ..Query::default()
+        }
+    }
+
+    fn limits(max_packets: usize, max_bytes: usize, max_age: Option<u64>) -> StoreLimits {
+        StoreLimits {
+            max_packets,
+            max_bytes,
+            max_age: max_age.map(Duration::from_secs),
+        }
+    }
+
+    #[test]
+    fn evicts_by_count_and_keeps_flow_queries_right() {
+        let store = PacketStore::new(limits(3, usize::MAX, None));
+        for (second, port) in [1000, 2000, 1000, 2000, 1000].into_iter().enumerate() {
+            insert(&store, port, 0, second as u64);
+        }
+
+        assert_eq!(numbers(&store.query(&Query::default())), [3, 4, 5]);
+        assert_eq!(numbers(&store.query(&flow(1000))), [3, 5]);
+        assert_eq!(numbers(&store.query(&flow(2000))), [4]);
+
+        for second in 5..8 {
+            insert(&store, 3000, 0, second);
+        }
+        assert_eq!(numbers(&store.query(&flow(1000))), []);
+        assert_eq!(numbers(&store.query(&flow(2000))), []);
+        assert_eq!(numbers(&store.query(&flow(3000))), [6, 7, 8]);
+    }
+
+    #[test]
+    fn evicts_by_bytes() {
+        // 42 bytes of headers and 58 of payload
+        let store = PacketStore::new(limits(usize::MAX, 250, None));
+        for second in 0..3 {
+            insert(&store, 1000, 58, second);
+        }
+        assert_eq!(numbers(&store.query(&Query::default())), [2, 3]);
+
+        insert(&store, 1000, 208, 3);
+        assert_eq!(numbers(&store.query(&Query::default())), [4]);
+        assert_eq!(numbers(&store.query(&flow(1000))), [4]);
+    }
+
+    #[test]
+    fn evicts_by_age() {
+        let store = PacketStore::new(limits(usize::MAX, usize::MAX, Some(10)));
+        for second in [0, 5, 10, 12] {
+            insert(&store, 1000, 0, second);
+        }
+        assert_eq!(numbers(&store.query(&Query::default())), [2, 3, 4]);
+
+        insert(&store, 2000, 0, 30);
+        assert_eq!(numbers(&store.query(&Query::default())), [5]);
+        assert_eq!(numbers(&store.query(&flow(1000))), []);
+    }
+
+    #[test]
+    fn time_range_and_limit() {
+        let store = PacketStore::new(StoreLimits::default());
+        for second in 0..6 {
+            insert(&store, 1000, 0, second * 10);
+        }
+        let range = |since: Option<u64>, until: Option<u64>| Query {
+            since: since.map(at),
+            until: until.map(at),
+            ..Query::default()
+        };
+
+        assert_eq!(numbers(&store.query(&range(Some(10), Some(30)))), [2, 3, 4]);
+        assert_eq!(numbers(&store.query(&range(Some(15), None))), [3, 4, 5, 6]);
+        assert_eq!(numbers(&store.query(&range(None, Some(5)))), [1]);
+        assert_eq!(numbers(&store.query(&range(Some(35), Some(25)))), []);
+        assert_eq!(numbers(&store.query(&range(Some(60), None))), []);
+
+        let newest = Query {
+            limit: Some(2),
+            ..range(None, Some(30))
+        };
+        assert_eq!(numbers(&store.query(&newest)), [3, 4]);
+    }
+
+    #[test]
+    fn out_of_order_timestamps_until_evicted() {
+        let store = PacketStore::new(limits(3, usize::MAX, None));
+        for second in [10, 20, 15] {
+            insert(&store, 1000, 0, second);
+        }
+        let since = Query {
+            since: Some(at(12)),
+            ..Query::default()
+        };
+        assert_eq!(numbers(&store.query(&since)), [3, 2]);
+
+        // packet 2 was the later neighbour, so the ring is sorted again
+        for second in [25, 30] {
+            insert(&store, 1000, 0, second);
+        }
+        assert_eq!(numbers(&store.query(&since)), [3, 4, 5]);
+        let ring = store.ring("eth0");
+        let ring = ring.lock().unwrap();
+        assert!(ring.last_inversion <= ring.packets.front().unwrap().number);
+    }
+
+    #[test]
+    fn interfaces_are_kept_apart() {
+        let store = PacketStore::new(limits(1, usize::MAX, None));
+        insert(&store, 1000, 0, 0);
+        let (data, frame) = datagram(1000, 0);
+        store.insert("eth1", at(1), &data, frame);
+
+        let eth1 = Query {
+            interface: Some("eth1".to_string()),
+            ..flow(1000)
+        };
+        assert_eq!(numbers(&store.query(&eth1)), [1]);
+        assert_eq!(store.query(&Query::default()).len(), 2);
+        assert!(store
+            .query(&Query {
+                interface: Some("wlan0".to_string()),
+                ..Query::default()
+            })
+            .is_empty());
+    }
+}
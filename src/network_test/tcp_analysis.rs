@@ -0,0 +1,534 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::time::{Duration, SystemTime};
+
+use crate::network_test::datalink::EthernetIIFrame;
+use crate::network_test::flow::{FlowKey, TCP_ACK, TCP_FIN, TCP_RST, TCP_SYN};
+use crate::network_test::network::PacketType;
+use crate::network_test::transport::{TCPSegment, TransportSegment};
+
+/// Holes remembered per direction; older ones are forgotten.
+const MAX_GAPS: usize = 16;
+/// Segments per direction waiting for the ACK that gives an RTT sample.
+const MAX_UNACKED: usize = 1024;
+/// Directions tracked before idle ones are dropped.
+const MAX_DIRECTIONS: usize = 65_536;
+const IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Something noteworthy about one segment, named as Wireshark names it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TcpEvent {
+    /// data that was already sent once
+    Retransmission,
+    /// data that fills a hole left by an earlier lost segment
+    OutOfOrder,
+    /// the sequence number jumped past data never captured
+    PreviousSegmentLost,
+    /// the n-th repeat of the previous ACK
+    DuplicateAck(u32),
+    ZeroWindow,
+    /// the data in flight now fills the receiver's advertised window
+    WindowFull,
+    KeepAlive,
+}
+
+impl fmt::Display for TcpEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TcpEvent::Retransmission => write!(f, "TCP Retransmission"),
+            TcpEvent::OutOfOrder => write!(f, "TCP Out-Of-Order"),
+            TcpEvent::PreviousSegmentLost => write!(f, "TCP Previous segment not captured"),
+            TcpEvent::DuplicateAck(count) => write!(f, "TCP Dup ACK #{}", count),
+            TcpEvent::ZeroWindow => write!(f, "TCP ZeroWindow"),
+            TcpEvent::WindowFull => write!(f, "TCP Window Full"),
+            TcpEvent::KeepAlive => write!(f, "TCP Keep-Alive"),
+        }
+    }
+}
+
+/// What `TcpAnalyzer` found out about one segment.
+#[derive(Debug, Clone, Default)]
+pub struct TcpAnalysis {
+    pub events: Vec<TcpEvent>,
+    /// time since the segment this packet acknowledges was sent
+    pub rtt: Option<Duration>,
+    /// the advertised window after window scaling, when scaling is in effect
+    pub window: Option<u32>,
+    /// the window field as the segment carries it
+    pub raw_window: u16,
+}
+
+impl TcpAnalysis {
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty() && self.rtt.is_none() && self.window.is_none()
+    }
+
+    /// Puts the scaled window in place of the raw `win=` in the one-line
+    /// summary of the segment, once scaling is in effect, as Wireshark does.
+    pub fn scale_summary(&self, summary: &str) -> String {
+        match self.window {
+            Some(window) => summary.replacen(
+                &format!(" win={} ", self.raw_window),
+                &format!(" win={} ", window),
+                1,
+            ),
+            None => summary.to_string(),
+        }
+    }
+}
+
+/// `{}` prints `[event] [RTT x ms]`, `{:#}` one line per finding and
+/// the scaled window.
+impl fmt::Display for TcpAnalysis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let rtt = self
+            .rtt
+            .map(|rtt| format!("RTT {:.3}ms", rtt.as_secs_f64() * 1000.0));
+        let findings = self.events.iter().map(|event| event.to_string()).chain(rtt);
+
+        if f.alternate() {
+            writeln!(f, "TCP analysis")?;
+            for finding in findings {
+                writeln!(f, "    {}", finding)?;
+            }
+            if let Some(window) = self.window {
+                writeln!(f, "    calculated window {}", window)?;
+            }
+            return Ok(());
+        }
+
+        let findings: Vec<String> = findings.map(|finding| format!("[{}]", finding)).collect();
+        write!(f, "{}", findings.join(" "))
+    }
+}
+
+/// State of one direction of a connection.
+#[derive(Debug)]
+struct Direction {
+    /// sequence number following the highest byte sent so far
+    next_seq: Option<u32>,
+    /// sequence ranges skipped by `PreviousSegmentLost`, oldest first
+    gaps: VecDeque<(u32, u32)>,
+    last_ack: Option<u32>,
+    /// scaled when the shift is known
+    last_window: u32,
+    /// whether this direction's SYN was seen, and the shift it announced
+    syn_seen: bool,
+    window_scale: Option<u8>,
+    duplicate_acks: u32,
+    /// ACK number that will cover each segment, and when it was sent
+    unacked: VecDeque<(u32, SystemTime)>,
+    last_seen: SystemTime,
+}
+
+impl Direction {
+    fn new(timestamp: SystemTime) -> Self {
+        Direction {
+            next_seq: None,
+            gaps: VecDeque::new(),
+            last_ack: None,
+            last_window: 0,
+            syn_seen: false,
+            window_scale: None,
+            duplicate_acks: 0,
+            unacked: VecDeque::new(),
+            last_seen: timestamp,
+        }
+    }
+}
+
+/// `a` comes before `b` in sequence space, which wraps around.
+fn before(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) < 0
+}
+
+/// Follows TCP connections segment by segment and annotates them.
+#[derive(Debug, Default)]
+pub struct TcpAnalyzer {
+    directions: HashMap<FlowKey, Direction>,
+}
+
+impl TcpAnalyzer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Analyses `frame` if it carries TCP; `None` otherwise.
+    pub fn analyze(
+        &mut self,
+        frame: &EthernetIIFrame,
+        timestamp: SystemTime,
+    ) -> Option<TcpAnalysis> {
+        let key = FlowKey::from_frame(frame)?;
+        if key.protocol != 6 {
+            return None;
+        }
+
+        match frame.get_network_packet() {
+            PacketType::IPv4(packet) => match packet.get_transport_segment() {
+                TransportSegment::TCP(segment) => Some(self.segment(key, segment, timestamp)),
+                _ => None,
+            },
+            PacketType::IPv6(packet) => TCPSegment::new(packet.get_payload())
+                .map(|segment| self.segment(key, &segment, timestamp)),
+            _ => None,
+        }
+    }
+
+    fn segment(
+        &mut self,
+        key: FlowKey,
+        segment: &TCPSegment,
+        timestamp: SystemTime,
+    ) -> TcpAnalysis {
+        if self.directions.len() > MAX_DIRECTIONS {
+            self.directions.retain(|_, direction| {
+                timestamp
+                    .duration_since(direction.last_seen)
+                    .unwrap_or_default()
+                    < IDLE_TIMEOUT
+            });
+        }
+
+        let flags = segment.get_flags();
+        let (syn, fin, rst, ack) = (
+            flags & TCP_SYN != 0,
+            flags & TCP_FIN != 0,
+            flags & TCP_RST != 0,
+            flags & TCP_ACK != 0,
+        );
+        let seq = segment.get_sequence_number();
+        let payload = segment.get_data().len() as u32;
+        // SYN and FIN take one sequence number each
+        let length = payload + syn as u32 + fin as u32;
+        let end = seq.wrapping_add(length);
+        let raw_window = segment.get_window_size();
+
+        let peer = self.directions.get(&key.reversed());
+        let (peer_ack, peer_window) =
+            peer.map_or((None, 0), |peer| (peer.last_ack, peer.last_window));
+        let peer_scale = peer
+            .filter(|peer| peer.syn_seen)
+            .map(|peer| peer.window_scale);
+
+        let mut analysis = TcpAnalysis {
+            raw_window,
+            ..TcpAnalysis::default()
+        };
+        let forward = self
+            .directions
+            .entry(key)
+            .or_insert_with(|| Direction::new(timestamp));
+        forward.last_seen = timestamp;
+
+        // a SYN with a new sequence number starts the connection over
+        if syn && forward.next_seq.is_some_and(|next| next != end) {
+            *forward = Direction::new(timestamp);
+        }
+        if syn {
+            forward.syn_seen = true;
+            forward.window_scale = segment.get_window_scale();
+        }
+
+        // scaling applies only once both SYNs carried the option, and never to SYNs
+        let shift = match (forward.syn_seen, forward.window_scale, peer_scale) {
+            _ if syn => Some(0),
+            (true, Some(shift), Some(Some(_))) => Some(shift),
+            (true, _, Some(_)) => Some(0),
+            _ => None,
+        };
+        let window = (raw_window as u32) << shift.unwrap_or(0);
+        if shift.is_some_and(|shift| shift > 0) {
+            analysis.window = Some(window);
+        }
+
+        let mut fresh = length > 0;
+        if let Some(next) = forward.next_seq {
+            if payload <= 1 && !syn && !fin && !rst && seq == next.wrapping_sub(1) {
+                analysis.events.push(TcpEvent::KeepAlive);
+                fresh = false;
+            } else if length > 0 && before(seq, next) {
+                let gap = forward
+                    .gaps
+                    .iter()
+                    .position(|&(start, stop)| !before(seq, start) && before(seq, stop));
+                match gap {
+                    Some(index) => {
+                        analysis.events.push(TcpEvent::OutOfOrder);
+                        let (start, stop) = forward.gaps.remove(index).unwrap();
+                        // keep whatever part of the hole is still missing
+                        if before(start, seq) {
+                            forward.gaps.push_back((start, seq));
+                        }
+                        if before(end, stop) {
+                            forward.gaps.push_back((end, stop));
+                        }
+                    }
+                    None => {
+                        analysis.events.push(TcpEvent::Retransmission);
+                        // Karn's rule: an ACK after a retransmission says nothing about RTT
+                        forward
+                            .unacked
+                            .retain(|&(covering, _)| !before(seq, covering));
+                        fresh = false;
+                    }
+                }
+            } else if length > 0 && before(next, seq) {
+                analysis.events.push(TcpEvent::PreviousSegmentLost);
+                forward.gaps.push_back((next, seq));
+                if forward.gaps.len() > MAX_GAPS {
+                    forward.gaps.pop_front();
+                }
+            }
+        }
+        if forward.next_seq.is_none_or(|next| before(next, end)) {
+            forward.next_seq = Some(end);
+        }
+
+        if fresh {
+            forward.unacked.push_back((end, timestamp));
+            if forward.unacked.len() > MAX_UNACKED {
+                forward.unacked.pop_front();
+            }
+        }
+
+        if payload > 0
+            && peer_window > 0
+            && peer_ack.is_some_and(|peer_ack| end == peer_ack.wrapping_add(peer_window))
+        {
+            analysis.events.push(TcpEvent::WindowFull);
+        }
+
+        if window == 0 && !syn && !fin && !rst {
+            analysis.events.push(TcpEvent::ZeroWindow);
+        }
+
+        let ack_number = segment.get_acknowledgement_number();
+        if ack {
+            let keep_alive = analysis.events.contains(&TcpEvent::KeepAlive);
+            // a keep-alive repeats the ACK on purpose
+            let bare = payload == 0 && !syn && !fin && !rst && !keep_alive;
+            if bare && forward.last_ack == Some(ack_number) && forward.last_window == window {
+                forward.duplicate_acks += 1;
+                analysis
+                    .events
+                    .push(TcpEvent::DuplicateAck(forward.duplicate_acks));
+            } else if forward.last_ack != Some(ack_number) {
+                forward.duplicate_acks = 0;
+            }
+            forward.last_ack = Some(ack_number);
+        }
+        forward.last_window = window;
+
+        if ack {
+            if let Some(peer) = self.directions.get_mut(&key.reversed()) {
+                // the segment ending right at the ACK gives the sample, or
+                // else the last one sent that it covers
+                let mut exact = false;
+                while let Some(&(covering, sent)) = peer.unacked.front() {
+                    if before(ack_number, covering) {
+                        break;
+                    }
+                    peer.unacked.pop_front();
+                    if !exact {
+                        analysis.rtt = timestamp.duration_since(sent).ok();
+                        exact = covering == ack_number;
+                    }
+                }
+            }
+        }
+
+        if rst {
+            self.directions.remove(&key);
+            self.directions.remove(&key.reversed());
+        }
+
+        analysis
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network_test::util::frames::Tcp;
+
+    /// MSS 1460, NOP and a window scale of `shift`.
+    fn scale(shift: u8) -> Vec<u8> {
+        vec![2, 4, 0x05, 0xb4, 1, 3, 3, shift]
+    }
+
+    fn tcp(
+        from_client: bool,
+        flags: u8,
+        sequence: u32,
+        acknowledgement: u32,
+        length: usize,
+    ) -> Tcp {
+        Tcp {
+            sequence,
+            acknowledgement,
+            data: vec![0; length],
+            ..Tcp::new(from_client, flags)
+        }
+    }
+
+    fn at(millis: u64) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_millis(millis)
+    }
+
+    fn events(analyzer: &mut TcpAnalyzer, tcp: Tcp, millis: u64) -> Vec<TcpEvent> {
+        analyzer.analyze(&tcp.frame(), at(millis)).unwrap().events
+    }
+
+    /// Client sequence numbers start at 101, the server's at 501.
+    fn handshake(analyzer: &mut TcpAnalyzer) {
+        for (segment, millis) in [
+            (tcp(true, TCP_SYN, 100, 0, 0), 0),
+            (tcp(false, TCP_SYN | TCP_ACK, 500, 101, 0), 10),
+            (tcp(true, TCP_ACK, 101, 501, 0), 12),
+        ] {
+            assert_eq!(events(analyzer, segment, millis), []);
+        }
+    }
+
+    #[test]
+    fn handshake_rtt_and_window_scale() {
+        let mut analyzer = TcpAnalyzer::new();
+        let mut send = |tcp: Tcp, millis| analyzer.analyze(&tcp.frame(), at(millis)).unwrap();
+
+        let syn = Tcp {
+            options: scale(7),
+            ..tcp(true, TCP_SYN, 100, 0, 0)
+        };
+        let analysis = send(syn, 0);
+        assert!(analysis.is_empty());
+
+        let syn_ack = Tcp {
+            options: scale(8),
+            window: 65160,
+            ..tcp(false, TCP_SYN | TCP_ACK, 500, 101, 0)
+        };
+        let analysis = send(syn_ack, 10);
+        assert_eq!(analysis.rtt, Some(Duration::from_millis(10)));
+        // SYNs are never scaled
+        assert_eq!(analysis.window, None);
+
+        let ack = Tcp {
+            window: 502,
+            ..tcp(true, TCP_ACK, 101, 501, 0)
+        };
+        let analysis = send(ack, 12);
+        assert_eq!(analysis.rtt, Some(Duration::from_millis(2)));
+        assert_eq!(analysis.window, Some(502 << 7));
+        assert_eq!(analysis.raw_window, 502);
+        assert_eq!(
+            analysis.scale_summary("TCP 1234 → 80 [ACK] win=502 len=0"),
+            "TCP 1234 → 80 [ACK] win=64256 len=0"
+        );
+        assert_eq!(analysis.to_string(), "[RTT 2.000ms]");
+
+        send(tcp(true, TCP_ACK, 101, 501, 100), 20);
+        let reply = Tcp {
+            window: 100,
+            ..tcp(false, TCP_ACK, 501, 201, 0)
+        };
+        let analysis = send(reply, 35);
+        assert_eq!(analysis.rtt, Some(Duration::from_millis(15)));
+        assert_eq!(analysis.window, Some(100 << 8));
+    }
+
+    #[test]
+    fn unscaled_window_leaves_the_summary_alone() {
+        let mut analyzer = TcpAnalyzer::new();
+        handshake(&mut analyzer);
+        let analysis = analyzer
+            .analyze(&tcp(true, TCP_ACK, 101, 501, 10).frame(), at(20))
+            .unwrap();
+        assert_eq!(analysis.window, None);
+        assert_eq!(analysis.scale_summary(" win=29200 "), " win=29200 ");
+    }
+
+    #[test]
+    fn loss_out_of_order_retransmission_and_keep_alive() {
+        let mut analyzer = TcpAnalyzer::new();
+        handshake(&mut analyzer);
+        let mut send = |tcp: Tcp| events(&mut analyzer, tcp, 20);
+
+        assert_eq!(send(tcp(true, TCP_ACK, 101, 501, 100)), []);
+        assert_eq!(
+            send(tcp(true, TCP_ACK, 301, 501, 100)),
+            [TcpEvent::PreviousSegmentLost]
+        );
+        assert_eq!(
+            send(tcp(true, TCP_ACK, 201, 501, 100)),
+            [TcpEvent::OutOfOrder]
+        );
+        // the hole is filled, so the same data again is a retransmission
+        assert_eq!(
+            send(tcp(true, TCP_ACK, 201, 501, 100)),
+            [TcpEvent::Retransmission]
+        );
+        assert_eq!(send(tcp(true, TCP_ACK, 400, 501, 0)), [TcpEvent::KeepAlive]);
+        // a keep-alive is no duplicate ACK, the same bare ACK after it is
+        assert_eq!(
+            send(tcp(true, TCP_ACK, 401, 501, 0)),
+            [TcpEvent::DuplicateAck(1)]
+        );
+    }
+
+    #[test]
+    fn no_rtt_from_a_retransmitted_segment() {
+        let mut analyzer = TcpAnalyzer::new();
+        handshake(&mut analyzer);
+        let mut send = |tcp: Tcp, millis| analyzer.analyze(&tcp.frame(), at(millis)).unwrap();
+
+        send(tcp(true, TCP_ACK, 101, 501, 100), 20);
+        let analysis = send(tcp(true, TCP_ACK, 101, 501, 100), 220);
+        assert_eq!(analysis.events, [TcpEvent::Retransmission]);
+
+        // Karn's rule: the ACK could answer either copy
+        let analysis = send(tcp(false, TCP_ACK, 501, 201, 0), 230);
+        assert_eq!(analysis.rtt, None);
+        assert!(analysis.is_empty());
+    }
+
+    #[test]
+    fn duplicate_acks_zero_window_and_window_full() {
+        let mut analyzer = TcpAnalyzer::new();
+        handshake(&mut analyzer);
+        let mut send = |tcp: Tcp| events(&mut analyzer, tcp, 20);
+        let ack = |window| Tcp {
+            window,
+            ..tcp(false, TCP_ACK, 501, 101, 0)
+        };
+
+        assert_eq!(send(ack(200)), []);
+        assert_eq!(send(ack(200)), [TcpEvent::DuplicateAck(1)]);
+        assert_eq!(send(ack(200)), [TcpEvent::DuplicateAck(2)]);
+        // a window update is no duplicate
+        assert_eq!(send(ack(0)), [TcpEvent::ZeroWindow]);
+        assert_eq!(send(ack(200)), []);
+
+        // 200 bytes past the server's ACK fill its window
+        assert_eq!(send(tcp(true, TCP_ACK, 101, 501, 100)), []);
+        assert_eq!(
+            send(tcp(true, TCP_ACK, 201, 501, 100)),
+            [TcpEvent::WindowFull]
+        );
+    }
+
+    #[test]
+    fn reset_forgets_the_connection() {
+        let mut analyzer = TcpAnalyzer::new();
+        handshake(&mut analyzer);
+        assert_eq!(
+            events(&mut analyzer, tcp(false, TCP_RST, 501, 0, 0), 20),
+            []
+        );
+        // a new connection on the same ports starts from scratch
+        assert_eq!(
+            events(&mut analyzer, tcp(true, TCP_ACK, 9000, 1, 10), 30),
+            []
+        );
+    }
+}
@@ -0,0 +1,276 @@
+use std::fmt;
+use std::io;
+use std::mem;
+use std::ptr;
+use std::sync::atomic::{fence, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use libc::c_int;
+use pnet::datalink::NetworkInterface;
+
+use crate::network_test::capture::Fanout;
+use crate::network_test::packet_socket::PacketSocket;
+
+/// The room one frame takes when the ring is sized by a frame count.
+/// Packets themselves may be longer, up to a whole block.
+const FRAME_SIZE: usize = 2048;
+
+/// The shape of a TPACKET_V3 receive ring.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RingOptions {
+    /// bytes per block, a multiple of the page size; no packet is longer
+    pub block_size: usize,
+    /// frames the ring holds, rounded up to whole blocks
+    pub frame_count: usize,
+    /// how long the kernel fills a block before handing it over part full
+    pub retire_timeout: Duration,
+}
+
+impl Default for RingOptions {
+    fn default() -> Self {
+        RingOptions {
+            block_size: 1 << 20,
+            frame_count: 16_384,
+            retire_timeout: Duration::from_millis(64),
+        }
+    }
+}
+
+impl RingOptions {
+    pub fn block_count(&self) -> usize {
+        (self.frame_count * FRAME_SIZE)
+            .div_ceil(self.block_size.max(1))
+            .max(1)
+    }
+
+    fn frames_per_block(&self) -> usize {
+        self.block_size / FRAME_SIZE
+    }
+}
+
+impl fmt::Display for RingOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "mmap ring of {} blocks of {} KiB ({} frames), retire timeout {} ms",
+            self.block_count(),
+            self.block_size / 1024,
+            self.block_count() * self.frames_per_block(),
+            self.retire_timeout.as_millis()
+        )
+    }
+}
+
+/// What the kernel counted for a socket since the previous look.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct KernelStats {
+    /// packets that reached the socket, dropped ones included
+    pub packets: u64,
+    /// packets lost because the ring or the socket buffer was full
+    pub drops: u64,
+    /// times the ring filled up and the kernel stopped queueing; always
+    /// 0 without a ring
+    pub freezes: u64,
+}
+
+impl fmt::Display for KernelStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "kernel: {} packets, {} dropped, {} queue freezes",
+            self.packets, self.drops, self.freezes
+        )
+    }
+}
+
+/// A packet socket whose receive ring is mapped into our memory, so frames
+/// are read where the kernel wrote them, a block of them at a time.
+pub struct Ring {
+    socket: PacketSocket,
+    map: *mut u8,
+    block_size: usize,
+    block_count: usize,
+    /// the block the kernel hands over next
+    next: usize,
+}
+
+impl Ring {
+    /// Sets up a TPACKET_V3 ring on `interface`, optionally promiscuous
+    /// and in a fanout group.
+    pub fn open(
+        interface: &NetworkInterface,
+        options: &RingOptions,
+        promiscuous: bool,
+        fanout: Option<Fanout>,
+    ) -> Result<Self, String> {
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) }.max(1) as usize;
+        if options.block_size == 0 || !options.block_size.is_multiple_of(page_size) {
+            return Err(format!(
+                "the ring block size must be a multiple of the {} B page size",
+                page_size
+            ));
+        }
+        let error = |what: &str, e: io::Error| format!("cannot {}: {}", what, e);
+
+        // the ring is set up before binding, so only frames of `interface` fill it
+        let mut ring = Ring {
+            socket: PacketSocket::new()?,
+            map: ptr::null_mut(),
+            block_size: options.block_size,
+            block_count: options.block_count(),
+            next: 0,
+        };
+
+        let version = libc::tpacket_versions::TPACKET_V3 as c_int;
+        ring.set_option(libc::PACKET_VERSION, &version)
+            .map_err(|e| error("switch to TPACKET_V3", e))?;
+
+        let request = libc::tpacket_req3 {
+            tp_block_size: ring.block_size as u32,
+            tp_block_nr: ring.block_count as u32,
+            tp_frame_size: FRAME_SIZE as u32,
+            tp_frame_nr: (ring.block_count * options.frames_per_block()) as u32,
+            tp_retire_blk_tov: options.retire_timeout.as_millis().max(1) as u32,
+            tp_sizeof_priv: 0,
+            tp_feature_req_word: 0,
+        };
+        ring.set_option(libc::PACKET_RX_RING, &request)
+            .map_err(|e| error("set up the receive ring", e))?;
+
+        let length = ring.block_size * ring.block_count;
+        let map = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                length,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                ring.socket.fd(),
+                0,
+            )
+        };
+        if map == libc::MAP_FAILED {
+            return Err(error("map the receive ring", io::Error::last_os_error()));
+        }
+        ring.map = map as *mut u8;
+
+        ring.socket.attach(interface, promiscuous, fanout)?;
+
+        Ok(ring)
+    }
+
+    fn set_option<T>(&self, name: c_int, value: &T) -> io::Result<()> {
+        self.socket.set_option(libc::SOL_PACKET, name, value)
+    }
+
+    fn block(&self, index: usize) -> *mut libc::tpacket_block_desc {
+        unsafe { self.map.add(index * self.block_size) as *mut libc::tpacket_block_desc }
+    }
+
+    fn block_status(&self, index: usize) -> u32 {
+        let block = self.block(index);
+        let status = unsafe { ptr::read_volatile(ptr::addr_of!((*block).hdr.bh1.block_status)) };
+        // the packets must not be read before the status saying they are ours
+        fence(Ordering::Acquire);
+        status
+    }
+
+    /// Waits up to `timeout` for the kernel to hand over the next block,
+    /// passes each packet in it to `each` with its kernel timestamp, then
+    /// gives the block back. Returns false once `each` did.
+    pub fn next_block(
+        &mut self,
+        timeout: Duration,
+        each: &mut dyn FnMut(SystemTime, &[u8]) -> bool,
+    ) -> io::Result<bool> {
+        let index = self.next;
+        if self.block_status(index) & libc::TP_STATUS_USER == 0 {
+            let mut poll = libc::pollfd {
+                fd: self.socket.fd(),
+                events: libc::POLLIN | libc::POLLERR,
+                revents: 0,
+            };
+            let timeout = timeout.as_millis().min(c_int::MAX as u128) as c_int;
+            if unsafe { libc::poll(&mut poll, 1, timeout) } < 0 {
+                let e = io::Error::last_os_error();
+                // a signal such as Ctrl-C interrupts the wait
+                return match e.kind() {
+                    io::ErrorKind::Interrupted => Ok(true),
+                    _ => Err(e),
+                };
+            }
+            if self.block_status(index) & libc::TP_STATUS_USER == 0 {
+                return Ok(true);
+            }
+        }
+
+        let block = self.block(index);
+        let (count, first) = unsafe {
+            let header = &(*block).hdr.bh1;
+            (header.num_pkts, header.offset_to_first_pkt as usize)
+        };
+        let mut offset = first;
+        let mut more = true;
+        for _ in 0..count {
+            if offset >= self.block_size {
+                break;
+            }
+            let (timestamp, packet, next) = unsafe {
+                let header = (block as *const u8).add(offset) as *const libc::tpacket3_hdr;
+                let header = &*header;
+                let start = offset + header.tp_mac as usize;
+                let length =
+                    (header.tp_snaplen as usize).min(self.block_size.saturating_sub(start));
+                let packet = std::slice::from_raw_parts((block as *const u8).add(start), length);
+                let timestamp = UNIX_EPOCH + Duration::new(header.tp_sec as u64, header.tp_nsec);
+                (timestamp, packet, header.tp_next_offset as usize)
+            };
+            if !each(timestamp, packet) {
+                more = false;
+                break;
+            }
+            if next == 0 {
+                break;
+            }
+            offset += next;
+        }
+
+        // our reads must be done before the kernel may write the block again
+        fence(Ordering::Release);
+        unsafe {
+            ptr::write_volatile(
+                ptr::addr_of_mut!((*block).hdr.bh1.block_status),
+                libc::TP_STATUS_KERNEL,
+            );
+        }
+        self.next = (index + 1) % self.block_count;
+
+        Ok(more)
+    }
+
+    /// The kernel's counts since the previous call; reading them resets them.
+    pub fn statistics(&self) -> io::Result<KernelStats> {
+        let stats: libc::tpacket_stats_v3 = self
+            .socket
+            .get_option(libc::SOL_PACKET, libc::PACKET_STATISTICS)?;
+
+        Ok(KernelStats {
+            packets: stats.tp_packets as u64,
+            drops: stats.tp_drops as u64,
+            freezes: stats.tp_freeze_q_cnt as u64,
+        })
+    }
+}
+
+impl Drop for Ring {
+    fn drop(&mut self) {
+        // the socket closes itself afterwards
+        if !self.map.is_null() {
+            unsafe {
+                libc::munmap(
+                    self.map as *mut libc::c_void,
+                    self.block_size * self.block_count,
+                );
+            }
+        }
+    }
+}
@@ -1,4 +1,8 @@
-use crate::network_test::util;
+use crate::network_test::hexdump::Region;
+use crate::network_test::network::IPv4Packet;
+use crate::network_test::util::{self, Tlv};
+use std::fmt;
+use std::net::Ipv4Addr;
 
 #[derive(Debug)]
 pub enum ProtocolType {
@@ -22,18 +26,199 @@ pub struct ICMPSegment {
     icmp_subtype: u8,
     checksum: u16,
     content: u32,
+    message: ICMPMessage,
+}
+
+/// The part of an ICMP message after the type, code and checksum.
+#[derive(Debug)]
+pub enum ICMPMessage {
+    /// echo request or reply
+    Echo {
+        identifier: u16,
+        sequence: u16,
+        payload: Vec<u8>,
+    },
+    DestinationUnreachable {
+        /// set with code 4, fragmentation needed (RFC 1191)
+        next_hop_mtu: Option<u16>,
+        original: Option<QuotedDatagram>,
+    },
+    Redirect {
+        gateway: Ipv4Addr,
+        original: Option<QuotedDatagram>,
+    },
+    TimeExceeded {
+        original: Option<QuotedDatagram>,
+    },
+    ParameterProblem {
+        pointer: u8,
+        original: Option<QuotedDatagram>,
+    },
+    /// timestamp request or reply, in milliseconds since midnight UT
+    Timestamp {
+        identifier: u16,
+        sequence: u16,
+        originate: u32,
+        receive: u32,
+        transmit: u32,
+    },
+    RouterAdvertisement {
+        lifetime: u16,
+        /// router address and preference level
+        entries: Vec<(Ipv4Addr, i32)>,
+    },
+    RouterSolicitation,
+    Other(Vec<u8>),
+}
+
+/// The start of the datagram an ICMP error is about.
+#[derive(Debug)]
+pub struct QuotedDatagram {
+    pub packet: Box<IPv4Packet>,
+    /// taken from the quoted transport bytes even when too few are left to dissect
+    pub ports: Option<(u16, u16)>,
+    /// the quoted transport bytes as they are, usually the first 8
+    pub transport: Vec<u8>,
 }
 
 impl ICMPSegment {
     pub fn new(byte_array: &[u8]) -> Option<Self> {
-        let mut iter = byte_array.iter().map(|&s| s);
-        
-        let icmp_type = iter.next().unwrap();
-        let icmp_subtype = iter.next().unwrap();
+        if byte_array.len() < 8 {
+            return None;
+        }
+
+        let mut iter = byte_array.iter().copied();
+
+        let icmp_type = iter.next()?;
+        let icmp_subtype = iter.next()?;
         let checksum = util::assemble_byte(&mut iter.by_ref().take(2));
         let content = util::assemble_byte(&mut iter.by_ref().take(4));
+        let message = ICMPMessage::new(icmp_type, icmp_subtype, &byte_array[4..]);
+
+        Some(ICMPSegment {
+            icmp_type,
+            icmp_subtype,
+            checksum,
+            content,
+            message,
+        })
+    }
+}
+
+impl ICMPMessage {
+    /// Decodes everything after the checksum; `rest` is at least 4 bytes.
+    fn new(icmp_type: u8, code: u8, rest: &[u8]) -> Self {
+        let u16_at = |at: usize| u16::from_be_bytes([rest[at], rest[at + 1]]);
+        let u32_at =
+            |at: usize| u32::from_be_bytes([rest[at], rest[at + 1], rest[at + 2], rest[at + 3]]);
+        let data = &rest[4..];
+
+        match icmp_type {
+            0 | 8 => ICMPMessage::Echo {
+                identifier: u16_at(0),
+                sequence: u16_at(2),
+                payload: data.to_vec(),
+            },
+            3 => ICMPMessage::DestinationUnreachable {
+                next_hop_mtu: (code == 4).then(|| u16_at(2)),
+                original: QuotedDatagram::new(data),
+            },
+            5 => ICMPMessage::Redirect {
+                gateway: Ipv4Addr::new(rest[0], rest[1], rest[2], rest[3]),
+                original: QuotedDatagram::new(data),
+            },
+            11 => ICMPMessage::TimeExceeded {
+                original: QuotedDatagram::new(data),
+            },
+            12 => ICMPMessage::ParameterProblem {
+                pointer: rest[0],
+                original: QuotedDatagram::new(data),
+            },
+            13 | 14 if rest.len() >= 16 => ICMPMessage::Timestamp {
+                identifier: u16_at(0),
+                sequence: u16_at(2),
+                originate: u32_at(4),
+                receive: u32_at(8),
+                transmit: u32_at(12),
+            },
+            9 => {
+                let (count, entry_words) = (rest[0] as usize, rest[1] as usize);
+                // each entry is at least an address and a preference
+                let entry_size = entry_words.max(2) * 4;
+                let entries = data
+                    .chunks_exact(entry_size)
+                    .take(count)
+                    .map(|entry| {
+                        (
+                            Ipv4Addr::new(entry[0], entry[1], entry[2], entry[3]),
+                            i32::from_be_bytes([entry[4], entry[5], entry[6], entry[7]]),
+                        )
+                    })
+                    .collect();
+                ICMPMessage::RouterAdvertisement {
+                    lifetime: u16_at(2),
+                    entries,
+                }
+            }
+            10 => ICMPMessage::RouterSolicitation,
+            _ => ICMPMessage::Other(data.to_vec()),
+        }
+    }
+
+    fn original(&self) -> Option<&QuotedDatagram> {
+        match self {
+            ICMPMessage::DestinationUnreachable { original, .. }
+            | ICMPMessage::Redirect { original, .. }
+            | ICMPMessage::TimeExceeded { original }
+            | ICMPMessage::ParameterProblem { original, .. } => original.as_ref(),
+            _ => None,
+        }
+    }
+}
+
+impl QuotedDatagram {
+    fn new(bytes: &[u8]) -> Option<Self> {
+        let packet = IPv4Packet::new_quoted(bytes)?;
+        let transport = bytes.get(packet.get_header_length()..)?;
+        let ports = match packet.get_protocol() {
+            6 | 17 if transport.len() >= 4 => Some((
+                u16::from_be_bytes([transport[0], transport[1]]),
+                u16::from_be_bytes([transport[2], transport[3]]),
+            )),
+            _ => None,
+        };
+
+        Some(QuotedDatagram {
+            packet: Box::new(packet),
+            ports,
+            transport: transport.to_vec(),
+        })
+    }
+}
 
-        Some(ICMPSegment { icmp_type, icmp_subtype, checksum, content })
+/// `proto src:port → dst:port`, the flow the error refers to.
+impl fmt::Display for QuotedDatagram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let packet = &self.packet;
+        let protocol = ProtocolType::from(packet.get_protocol());
+        match self.ports {
+            Some((source, destination)) => write!(
+                f,
+                "{} {}:{} → {}:{}",
+                protocol,
+                packet.get_source(),
+                source,
+                packet.get_destination(),
+                destination
+            ),
+            None => write!(
+                f,
+                "{} {} → {}",
+                protocol,
+                packet.get_source(),
+                packet.get_destination()
+            ),
+        }
     }
 }
 
@@ -57,19 +242,59 @@ pub struct TCPSegment {
     checksum: u16,
     urgent_pointer: u16,
     option: Vec<u8>,
+    /// `option` decoded, each with its offset inside `option`
+    options: Vec<(usize, TcpOption)>,
     data: Vec<u8>,
 }
 
+/// One TCP option. Parsing stops at the first `Malformed` one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TcpOption {
+    EndOfList,
+    NoOperation,
+    MaximumSegmentSize(u16),
+    /// the shift count as sent; RFC 7323 caps it at 14
+    WindowScale(u8),
+    SackPermitted,
+    /// left and right edges of each block
+    Sack(Vec<(u32, u32)>),
+    Timestamps {
+        value: u32,
+        echo_reply: u32,
+    },
+    /// an empty cookie requests one
+    FastOpen(Vec<u8>),
+    Mptcp {
+        subtype: u8,
+        data: Vec<u8>,
+    },
+    Unknown {
+        kind: u8,
+        data: Vec<u8>,
+    },
+    Malformed {
+        kind: u8,
+        reason: String,
+    },
+}
+
 impl TCPSegment {
     pub fn new(byte_array: &[u8]) -> Option<Self> {
-        let mut iter = byte_array.iter().map(|&s| s);
+        if byte_array.len() < 20 {
+            return None;
+        }
+
+        let mut iter = byte_array.iter().copied();
 
         let source_port: u16 = util::assemble_byte(&mut iter.by_ref().take(2));
         let destination_port: u16 = util::assemble_byte(&mut iter.by_ref().take(2));
         let sequence_number: u32 = util::assemble_byte(&mut iter.by_ref().take(4));
         let acknowledgement_number: u32 = util::assemble_byte(&mut iter.by_ref().take(4));
-        let (data_offset, reserved) = util::splice_byte(4, iter.next().unwrap());
-        let (cwr, other) = util::splice_byte(1, iter.next().unwrap());
+        let (data_offset, reserved) = util::splice_byte(4, iter.next()?);
+        if data_offset < 5 {
+            return None;
+        }
+        let (cwr, other) = util::splice_byte(1, iter.next()?);
         let (ece, other) = util::splice_byte(2, other);
         let (urg, other) = util::splice_byte(3, other);
         let (ack, other) = util::splice_byte(4, other);
@@ -79,7 +304,8 @@ impl TCPSegment {
         let window_size = util::assemble_byte(&mut iter.by_ref().take(2));
         let checksum = util::assemble_byte(&mut iter.by_ref().take(2));
         let urgent_pointer = util::assemble_byte(&mut iter.by_ref().take(2));
-        let option = iter.by_ref().take(data_offset as usize * 4 - 20).collect();
+        let option: Vec<u8> = iter.by_ref().take(data_offset as usize * 4 - 20).collect();
+        let options = TcpOption::parse_all(&option);
         let data = iter.collect();
 
         Some(TCPSegment {
@@ -101,6 +327,7 @@ impl TCPSegment {
             checksum,
             urgent_pointer,
             option,
+            options,
             data,
         })
     }
@@ -117,8 +344,12 @@ pub struct UDPSegment {
 
 impl UDPSegment {
     pub fn new(byte_array: &[u8]) -> Option<Self> {
-        let mut iter = byte_array.iter().map(|&s| s);
-        
+        if byte_array.len() < 8 {
+            return None;
+        }
+
+        let mut iter = byte_array.iter().copied();
+
         let source_port = util::assemble_byte(&mut iter.by_ref().take(2));
         let destination_port = util::assemble_byte(&mut iter.by_ref().take(2));
         let length = util::assemble_byte(&mut iter.by_ref().take(2));
@@ -134,3 +365,843 @@ impl UDPSegment {
         })
     }
 }
+
+impl TcpOption {
+    /// Decodes an option area, stopping after `EndOfList` or a malformed option.
+    pub fn parse_all(bytes: &[u8]) -> Vec<(usize, TcpOption)> {
+        util::parse_options(
+            bytes,
+            |tlv| match tlv {
+                Tlv::EndOfList => TcpOption::EndOfList,
+                Tlv::NoOperation => TcpOption::NoOperation,
+                Tlv::Option { kind, value } => TcpOption::parse(kind, value),
+                Tlv::Malformed { kind, reason } => TcpOption::malformed(kind, &reason),
+            },
+            // a value that does not fit its kind stops the walk too
+            |option| matches!(option, TcpOption::Malformed { .. }),
+        )
+    }
+
+    /// Decodes one option from its kind and the bytes after the length byte.
+    fn parse(kind: u8, value: &[u8]) -> TcpOption {
+        let u32_at = |at: usize| {
+            u32::from_be_bytes([value[at], value[at + 1], value[at + 2], value[at + 3]])
+        };
+        let expect = |length: usize| {
+            TcpOption::malformed(
+                kind,
+                &format!("length {} should be {}", value.len() + 2, length),
+            )
+        };
+
+        match kind {
+            2 if value.len() == 2 => {
+                TcpOption::MaximumSegmentSize(u16::from_be_bytes([value[0], value[1]]))
+            }
+            2 => expect(4),
+            3 if value.len() == 1 => TcpOption::WindowScale(value[0]),
+            3 => expect(3),
+            4 if value.is_empty() => TcpOption::SackPermitted,
+            4 => expect(2),
+            5 if !value.is_empty() && value.len().is_multiple_of(8) && value.len() <= 32 => {
+                TcpOption::Sack(
+                    (0..value.len() / 8)
+                        .map(|block| (u32_at(block * 8), u32_at(block * 8 + 4)))
+                        .collect(),
+                )
+            }
+            5 => TcpOption::malformed(
+                kind,
+                &format!(
+                    "length {} is not 2 plus 1 to 4 blocks of 8",
+                    value.len() + 2
+                ),
+            ),
+            8 if value.len() == 8 => TcpOption::Timestamps {
+                value: u32_at(0),
+                echo_reply: u32_at(4),
+            },
+            8 => expect(10),
+            34 if value.is_empty()
+                || (4..=16).contains(&value.len()) && value.len().is_multiple_of(2) =>
+            {
+                TcpOption::FastOpen(value.to_vec())
+            }
+            34 => TcpOption::malformed(
+                kind,
+                &format!("cookie of {} bytes is not 4 to 16 and even", value.len()),
+            ),
+            // the experimental Fast Open option, told apart by its magic number
+            254 if value.starts_with(&[0xf9, 0x89]) => TcpOption::FastOpen(value[2..].to_vec()),
+            30 if !value.is_empty() => TcpOption::Mptcp {
+                subtype: value[0] >> 4,
+                data: value.to_vec(),
+            },
+            30 => TcpOption::malformed(kind, "no MPTCP subtype"),
+            kind => TcpOption::Unknown {
+                kind,
+                data: value.to_vec(),
+            },
+        }
+    }
+
+    fn malformed(kind: u8, reason: &str) -> TcpOption {
+        TcpOption::Malformed {
+            kind,
+            reason: reason.to_string(),
+        }
+    }
+
+    pub fn mptcp_subtype_name(subtype: u8) -> &'static str {
+        match subtype {
+            0 => "MP_CAPABLE",
+            1 => "MP_JOIN",
+            2 => "DSS",
+            3 => "ADD_ADDR",
+            4 => "REMOVE_ADDR",
+            5 => "MP_PRIO",
+            6 => "MP_FAIL",
+            7 => "MP_FASTCLOSE",
+            8 => "MP_TCPRST",
+            15 => "MP_EXPERIMENTAL",
+            _ => "Unknown",
+        }
+    }
+}
+
+/// Printed the way Wireshark's info column shows options, e.g. `MSS=1460`.
+impl fmt::Display for TcpOption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TcpOption::EndOfList => write!(f, "EOL"),
+            TcpOption::NoOperation => write!(f, "NOP"),
+            TcpOption::MaximumSegmentSize(mss) => write!(f, "MSS={}", mss),
+            TcpOption::WindowScale(shift) if *shift > 14 => {
+                write!(f, "WS={} (shift {} capped to 14)", 1u32 << 14, shift)
+            }
+            TcpOption::WindowScale(shift) => write!(f, "WS={}", 1u32 << shift),
+            TcpOption::SackPermitted => write!(f, "SACK_PERM"),
+            TcpOption::Sack(blocks) => {
+                let blocks: Vec<String> = blocks
+                    .iter()
+                    .map(|(left, right)| format!("{}-{}", left, right))
+                    .collect();
+                write!(f, "SACK={}", blocks.join(","))
+            }
+            TcpOption::Timestamps { value, echo_reply } => {
+                write!(f, "TSval={} TSecr={}", value, echo_reply)
+            }
+            TcpOption::FastOpen(cookie) if cookie.is_empty() => write!(f, "TFO=request"),
+            TcpOption::FastOpen(cookie) => {
+                write!(f, "TFO=")?;
+                cookie.iter().try_for_each(|b| write!(f, "{:02x}", b))
+            }
+            TcpOption::Mptcp { subtype, data } => write!(
+                f,
+                "MPTCP={} ({} bytes)",
+                TcpOption::mptcp_subtype_name(*subtype),
+                data.len() + 2
+            ),
+            TcpOption::Unknown { kind, data } => {
+                write!(f, "Option{}({} bytes)", kind, data.len() + 2)
+            }
+            TcpOption::Malformed { kind, reason } => {
+                write!(f, "Malformed option {}: {}", kind, reason)
+            }
+        }
+    }
+}
+
+impl From<u8> for ProtocolType {
+    fn from(number: u8) -> Self {
+        match number {
+            1 => ProtocolType::ICMP,
+            6 => ProtocolType::TCP,
+            17 => ProtocolType::UDP,
+            x => ProtocolType::UNDEFINED(x),
+        }
+    }
+}
+
+impl ProtocolType {
+    /// The IP protocol number this type was parsed from.
+    pub fn number(&self) -> u8 {
+        match self {
+            ProtocolType::ICMP => 1,
+            ProtocolType::TCP => 6,
+            ProtocolType::UDP => 17,
+            ProtocolType::UNDEFINED(x) => *x,
+        }
+    }
+}
+
+impl fmt::Display for ProtocolType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProtocolType::ICMP => write!(f, "ICMP"),
+            ProtocolType::TCP => write!(f, "TCP"),
+            ProtocolType::UDP => write!(f, "UDP"),
+            ProtocolType::UNDEFINED(x) => write!(f, "Unknown({})", x),
+        }
+    }
+}
+
+impl TransportSegment {
+    pub fn name(&self) -> Option<&'static str> {
+        match self {
+            TransportSegment::ICMP(_) => Some("ICMP"),
+            TransportSegment::TCP(_) => Some("TCP"),
+            TransportSegment::UDP(_) => Some("UDP"),
+            TransportSegment::UNDEFINED => None,
+        }
+    }
+
+    pub fn write_tree(&self, f: &mut fmt::Formatter<'_>, base: usize) -> fmt::Result {
+        match self {
+            TransportSegment::ICMP(segment) => segment.write_tree(f, base),
+            TransportSegment::TCP(segment) => segment.write_tree(f, base),
+            TransportSegment::UDP(segment) => segment.write_tree(f, base),
+            TransportSegment::UNDEFINED => Ok(()),
+        }
+    }
+
+    /// Appends the byte ranges of this segment, which starts at `base`.
+    pub fn regions(&self, base: usize, regions: &mut Vec<Region>) {
+        match self {
+            TransportSegment::ICMP(segment) => segment.regions(base, regions),
+            TransportSegment::TCP(segment) => segment.regions(base, regions),
+            TransportSegment::UDP(segment) => segment.regions(base, regions),
+            TransportSegment::UNDEFINED => {}
+        }
+    }
+}
+
+/// `{}` prints the one-line info column, `{:#}` prints the layer tree.
+impl fmt::Display for TransportSegment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if f.alternate() {
+            return self.write_tree(f, 0);
+        }
+
+        match self {
+            TransportSegment::ICMP(segment) => write!(f, "{}", segment),
+            TransportSegment::TCP(segment) => write!(f, "{}", segment),
+            TransportSegment::UDP(segment) => write!(f, "{}", segment),
+            TransportSegment::UNDEFINED => Ok(()),
+        }
+    }
+}
+
+impl ICMPSegment {
+    pub fn get_type(&self) -> u8 {
+        self.icmp_type
+    }
+
+    pub fn get_code(&self) -> u8 {
+        self.icmp_subtype
+    }
+
+    pub fn get_message(&self) -> &ICMPMessage {
+        &self.message
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self.icmp_type {
+            0 => "Echo (ping) reply",
+            3 => "Destination unreachable",
+            5 => "Redirect",
+            8 => "Echo (ping) request",
+            9 => "Router advertisement",
+            10 => "Router solicitation",
+            11 => "Time exceeded",
+            12 => "Parameter problem",
+            13 => "Timestamp request",
+            14 => "Timestamp reply",
+            _ => "Unknown",
+        }
+    }
+
+    pub fn code_name(&self) -> Option<&'static str> {
+        Some(match (self.icmp_type, self.icmp_subtype) {
+            (3, 0) => "Network unreachable",
+            (3, 1) => "Host unreachable",
+            (3, 2) => "Protocol unreachable",
+            (3, 3) => "Port unreachable",
+            (3, 4) => "Fragmentation needed",
+            (3, 5) => "Source route failed",
+            (3, 6) => "Destination network unknown",
+            (3, 7) => "Destination host unknown",
+            (3, 8) => "Source host isolated",
+            (3, 9) => "Network administratively prohibited",
+            (3, 10) => "Host administratively prohibited",
+            (3, 11) => "Network unreachable for TOS",
+            (3, 12) => "Host unreachable for TOS",
+            (3, 13) => "Communication administratively prohibited",
+            (3, 14) => "Host precedence violation",
+            (3, 15) => "Precedence cutoff in effect",
+            (5, 0) => "Redirect for network",
+            (5, 1) => "Redirect for host",
+            (5, 2) => "Redirect for TOS and network",
+            (5, 3) => "Redirect for TOS and host",
+            (11, 0) => "TTL exceeded in transit",
+            (11, 1) => "Fragment reassembly time exceeded",
+            (12, 0) => "Pointer indicates the error",
+            (12, 1) => "Missing a required option",
+            (12, 2) => "Bad length",
+            _ => return None,
+        })
+    }
+
+    pub fn regions(&self, base: usize, regions: &mut Vec<Region>) {
+        regions.push(Region::new("ICMP", base, base + 8));
+        match &self.message {
+            ICMPMessage::Echo { payload, .. } => {
+                regions.push(Region::new("ICMP data", base + 8, base + 8 + payload.len()))
+            }
+            message => {
+                if let Some(original) = message.original() {
+                    original.packet.regions(base + 8, regions);
+                }
+            }
+        }
+    }
+
+    pub fn write_tree(&self, f: &mut fmt::Formatter<'_>, base: usize) -> fmt::Result {
+        writeln!(f, "ICMP @0x{:04x}", base)?;
+        util::write_field(
+            f,
+            base,
+            "type",
+            format!("{} ({})", self.icmp_type, self.type_name()),
+        )?;
+        match self.code_name() {
+            Some(name) => util::write_field(
+                f,
+                base + 1,
+                "code",
+                format!("{} ({})", self.icmp_subtype, name),
+            )?,
+            None => util::write_field(f, base + 1, "code", self.icmp_subtype)?,
+        }
+        util::write_field(f, base + 2, "checksum", format!("0x{:04x}", self.checksum))?;
+
+        match &self.message {
+            ICMPMessage::Echo {
+                identifier,
+                sequence,
+                payload,
+            } => {
+                util::write_field(f, base + 4, "identifier", format!("0x{:04x}", identifier))?;
+                util::write_field(f, base + 6, "sequence", sequence)?;
+                util::write_field(f, base + 8, "data", format!("{} bytes", payload.len()))?;
+            }
+            ICMPMessage::DestinationUnreachable {
+                next_hop_mtu: Some(mtu),
+                ..
+            } => util::write_field(f, base + 6, "next_hop_mtu", mtu)?,
+            ICMPMessage::Redirect { gateway, .. } => {
+                util::write_field(f, base + 4, "gateway", gateway)?
+            }
+            ICMPMessage::ParameterProblem { pointer, .. } => {
+                util::write_field(f, base + 4, "pointer", pointer)?
+            }
+            ICMPMessage::Timestamp {
+                identifier,
+                sequence,
+                originate,
+                receive,
+                transmit,
+            } => {
+                util::write_field(f, base + 4, "identifier", format!("0x{:04x}", identifier))?;
+                util::write_field(f, base + 6, "sequence", sequence)?;
+                util::write_field(f, base + 8, "originate", originate)?;
+                util::write_field(f, base + 12, "receive", receive)?;
+                util::write_field(f, base + 16, "transmit", transmit)?;
+            }
+            ICMPMessage::RouterAdvertisement { lifetime, entries } => {
+                util::write_field(f, base + 4, "addresses", entries.len())?;
+                util::write_field(f, base + 6, "lifetime", format!("{}s", lifetime))?;
+                for (index, (router, preference)) in entries.iter().enumerate() {
+                    util::write_field(
+                        f,
+                        base + 8 + index * 8,
+                        "router",
+                        format!("{} (preference {})", router, preference),
+                    )?;
+                }
+            }
+            ICMPMessage::Other(data) if !data.is_empty() => {
+                util::write_field(f, base + 4, "content", format!("0x{:08x}", self.content))?;
+                util::write_field(f, base + 8, "data", format!("{} bytes", data.len()))?;
+            }
+            _ => util::write_field(f, base + 4, "content", format!("0x{:08x}", self.content))?,
+        }
+
+        if let Some(original) = self.message.original() {
+            writeln!(f, "Quoted datagram: {}", original)?;
+            original.packet.write_tree(f, base + 8)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for ICMPSegment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if f.alternate() {
+            return self.write_tree(f, 0);
+        }
+
+        write!(f, "{}", self.type_name())?;
+        if let Some(name) = self.code_name() {
+            write!(f, " ({})", name)?;
+        }
+
+        match &self.message {
+            ICMPMessage::Echo {
+                identifier,
+                sequence,
+                ..
+            } => write!(f, " id=0x{:04x} seq={}", identifier, sequence)?,
+            ICMPMessage::DestinationUnreachable {
+                next_hop_mtu: Some(mtu),
+                ..
+            } => write!(f, " mtu={}", mtu)?,
+            ICMPMessage::Redirect { gateway, .. } => write!(f, " gateway={}", gateway)?,
+            ICMPMessage::Other(_) => {
+                write!(f, " type={} code={}", self.icmp_type, self.icmp_subtype)?
+            }
+            _ => {}
+        }
+
+        if let Some(original) = self.message.original() {
+            write!(f, " for {}", original)?;
+        }
+        Ok(())
+    }
+}
+
+impl TCPSegment {
+    pub fn get_source_port(&self) -> u16 {
+        self.source_port
+    }
+
+    pub fn get_destination_port(&self) -> u16 {
+        self.destination_port
+    }
+
+    pub fn get_sequence_number(&self) -> u32 {
+        self.sequence_number
+    }
+
+    pub fn get_acknowledgement_number(&self) -> u32 {
+        self.acknowledgement_number
+    }
+
+    pub fn get_window_size(&self) -> u16 {
+        self.window_size
+    }
+
+    pub fn get_data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn get_options(&self) -> impl Iterator<Item = &TcpOption> {
+        self.options.iter().map(|(_, option)| option)
+    }
+
+    /// The option area as sent, padding after the end of the list included.
+    pub fn get_option_bytes(&self) -> &[u8] {
+        &self.option
+    }
+
+    pub fn get_urgent_pointer(&self) -> u16 {
+        self.urgent_pointer
+    }
+
+    /// The window scale shift this segment announces, capped at 14.
+    pub fn get_window_scale(&self) -> Option<u8> {
+        self.get_options().find_map(|option| match option {
+            TcpOption::WindowScale(shift) => Some((*shift).min(14)),
+            _ => None,
+        })
+    }
+
+    /// The flag byte as sent, CWR in the high bit and FIN in the low bit.
+    pub fn get_flags(&self) -> u8 {
+        [
+            self.cwr, self.ece, self.urg, self.ack, self.psh, self.rst, self.syn, self.fin,
+        ]
+        .into_iter()
+        .fold(0, |flags, bit| (flags << 1) | bit)
+    }
+
+    /// Names of the flags that are set, in the order Wireshark lists them.
+    pub fn flag_names(&self) -> Vec<&'static str> {
+        [
+            (self.fin, "FIN"),
+            (self.syn, "SYN"),
+            (self.rst, "RST"),
+            (self.psh, "PSH"),
+            (self.ack, "ACK"),
+            (self.urg, "URG"),
+            (self.ece, "ECE"),
+            (self.cwr, "CWR"),
+        ]
+        .into_iter()
+        .filter(|(bit, _)| *bit != 0)
+        .map(|(_, name)| name)
+        .collect()
+    }
+
+    pub fn regions(&self, base: usize, regions: &mut Vec<Region>) {
+        let option = base + 20;
+        let data = option + self.option.len();
+
+        regions.push(Region::new("TCP", base, option));
+        regions.push(Region::new("TCP options", option, data));
+        regions.push(Region::new("TCP data", data, data + self.data.len()));
+    }
+
+    pub fn write_tree(&self, f: &mut fmt::Formatter<'_>, base: usize) -> fmt::Result {
+        writeln!(f, "TCP @0x{:04x}", base)?;
+        util::write_field(f, base, "source_port", self.source_port)?;
+        util::write_field(f, base + 2, "destination_port", self.destination_port)?;
+        util::write_field(f, base + 4, "sequence_number", self.sequence_number)?;
+        util::write_field(
+            f,
+            base + 8,
+            "acknowledgement_number",
+            self.acknowledgement_number,
+        )?;
+        util::write_field(
+            f,
+            base + 12,
+            "data_offset",
+            format!(
+                "{} ({} bytes)",
+                self.data_offset,
+                self.data_offset as usize * 4
+            ),
+        )?;
+        util::write_field(
+            f,
+            base + 13,
+            "flags",
+            format!("[{}]", self.flag_names().join(", ")),
+        )?;
+        util::write_field(f, base + 14, "window_size", self.window_size)?;
+        util::write_field(f, base + 16, "checksum", format!("0x{:04x}", self.checksum))?;
+        util::write_field(f, base + 18, "urgent_pointer", self.urgent_pointer)?;
+        for (offset, option) in &self.options {
+            util::write_field(f, base + 20 + offset, "option", option)?;
+        }
+        util::write_field(
+            f,
+            base + self.data_offset as usize * 4,
+            "data",
+            format!("{} bytes", self.data.len()),
+        )
+    }
+}
+
+impl fmt::Display for TCPSegment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if f.alternate() {
+            return self.write_tree(f, 0);
+        }
+
+        write!(
+            f,
+            "{} → {} [{}] seq={} ack={} win={} len={}",
+            self.source_port,
+            self.destination_port,
+            self.flag_names().join(", "),
+            self.sequence_number,
+            self.acknowledgement_number,
+            self.window_size,
+            self.data.len()
+        )?;
+
+        for option in self.get_options() {
+            if !matches!(option, TcpOption::NoOperation | TcpOption::EndOfList) {
+                write!(f, " {}", option)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl UDPSegment {
+    pub fn get_source_port(&self) -> u16 {
+        self.source_port
+    }
+
+    pub fn get_destination_port(&self) -> u16 {
+        self.destination_port
+    }
+
+    pub fn get_data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn regions(&self, base: usize, regions: &mut Vec<Region>) {
+        regions.push(Region::new("UDP", base, base + 8));
+        regions.push(Region::new(
+            "UDP data",
+            base + 8,
+            base + 8 + self.data.len(),
+        ));
+    }
+
+    pub fn write_tree(&self, f: &mut fmt::Formatter<'_>, base: usize) -> fmt::Result {
+        writeln!(f, "UDP @0x{:04x}", base)?;
+        util::write_field(f, base, "source_port", self.source_port)?;
+        util::write_field(f, base + 2, "destination_port", self.destination_port)?;
+        util::write_field(f, base + 4, "length", self.length)?;
+        util::write_field(f, base + 6, "checksum", format!("0x{:04x}", self.checksum))?;
+        util::write_field(f, base + 8, "data", format!("{} bytes", self.data.len()))
+    }
+}
+
+impl fmt::Display for UDPSegment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if f.alternate() {
+            return self.write_tree(f, 0);
+        }
+
+        write!(
+            f,
+            "{} → {} len={}",
+            self.source_port,
+            self.destination_port,
+            self.data.len()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn linux_syn_options() {
+        // MSS 1460, SACK permitted, timestamps, NOP, window scale 7
+        let bytes = [
+            2, 4, 0x05, 0xb4, 4, 2, 8, 10, 0, 0, 0, 1, 0, 0, 0, 0, 1, 3, 3, 7,
+        ];
+        assert_eq!(
+            TcpOption::parse_all(&bytes),
+            vec![
+                (0, TcpOption::MaximumSegmentSize(1460)),
+                (4, TcpOption::SackPermitted),
+                (
+                    6,
+                    TcpOption::Timestamps {
+                        value: 1,
+                        echo_reply: 0
+                    }
+                ),
+                (16, TcpOption::NoOperation),
+                (17, TcpOption::WindowScale(7)),
+            ]
+        );
+    }
+
+    #[test]
+    fn sack_fast_open_mptcp_and_unknown_options() {
+        let bytes = [
+            5, 10, 0, 0, 0, 1, 0, 0, 0, 2, // SACK block 1-2
+            34, 6, 0xaa, 0xbb, 0xcc, 0xdd, // Fast Open cookie
+            254, 6, 0xf9, 0x89, 0xaa, 0xbb, // experimental Fast Open
+            30, 4, 0x20, 0x01, // MPTCP DSS
+            253, 3, 0xff, // experimental, unknown
+        ];
+        assert_eq!(
+            TcpOption::parse_all(&bytes),
+            vec![
+                (0, TcpOption::Sack(vec![(1, 2)])),
+                (10, TcpOption::FastOpen(vec![0xaa, 0xbb, 0xcc, 0xdd])),
+                (16, TcpOption::FastOpen(vec![0xaa, 0xbb])),
+                (
+                    22,
+                    TcpOption::Mptcp {
+                        subtype: 2,
+                        data: vec![0x20, 0x01]
+                    }
+                ),
+                (
+                    26,
+                    TcpOption::Unknown {
+                        kind: 253,
+                        data: vec![0xff]
+                    }
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn end_of_list_stops_the_walk() {
+        assert_eq!(
+            TcpOption::parse_all(&[1, 0, 2, 4, 0x05, 0xb4]),
+            vec![(0, TcpOption::NoOperation), (1, TcpOption::EndOfList)]
+        );
+    }
+
+    #[test]
+    fn malformed_lengths_stop_the_walk() {
+        let malformed = |kind: u8, reason: &str| TcpOption::Malformed {
+            kind,
+            reason: reason.to_string(),
+        };
+
+        assert_eq!(
+            TcpOption::parse_all(&[2, 3, 5, 1, 1]),
+            vec![(0, malformed(2, "length 3 should be 4"))]
+        );
+        assert_eq!(
+            TcpOption::parse_all(&[1, 8, 10, 0]),
+            vec![
+                (0, TcpOption::NoOperation),
+                (1, malformed(8, "length 10 runs past the 4 option bytes"))
+            ]
+        );
+        assert_eq!(
+            TcpOption::parse_all(&[3, 1, 7]),
+            vec![(0, malformed(3, "length 1 is below 2"))]
+        );
+        assert_eq!(
+            TcpOption::parse_all(&[5, 4, 0, 0]),
+            vec![(0, malformed(5, "length 4 is not 2 plus 1 to 4 blocks of 8"))]
+        );
+        assert_eq!(
+            TcpOption::parse_all(&[34, 3, 0]),
+            vec![(
+                0,
+                malformed(34, "cookie of 1 bytes is not 4 to 16 and even")
+            )]
+        );
+    }
+
+    /// An IPv4 header from 10.0.0.1 to 10.0.0.2 claiming 1500 bytes, as
+    /// ICMP errors quote it, followed by `transport`.
+    fn quoted(protocol: u8, transport: &[u8]) -> Vec<u8> {
+        let mut bytes = vec![0x45, 0, 0x05, 0xdc, 0, 1, 0x40, 0, 64, protocol, 0, 0];
+        bytes.extend_from_slice(&[10, 0, 0, 1, 10, 0, 0, 2]);
+        bytes.extend_from_slice(transport);
+        bytes
+    }
+
+    #[test]
+    fn echo_request() {
+        let segment = ICMPSegment::new(&[8, 0, 0, 0, 0x12, 0x34, 0, 1, b'a', b'b']).unwrap();
+        assert_eq!(segment.type_name(), "Echo (ping) request");
+        let ICMPMessage::Echo {
+            identifier,
+            sequence,
+            payload,
+        } = segment.get_message()
+        else {
+            panic!("not an echo: {:?}", segment.get_message());
+        };
+        assert_eq!((*identifier, *sequence), (0x1234, 1));
+        assert_eq!(payload, b"ab");
+    }
+
+    #[test]
+    fn fragmentation_needed_quotes_the_udp_flow() {
+        let mut bytes = vec![3, 4, 0, 0, 0, 0, 0x05, 0x78];
+        bytes.extend(quoted(17, &[0x14, 0xe9, 0, 53, 0x05, 0xc8, 0, 0]));
+        let segment = ICMPSegment::new(&bytes).unwrap();
+        assert_eq!(segment.code_name(), Some("Fragmentation needed"));
+        let ICMPMessage::DestinationUnreachable {
+            next_hop_mtu,
+            original: Some(original),
+        } = segment.get_message()
+        else {
+            panic!("not a quoting unreachable: {:?}", segment.get_message());
+        };
+        assert_eq!(*next_hop_mtu, Some(1400));
+        assert_eq!(original.ports, Some((5353, 53)));
+        assert_eq!(original.to_string(), "UDP 10.0.0.1:5353 → 10.0.0.2:53");
+        assert!(matches!(
+            original.packet.get_transport_segment(),
+            TransportSegment::UDP(_)
+        ));
+    }
+
+    #[test]
+    fn time_exceeded_keeps_a_cut_off_tcp_header() {
+        let mut bytes = vec![11, 0, 0, 0, 0, 0, 0, 0];
+        bytes.extend(quoted(6, &[0x04, 0xd2, 0, 80, 0, 0, 0, 1]));
+        let segment = ICMPSegment::new(&bytes).unwrap();
+        assert_eq!(segment.code_name(), Some("TTL exceeded in transit"));
+        let ICMPMessage::TimeExceeded {
+            original: Some(original),
+        } = segment.get_message()
+        else {
+            panic!("not a quoting time exceeded: {:?}", segment.get_message());
+        };
+        // 8 bytes are too few to dissect, but still name the flow
+        assert!(matches!(
+            original.packet.get_transport_segment(),
+            TransportSegment::UNDEFINED
+        ));
+        assert_eq!(original.to_string(), "TCP 10.0.0.1:1234 → 10.0.0.2:80");
+        assert_eq!(original.transport.len(), 8);
+    }
+
+    #[test]
+    fn unreachable_with_a_cut_off_quote() {
+        let segment = ICMPSegment::new(&[3, 3, 0, 0, 0, 0, 0, 0, 0x45, 0, 0, 28]).unwrap();
+        assert!(matches!(
+            segment.get_message(),
+            ICMPMessage::DestinationUnreachable {
+                next_hop_mtu: None,
+                original: None
+            }
+        ));
+    }
+
+    #[test]
+    fn redirect_timestamp_and_router_advertisement() {
+        let mut bytes = vec![5, 1, 0, 0, 10, 0, 0, 254];
+        bytes.extend(quoted(17, &[0, 1, 0, 2, 0, 8, 0, 0]));
+        let segment = ICMPSegment::new(&bytes).unwrap();
+        let ICMPMessage::Redirect { gateway, original } = segment.get_message() else {
+            panic!("not a redirect: {:?}", segment.get_message());
+        };
+        assert_eq!(*gateway, Ipv4Addr::new(10, 0, 0, 254));
+        assert_eq!(original.as_ref().unwrap().ports, Some((1, 2)));
+
+        let mut bytes = vec![14, 0, 0, 0, 0, 7, 0, 2];
+        bytes.extend_from_slice(&[0, 0, 0, 10, 0, 0, 0, 20, 0, 0, 0, 30]);
+        let segment = ICMPSegment::new(&bytes).unwrap();
+        assert!(matches!(
+            segment.get_message(),
+            ICMPMessage::Timestamp {
+                identifier: 7,
+                sequence: 2,
+                originate: 10,
+                receive: 20,
+                transmit: 30
+            }
+        ));
+
+        // two entries of two words each, lifetime 1800 s
+        let mut bytes = vec![9, 0, 0, 0, 2, 2, 0x07, 0x08];
+        bytes.extend_from_slice(&[10, 0, 0, 1, 0, 0, 0, 5, 10, 0, 0, 2, 0xff, 0xff, 0xff, 0xff]);
+        let segment = ICMPSegment::new(&bytes).unwrap();
+        let ICMPMessage::RouterAdvertisement { lifetime, entries } = segment.get_message() else {
+            panic!("not a router advertisement: {:?}", segment.get_message());
+        };
+        assert_eq!(*lifetime, 1800);
+        assert_eq!(
+            entries,
+            &[
+                (Ipv4Addr::new(10, 0, 0, 1), 5),
+                (Ipv4Addr::new(10, 0, 0, 2), -1)
+            ]
+        );
+    }
+}
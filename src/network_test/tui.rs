@@ -0,0 +1,358 @@
+use std::collections::BTreeMap;
+use std::io::{self, ErrorKind};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use pnet::datalink::NetworkInterface;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Borders, List, ListItem, ListState, Paragraph, Tabs};
+use ratatui::{DefaultTerminal, Frame};
+
+use crate::network_test::capture::open_receiver;
+use crate::network_test::datalink::EthernetIIFrame;
+use crate::network_test::hexdump::{self, HexDump};
+use crate::network_test::interfaces::InterfaceSelection;
+use crate::network_test::store::{PacketStore, Query, StoreLimits, StoredPacket};
+
+enum Message {
+    Packet(usize),
+    Failed(usize, String),
+}
+
+struct Tab {
+    name: String,
+    /// what the store held for this interface when last looked at
+    packets: Vec<Arc<StoredPacket>>,
+    /// summary lines by packet number, kept while the packet is stored
+    summaries: BTreeMap<u64, String>,
+    seen: usize,
+    /// `seen` when the capture was paused
+    paused_at: usize,
+    error: Option<String>,
+    /// index into the filtered list, `None` follows the newest packet
+    selected: Option<usize>,
+}
+
+struct App {
+    store: Arc<PacketStore>,
+    started: SystemTime,
+    tabs: Vec<Tab>,
+    current: usize,
+    filter: String,
+    editing: Option<String>,
+    paused: bool,
+}
+
+/// Runs the live capture UI on the given interfaces, one tab each. The
+/// tabs show what `limits` lets the packet store keep.
+pub fn run(selection: &InterfaceSelection, limits: StoreLimits) -> io::Result<()> {
+    let interfaces = selection.resolve().map_err(io::Error::other)?;
+
+    let (tx, rx) = mpsc::channel();
+    let store = Arc::new(PacketStore::new(limits));
+
+    let mut app = App {
+        store: Arc::clone(&store),
+        started: SystemTime::now(),
+        tabs: interfaces
+            .iter()
+            .map(|interface| Tab {
+                name: interface.name.clone(),
+                packets: vec![],
+                summaries: BTreeMap::new(),
+                seen: 0,
+                paused_at: 0,
+                error: None,
+                selected: None,
+            })
+            .collect(),
+        current: 0,
+        filter: String::new(),
+        editing: None,
+        paused: false,
+    };
+
+    for (index, interface) in interfaces.into_iter().enumerate() {
+        let tx = tx.clone();
+        let store = Arc::clone(&store);
+        thread::spawn(move || capture(index, &interface, &store, tx));
+    }
+
+    let mut terminal = ratatui::init();
+    let result = event_loop(&mut terminal, &mut app, &rx);
+    ratatui::restore();
+
+    result
+}
+
+fn capture(index: usize, interface: &NetworkInterface, store: &PacketStore, tx: Sender<Message>) {
+    let mut rx = match open_receiver(interface) {
+        Ok(rx) => rx,
+        Err(e) => {
+            let _ = tx.send(Message::Failed(index, e.replace('\n', " ")));
+            return;
+        }
+    };
+
+    loop {
+        match rx.next() {
+            Ok(packet) => {
+                let Some(frame) = EthernetIIFrame::new(packet) else {
+                    continue;
+                };
+                store.insert(&interface.name, SystemTime::now(), packet, frame);
+
+                if tx.send(Message::Packet(index)).is_err() {
+                    return;
+                }
+            }
+            // the receiver times out regularly; only real errors end the tab
+            Err(e) if e.kind() == ErrorKind::TimedOut => {}
+            Err(e) => {
+                let _ = tx.send(Message::Failed(
+                    index,
+                    format!("An error occurred while reading: {}", e),
+                ));
+                return;
+            }
+        }
+    }
+}
+
+fn event_loop(
+    terminal: &mut DefaultTerminal,
+    app: &mut App,
+    rx: &Receiver<Message>,
+) -> io::Result<()> {
+    loop {
+        while let Ok(message) = rx.try_recv() {
+            app.receive(message);
+        }
+        app.refresh();
+
+        terminal.draw(|frame| app.draw(frame))?;
+
+        if event::poll(Duration::from_millis(100))? {
+            if let Event::Key(key) = event::read()? {
+                if key.kind == KeyEventKind::Press && app.handle_key(key) {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+impl Tab {
+    /// The packets whose summary contains `filter`, with that summary.
+    fn visible(&self, filter: &str) -> Vec<(&StoredPacket, &str)> {
+        let filter = filter.to_lowercase();
+
+        self.packets
+            .iter()
+            .filter_map(|p| Some((p.as_ref(), self.summaries.get(&p.number)?.as_str())))
+            .filter(|(_, summary)| filter.is_empty() || summary.to_lowercase().contains(&filter))
+            .collect()
+    }
+}
+
+impl App {
+    fn receive(&mut self, message: Message) {
+        match message {
+            Message::Packet(index) => self.tabs[index].seen += 1,
+            Message::Failed(index, error) => self.tabs[index].error = Some(error),
+        }
+    }
+
+    /// Takes a new snapshot of the current tab's packets from the store,
+    /// unless paused.
+    fn refresh(&mut self) {
+        if self.paused {
+            return;
+        }
+        let tab = &mut self.tabs[self.current];
+        tab.packets = self.store.query(&Query {
+            interface: Some(tab.name.clone()),
+            ..Query::default()
+        });
+
+        // packets leave the store oldest first, and their summaries with them
+        let oldest = tab
+            .packets
+            .iter()
+            .map(|p| p.number)
+            .min()
+            .unwrap_or(u64::MAX);
+        tab.summaries.retain(|number, _| *number >= oldest);
+        for packet in &tab.packets {
+            tab.summaries.entry(packet.number).or_insert_with(|| {
+                let offset = packet.timestamp.duration_since(self.started);
+                format!(
+                    "{:>12.6} {}",
+                    offset.unwrap_or_default().as_secs_f64(),
+                    packet.frame
+                )
+            });
+        }
+    }
+
+    /// Returns true when the UI should quit.
+    fn handle_key(&mut self, key: KeyEvent) -> bool {
+        if let Some(editing) = self.editing.as_mut() {
+            match key.code {
+                KeyCode::Enter => {
+                    self.filter = self.editing.take().unwrap_or_default();
+                    self.tabs.iter_mut().for_each(|tab| tab.selected = None);
+                }
+                KeyCode::Esc => self.editing = None,
+                KeyCode::Backspace => {
+                    editing.pop();
+                }
+                KeyCode::Char(c) => editing.push(c),
+                _ => {}
+            }
+            return false;
+        }
+
+        let count = self.tabs[self.current].visible(&self.filter).len();
+        let last = count.saturating_sub(1);
+        let tab = &mut self.tabs[self.current];
+        let selected = tab.selected.unwrap_or(last);
+
+        match key.code {
+            KeyCode::Char('q') => return true,
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return true,
+            KeyCode::Char('/') => self.editing = Some(self.filter.clone()),
+            KeyCode::Char(' ') => {
+                self.paused = !self.paused;
+                self.tabs
+                    .iter_mut()
+                    .for_each(|tab| tab.paused_at = tab.seen);
+            }
+            KeyCode::Tab | KeyCode::Right => self.current = (self.current + 1) % self.tabs.len(),
+            KeyCode::BackTab | KeyCode::Left => {
+                self.current = (self.current + self.tabs.len() - 1) % self.tabs.len()
+            }
+            KeyCode::Up => tab.selected = Some(selected.saturating_sub(1)),
+            KeyCode::Down => tab.selected = Some((selected + 1).min(last)),
+            KeyCode::PageUp => tab.selected = Some(selected.saturating_sub(20)),
+            KeyCode::PageDown => tab.selected = Some((selected + 20).min(last)),
+            KeyCode::Home => tab.selected = Some(0),
+            KeyCode::End => tab.selected = None,
+            _ => {}
+        }
+
+        false
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let [tabs_area, list_area, detail_area, status_area] = Layout::vertical([
+            Constraint::Length(1),
+            Constraint::Percentage(45),
+            Constraint::Min(5),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+        let [tree_area, hex_area] =
+            Layout::horizontal([Constraint::Percentage(45), Constraint::Percentage(55)])
+                .areas(detail_area);
+
+        let titles = self
+            .tabs
+            .iter()
+            .map(|tab| format!("{} ({})", tab.name, tab.seen));
+        frame.render_widget(
+            Tabs::new(titles)
+                .select(self.current)
+                .highlight_style(Style::default().add_modifier(Modifier::REVERSED)),
+            tabs_area,
+        );
+
+        let tab = &self.tabs[self.current];
+        let visible = tab.visible(&self.filter);
+        let selected = tab
+            .selected
+            .unwrap_or(visible.len().saturating_sub(1))
+            .min(visible.len().saturating_sub(1));
+
+        self.draw_list(frame, list_area, tab, &visible, selected);
+
+        let (tree, hex) = match visible.get(selected) {
+            Some((packet, _)) => (
+                format!("{:#}", packet.frame),
+                hexdump::dump(&packet.data, &packet.frame.regions(), HexDump::HexAscii),
+            ),
+            None => (String::new(), String::new()),
+        };
+        frame.render_widget(
+            Paragraph::new(tree).block(Block::default().borders(Borders::ALL).title("Detail")),
+            tree_area,
+        );
+        frame.render_widget(
+            Paragraph::new(hex).block(Block::default().borders(Borders::ALL).title("Hex")),
+            hex_area,
+        );
+
+        frame.render_widget(Paragraph::new(self.status_line(tab)), status_area);
+    }
+
+    fn draw_list(
+        &self,
+        frame: &mut Frame,
+        area: Rect,
+        tab: &Tab,
+        visible: &[(&StoredPacket, &str)],
+        selected: usize,
+    ) {
+        let height = area.height.saturating_sub(2) as usize;
+        let start = (selected + 1).saturating_sub(height);
+        let end = (start + height).min(visible.len());
+
+        let items: Vec<ListItem> = visible[start..end]
+            .iter()
+            .map(|(p, summary)| ListItem::new(format!("{:>6} {}", p.number, summary)))
+            .collect();
+
+        let title = match &tab.error {
+            Some(error) => format!("Packets - {}", error),
+            None => String::from("Packets"),
+        };
+
+        let mut state = ListState::default();
+        if !visible.is_empty() {
+            state.select(Some(selected - start));
+        }
+
+        frame.render_stateful_widget(
+            List::new(items)
+                .block(Block::default().borders(Borders::ALL).title(title))
+                .highlight_style(Style::default().bg(Color::Blue)),
+            area,
+            &mut state,
+        );
+    }
+
+    fn status_line(&self, tab: &Tab) -> Line<'static> {
+        if let Some(editing) = &self.editing {
+            return Line::from(format!("filter: {}_   [enter] apply [esc] cancel", editing));
+        }
+
+        let mut status =
+            String::from("[q]uit [/]filter [space]pause [tab]interface [↑↓]select [end]follow");
+        if !self.filter.is_empty() {
+            status.push_str(&format!(" | filter: {}", self.filter));
+        }
+        if self.paused {
+            status.push_str(" | PAUSED");
+        }
+        if self.paused && tab.seen > tab.paused_at {
+            status.push_str(&format!(" | {} new since paused", tab.seen - tab.paused_at));
+        }
+
+        Line::from(status)
+    }
+}
@@ -36,3 +36,365 @@ where
 {
     pieces.fold(T::default(), |sum, n| (sum << 8) | T::from(n))
 }
+
+pub fn write_field(
+    f: &mut std::fmt::Formatter<'_>,
+    offset: usize,
+    name: &str,
+    value: impl std::fmt::Display,
+) -> std::fmt::Result {
+    writeln!(f, "    0x{:04x}  {}: {}", offset, name, value)
+}
+
+/// The RFC 1071 Internet checksum; data that carries a correct checksum sums to 0.
+pub fn internet_checksum(bytes: &[u8]) -> u16 {
+    let mut sum: u32 = bytes
+        .chunks(2)
+        .map(|pair| u16::from_be_bytes([pair[0], *pair.get(1).unwrap_or(&0)]) as u32)
+        .sum();
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// What the TCP, UDP and ICMPv6 checksums cover besides the segment itself.
+pub fn pseudo_header(
+    source: net::IpAddr,
+    destination: net::IpAddr,
+    protocol: u8,
+    length: usize,
+) -> Vec<u8> {
+    match (source, destination) {
+        (net::IpAddr::V4(source), net::IpAddr::V4(destination)) => [
+            &source.octets()[..],
+            &destination.octets(),
+            &[0, protocol],
+            &(length as u16).to_be_bytes(),
+        ]
+        .concat(),
+        (source, destination) => [
+            &to_ipv6(source).octets()[..],
+            &to_ipv6(destination).octets(),
+            &(length as u32).to_be_bytes(),
+            &[0, 0, 0, protocol],
+        ]
+        .concat(),
+    }
+}
+
+fn to_ipv6(address: net::IpAddr) -> net::Ipv6Addr {
+    match address {
+        net::IpAddr::V4(address) => address.to_ipv6_mapped(),
+        net::IpAddr::V6(address) => address,
+    }
+}
+
+/// One entry of an option area laid out as TCP and IPv4 do: kinds 0 and 1
+/// are a single byte, every other kind is followed by a length byte that
+/// counts the kind and itself.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Tlv<'a> {
+    EndOfList,
+    NoOperation,
+    /// the bytes after the length byte
+    Option {
+        kind: u8,
+        value: &'a [u8],
+    },
+    /// a length that cannot be right; the walk stops here
+    Malformed {
+        kind: u8,
+        reason: String,
+    },
+}
+
+/// Walks an option area, yielding each option with its offset. The walk
+/// stops after `EndOfList` or a malformed length.
+pub fn tlv_options(bytes: &[u8]) -> impl Iterator<Item = (usize, Tlv<'_>)> {
+    let mut offset = 0;
+    let mut done = false;
+
+    std::iter::from_fn(move || {
+        if done || offset >= bytes.len() {
+            return None;
+        }
+
+        let kind = bytes[offset];
+        let (tlv, length) = match kind {
+            0 => (Tlv::EndOfList, 1),
+            1 => (Tlv::NoOperation, 1),
+            _ => match bytes.get(offset + 1).map(|&length| length as usize) {
+                None => (malformed_tlv(kind, "no length byte".to_string()), 1),
+                Some(length) if length < 2 => (
+                    malformed_tlv(kind, format!("length {} is below 2", length)),
+                    1,
+                ),
+                Some(length) if offset + length > bytes.len() => (
+                    malformed_tlv(
+                        kind,
+                        format!(
+                            "length {} runs past the {} option bytes",
+                            length,
+                            bytes.len()
+                        ),
+                    ),
+                    1,
+                ),
+                Some(length) => (
+                    Tlv::Option {
+                        kind,
+                        value: &bytes[offset + 2..offset + length],
+                    },
+                    length,
+                ),
+            },
+        };
+
+        done = matches!(tlv, Tlv::EndOfList | Tlv::Malformed { .. });
+        let at = offset;
+        offset += length;
+        Some((at, tlv))
+    })
+}
+
+/// Decodes an option area into `(offset, option)` pairs with `parse`,
+/// stopping after `EndOfList` or the first option `malformed` rejects,
+/// whether its length or its value was wrong.
+pub fn parse_options<T>(
+    bytes: &[u8],
+    mut parse: impl FnMut(Tlv) -> T,
+    malformed: impl Fn(&T) -> bool,
+) -> Vec<(usize, T)> {
+    let mut options = vec![];
+
+    for (offset, tlv) in tlv_options(bytes) {
+        let option = parse(tlv);
+        let stop = malformed(&option);
+        options.push((offset, option));
+        if stop {
+            break;
+        }
+    }
+
+    options
+}
+
+fn malformed_tlv(kind: u8, reason: String) -> Tlv<'static> {
+    Tlv::Malformed { kind, reason }
+}
+
+/// Hand-built frames for tests: Ethernet II carrying IPv4, TCP, UDP or
+/// ARP between 10.0.0.1 (the client, port 1234) and 10.0.0.2 (the server,
+/// port 80), each host's MAC ending in the last byte of its address.
+#[cfg(test)]
+pub mod frames {
+    use crate::network_test::datalink::EthernetIIFrame;
+    use crate::network_test::flow::TCP_ACK;
+
+    pub const CLIENT: [u8; 4] = [10, 0, 0, 1];
+    pub const SERVER: [u8; 4] = [10, 0, 0, 2];
+
+    pub fn mac(last: u8) -> [u8; 6] {
+        [0x02, 0, 0, 0, 0, last]
+    }
+
+    pub fn ethernet(
+        source: [u8; 6],
+        destination: [u8; 6],
+        ether_type: u16,
+        payload: &[u8],
+    ) -> Vec<u8> {
+        let mut frame = [destination, source].concat();
+        frame.extend_from_slice(&ether_type.to_be_bytes());
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    /// An IPv4 header without a checksum unless one is given.
+    #[derive(Debug, Clone)]
+    pub struct Ipv4 {
+        pub source: [u8; 4],
+        pub destination: [u8; 4],
+        pub protocol: u8,
+        pub identification: u16,
+        /// the flags and fragment offset field
+        pub fragment: u16,
+        pub ttl: u8,
+        pub checksum: [u8; 2],
+        /// padded to whole words
+        pub options: Vec<u8>,
+    }
+
+    impl Default for Ipv4 {
+        fn default() -> Self {
+            Ipv4 {
+                source: CLIENT,
+                destination: SERVER,
+                protocol: 6,
+                identification: 1,
+                fragment: 0,
+                ttl: 64,
+                checksum: [0, 0],
+                options: vec![],
+            }
+        }
+    }
+
+    impl Ipv4 {
+        /// From the client to the server, or back if not `from_client`.
+        pub fn between(from_client: bool, protocol: u8) -> Self {
+            let (source, destination) = match from_client {
+                true => (CLIENT, SERVER),
+                false => (SERVER, CLIENT),
+            };
+            Ipv4 {
+                source,
+                destination,
+                protocol,
+                ..Ipv4::default()
+            }
+        }
+
+        pub fn packet(&self, data: &[u8]) -> Vec<u8> {
+            let header = 20 + self.options.len();
+            let mut packet = vec![0x40 | (header / 4) as u8, 0];
+            packet.extend_from_slice(&((header + data.len()) as u16).to_be_bytes());
+            packet.extend_from_slice(&self.identification.to_be_bytes());
+            packet.extend_from_slice(&self.fragment.to_be_bytes());
+            packet.extend_from_slice(&[self.ttl, self.protocol]);
+            packet.extend_from_slice(&self.checksum);
+            packet.extend_from_slice(&self.source);
+            packet.extend_from_slice(&self.destination);
+            packet.extend_from_slice(&self.options);
+            packet.extend_from_slice(data);
+            packet
+        }
+
+        /// The packet in an Ethernet frame between the hosts' MACs.
+        pub fn frame(&self, data: &[u8]) -> Vec<u8> {
+            let (source, destination) = (mac(self.source[3]), mac(self.destination[3]));
+            ethernet(source, destination, 0x0800, &self.packet(data))
+        }
+    }
+
+    /// A TCP segment; the acknowledgement number is only set with ACK.
+    #[derive(Debug, Clone)]
+    pub struct Tcp {
+        pub from_client: bool,
+        pub sequence: u32,
+        pub acknowledgement: u32,
+        pub flags: u8,
+        pub window: u16,
+        /// padded to whole words
+        pub options: Vec<u8>,
+        pub data: Vec<u8>,
+    }
+
+    impl Tcp {
+        pub fn new(from_client: bool, flags: u8) -> Self {
+            Tcp {
+                from_client,
+                sequence: 1,
+                acknowledgement: if flags & TCP_ACK != 0 { 1 } else { 0 },
+                flags,
+                window: 0x7210,
+                options: vec![],
+                data: vec![],
+            }
+        }
+
+        pub fn segment(&self) -> Vec<u8> {
+            let (source, destination) = match self.from_client {
+                true => (1234u16, 80u16),
+                false => (80, 1234),
+            };
+            let mut segment = source.to_be_bytes().to_vec();
+            segment.extend_from_slice(&destination.to_be_bytes());
+            segment.extend_from_slice(&self.sequence.to_be_bytes());
+            segment.extend_from_slice(&self.acknowledgement.to_be_bytes());
+            segment.extend_from_slice(&[(((20 + self.options.len()) / 4) << 4) as u8, self.flags]);
+            segment.extend_from_slice(&self.window.to_be_bytes());
+            segment.extend_from_slice(&[0, 0, 0, 0]);
+            segment.extend_from_slice(&self.options);
+            segment.extend_from_slice(&self.data);
+            segment
+        }
+
+        /// The segment in an Ethernet/IPv4 frame between client and server.
+        pub fn frame(&self) -> EthernetIIFrame {
+            let frame = Ipv4::between(self.from_client, 6).frame(&self.segment());
+            EthernetIIFrame::new(&frame).unwrap()
+        }
+    }
+
+    pub fn udp(source_port: u16, destination_port: u16, data: &[u8]) -> Vec<u8> {
+        let mut datagram = source_port.to_be_bytes().to_vec();
+        datagram.extend_from_slice(&destination_port.to_be_bytes());
+        datagram.extend_from_slice(&(8 + data.len() as u16).to_be_bytes());
+        datagram.extend_from_slice(&[0, 0]);
+        datagram.extend_from_slice(data);
+        datagram
+    }
+
+    /// An Ethernet/IPv4 ARP packet; each side is a MAC and an address.
+    pub fn arp(operation: u16, sender: ([u8; 6], [u8; 4]), target: ([u8; 6], [u8; 4])) -> Vec<u8> {
+        let mut packet = vec![0, 1, 0x08, 0x00, 6, 4];
+        packet.extend_from_slice(&operation.to_be_bytes());
+        packet.extend_from_slice(&sender.0);
+        packet.extend_from_slice(&sender.1);
+        packet.extend_from_slice(&target.0);
+        packet.extend_from_slice(&target.1);
+        packet
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tlv_walk_yields_offsets() {
+        let bytes = [1, 2, 4, 0x05, 0xb4, 9, 2, 0, 2, 4];
+        let options: Vec<(usize, Tlv)> = tlv_options(&bytes).collect();
+        assert_eq!(
+            options,
+            vec![
+                (0, Tlv::NoOperation),
+                (
+                    1,
+                    Tlv::Option {
+                        kind: 2,
+                        value: &[0x05, 0xb4]
+                    }
+                ),
+                (
+                    5,
+                    Tlv::Option {
+                        kind: 9,
+                        value: &[]
+                    }
+                ),
+                (7, Tlv::EndOfList),
+            ]
+        );
+    }
+
+    #[test]
+    fn tlv_walk_stops_at_a_bad_length() {
+        fn last(bytes: &[u8]) -> (usize, Tlv<'_>) {
+            tlv_options(bytes).last().unwrap()
+        }
+        let malformed = |kind: u8, reason: &str| Tlv::Malformed {
+            kind,
+            reason: reason.to_string(),
+        };
+
+        assert_eq!(last(&[1, 7]), (1, malformed(7, "no length byte")));
+        assert_eq!(last(&[7, 0, 1]), (0, malformed(7, "length 0 is below 2")));
+        assert_eq!(
+            last(&[1, 7, 5, 0]),
+            (1, malformed(7, "length 5 runs past the 4 option bytes"))
+        );
+    }
+}